@@ -4,7 +4,18 @@ CLEANER_SCRIPT="cd /home/mediaDownloader/ && ./cleaner"
 CLEANER_COMMAND="${CLEANER_SCRIPT}"
 
 start_applications () {
-    /home/mediaDownloader/media_downloader & /home/mediaDownloader/bot
+    /home/mediaDownloader/media_downloader &
+    DOWNLOADER_PID=$!
+    /home/mediaDownloader/bot &
+    BOT_PID=$!
+}
+
+# Forwarding the termination to the applications so that in-flight work is drained
+stop_applications () {
+    echo "** Stopping applications **"
+    kill -TERM "${DOWNLOADER_PID}" "${BOT_PID}" "${CRON_PID}" 2>/dev/null
+    wait "${DOWNLOADER_PID}" "${BOT_PID}"
+    exit $?
 }
 
 clean_crontab () {
@@ -12,7 +23,11 @@ clean_crontab () {
 }
 
 activate_crontab () {
-    start_applications & crond -f
+    start_applications
+    crond -f &
+    CRON_PID=$!
+    trap stop_applications TERM INT
+    wait
 }
 
 # Checking for HC_UUID_CLEANER
@@ -1,47 +1,92 @@
-use std::sync::Arc;
+use std::{
+    fmt::{Display, Formatter},
+    process::ExitCode,
+    str::FromStr,
+    sync::Arc,
+};
 
 use mediadownloader::{
     get_redis_manager,
     media_downloader::site_validator::SupportedSites,
     reply_message,
-    services::{init_telemetry, RedisManager},
-    BotMessage, CONFIG_FILE_SYNC, REDIS_CHANNEL, TELEGRAM_CONFIG,
+    services::{
+        drain_tasks, init_telemetry, shutdown_signal, shutdown_telemetry, AudioFormat,
+        ChatSettings, OversizePolicy, RedisManager, SlideshowAudio,
+    },
+    BotMessage, CHECK_MARK, CONFIG_FILE_SYNC, CROSS_MARK, REDIS_CHANNEL, SHUTDOWN_DRAIN_DEADLINE,
+    TELEGRAM_CONFIG,
 };
 
 use frankenstein::{
     AsyncApi, AsyncTelegramApi, GetUpdatesParams, Message, SendMessageParams, UpdateContent,
 };
 use futures::TryFutureExt;
+use tokio::task::JoinSet;
 use tracing::{debug, error, info, span};
 
+const AUDIO_DEFAULT_OFF: &str = "off";
+const AUDIO_USAGE: &str = "/audio <url>";
+
 #[derive(Debug)]
 pub enum BotCommands {
     Start,
     Help,
+    SlideshowAudio(Option<String>),
+    Oversize(Option<String>),
+    Audio(Option<String>),
+    AudioDefault(Option<String>),
     UnkownCommand(String),
 }
 
 #[tokio::main]
-async fn main() {
+async fn main() -> ExitCode {
     init_telemetry(Some("bot".to_string())).await;
 
     info!("Starting bot...");
 
-    let api = AsyncApi::new(&TELEGRAM_CONFIG.token);
+    let api = TELEGRAM_CONFIG.api(&TELEGRAM_CONFIG.token);
+    let mut tasks = JoinSet::new();
+
+    tokio::select! {
+        _ = shutdown_signal() => {}
+        _ = poll_updates(&api, &mut tasks) => {}
+    }
+
+    // Updates are acknowledged through the offset, in-flight messages are still enqueued
+    info!("Shutting down, no more updates are polled");
+    let drained = drain_tasks(&mut tasks, SHUTDOWN_DRAIN_DEADLINE).await;
+    shutdown_telemetry().await;
 
+    match drained {
+        true => ExitCode::SUCCESS,
+        false => ExitCode::FAILURE,
+    }
+}
+
+/// Polls the Telegram updates until cancelled, processing every message in its own task
+/// # Arguments
+/// * `api` - The api to use for polling
+/// * `tasks` - The in-flight tasks
+async fn poll_updates(api: &AsyncApi, tasks: &mut JoinSet<()>) {
     let update_params_builder = GetUpdatesParams::builder();
     let mut update_params = update_params_builder.clone().build();
 
     loop {
         let result = api.get_updates(&update_params).await;
 
+        while let Some(outcome) = tasks.try_join_next() {
+            if let Err(e) = outcome {
+                error!("Message task failed: {:?}", e);
+            }
+        }
+
         match result {
             Ok(response) => {
                 for update in response.result {
                     let root_span = span!(tracing::Level::WARN, "BOT");
                     if let UpdateContent::Message(message) = update.content {
                         let api_clone = api.clone();
-                        tokio::spawn(async move {
+                        tasks.spawn(async move {
                             let _enter = root_span.enter();
                             let redis_manager = get_redis_manager().await;
                             process_message(message, redis_manager, api_clone).await;
@@ -81,11 +126,69 @@ async fn process_message(message: Message, redis_manager: &RedisManager, api: As
                 let supported_sites = Arc::new(SupportedSites::new(&CONFIG_FILE_SYNC));
 
                 let text = format!(
-                    "Send me videos from these {:?} and I will download them!",
-                    &supported_sites
+                    "Send me videos from these {:?} and I will download them!\n\n\
+                    {} - Sends the audio track of the video instead\n\
+                    {} - Sends the audio track of every link instead of the video\n\
+                    {} - What to do with videos over the size limit\n\
+                    {} - How to send the music of TikTok slideshows",
+                    &supported_sites,
+                    AUDIO_USAGE,
+                    audio_default_usage(),
+                    oversize_usage(),
+                    slideshow_audio_usage()
                 );
                 send_message(message.chat.id, &text, api).await;
             }
+            BotCommands::SlideshowAudio(option) => {
+                let usage = slideshow_audio_usage();
+                let text = update_chat_setting(
+                    redis_manager,
+                    message.chat.id,
+                    option,
+                    "Slideshow audio",
+                    &usage,
+                    |settings| settings.slideshow_audio,
+                    |settings, slideshow_audio| settings.slideshow_audio = slideshow_audio,
+                )
+                .await;
+                send_message(message.chat.id, &text, api).await;
+            }
+            BotCommands::Oversize(option) => {
+                let usage = oversize_usage();
+                let text = update_chat_setting(
+                    redis_manager,
+                    message.chat.id,
+                    option,
+                    "Oversized videos",
+                    &usage,
+                    |settings| settings.oversize,
+                    |settings, oversize| settings.oversize = oversize,
+                )
+                .await;
+                send_message(message.chat.id, &text, api).await;
+            }
+            BotCommands::Audio(Some(url)) => {
+                debug!("Enqueuing audio request");
+                publish_message(redis_manager, &message, url, true).await
+            }
+            BotCommands::Audio(None) => {
+                let text = format!("Use {}", AUDIO_USAGE);
+                send_message(message.chat.id, &text, api).await;
+            }
+            BotCommands::AudioDefault(option) => {
+                let usage = audio_default_usage();
+                let text = update_chat_setting(
+                    redis_manager,
+                    message.chat.id,
+                    option,
+                    "Audio default",
+                    &usage,
+                    |settings| AudioDefault(settings.audio_default),
+                    |settings, AudioDefault(audio_default)| settings.audio_default = audio_default,
+                )
+                .await;
+                send_message(message.chat.id, &text, api).await;
+            }
             BotCommands::UnkownCommand(unknown) => {
                 let error_message_text = format!("Unknown command `{}`", unknown);
                 error!("{}", error_message_text);
@@ -95,17 +198,20 @@ async fn process_message(message: Message, redis_manager: &RedisManager, api: As
                     Some(error_message_text),
                     None,
                     None,
+                    None,
+                    None,
                     api,
                 )
                 .unwrap_or_else(|e| {
                     error!("Failed to send reply: {:?}", e);
+                    None
                 })
                 .await;
             }
         },
         _ => {
-            debug!("Publishing message to channel");
-            publish_message(redis_manager, message).await
+            debug!("Enqueuing message");
+            publish_message(redis_manager, &message, text.clone(), false).await
         }
     }
 }
@@ -118,14 +224,118 @@ async fn process_message(message: Message, redis_manager: &RedisManager, api: As
 fn format_command(text: &str) -> BotCommands {
     let mut split = text.splitn(2, ' ');
     let command = split.next().unwrap_or("");
+    let argument = split
+        .next()
+        .map(str::trim)
+        .filter(|a| !a.is_empty())
+        .map(str::to_string);
 
     match command {
         "/start" => BotCommands::Start,
         "/help" => BotCommands::Help,
+        "/slideshow_audio" => BotCommands::SlideshowAudio(argument),
+        "/oversize" => BotCommands::Oversize(argument),
+        "/audio" => BotCommands::Audio(argument),
+        "/audio_default" => BotCommands::AudioDefault(argument),
         unknown => BotCommands::UnkownCommand(unknown.to_string()),
     }
 }
 
+fn slideshow_audio_usage() -> String {
+    format!("/slideshow_audio <{}>", SlideshowAudio::VARIANTS.join("|"))
+}
+
+fn oversize_usage() -> String {
+    format!("/oversize <{}>", OversizePolicy::VARIANTS.join("|"))
+}
+
+fn audio_default_usage() -> String {
+    format!(
+        "/audio_default <{}|{}>",
+        AUDIO_DEFAULT_OFF,
+        AudioFormat::VARIANTS.join("|")
+    )
+}
+
+/// Shows or updates a setting of the chat
+/// # Arguments
+/// * `redis_manager` - The redis manager storing the chat settings
+/// * `chat_id` - The chat
+/// * `option` - (`Option`) The option to set, the current one is shown otherwise
+/// * `name` - The name of the setting, as shown in the replies
+/// * `usage` - How the command is used, shown along with the current option
+/// * `get` - Reads the setting from the chat settings
+/// * `set` - Writes the setting to the chat settings
+/// # Returns
+/// * `String` - The text to reply with
+async fn update_chat_setting<T>(
+    redis_manager: &RedisManager,
+    chat_id: i64,
+    option: Option<String>,
+    name: &str,
+    usage: &str,
+    get: fn(&ChatSettings) -> T,
+    set: fn(&mut ChatSettings, T),
+) -> String
+where
+    T: FromStr + Display,
+    T::Err: Display,
+{
+    let mut settings = match redis_manager
+        .get_chat_settings(&REDIS_CHANNEL, chat_id)
+        .await
+    {
+        Ok(settings) => settings,
+        Err(e) => {
+            error!("Failed to look up chat settings: {:?}", e);
+            return format!("{} Could not load the settings, try again!", CROSS_MARK);
+        }
+    };
+
+    let Some(option) = option else {
+        return format!("{} is `{}`, use {}", name, get(&settings), usage);
+    };
+
+    match option.parse::<T>() {
+        Ok(value) => set(&mut settings, value),
+        Err(e) => return format!("{} {}", CROSS_MARK, e),
+    };
+
+    match redis_manager
+        .set_chat_settings(&REDIS_CHANNEL, chat_id, &settings)
+        .await
+    {
+        Ok(_) => format!("{} {} set to `{}`", CHECK_MARK, name, get(&settings)),
+        Err(e) => {
+            error!("Failed to store chat settings: {:?}", e);
+            format!("{} Could not save the settings, try again!", CROSS_MARK)
+        }
+    }
+}
+
+/// The audio format links are delivered as by default, `off` delivering them as videos
+struct AudioDefault(Option<AudioFormat>);
+
+impl FromStr for AudioDefault {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            AUDIO_DEFAULT_OFF => Ok(AudioDefault(None)),
+            format => format.parse().map(|format| AudioDefault(Some(format))),
+        }
+    }
+}
+
+impl Display for AudioDefault {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            Some(format) => write!(f, "{}", format),
+            None => write!(f, "{}", AUDIO_DEFAULT_OFF),
+        }
+    }
+}
+
 /// Sends a message to the given chat
 /// # Arguments
 /// * `chat_id` - The id of the chat to send the message to
@@ -178,26 +388,50 @@ async fn send_greeting(message: Message, api: AsyncApi) {
     }
 }
 
-/// Publishes the given message to the `REDIS_CHANNEL`
+/// Enqueues the given message as a job on the `REDIS_CHANNEL` stream, the user is told
+/// when it could not be enqueued
 /// # Arguments
 /// * `manager` - The redis manager to use for publishing
 /// * `message` - The message to publish
+/// * `url` - The URL requested by the message
+/// * `audio` - Whether only the audio track was requested
 /// # Returns
 /// * `Result<(), Box<dyn Error>>` - The result of the operation
-async fn publish_message(manager: &RedisManager, message: Message) {
+async fn publish_message(manager: &RedisManager, message: &Message, url: String, audio: bool) {
     let api = BotMessage {
         chat_id: message.chat.id,
         message_id: message.message_id,
-        url: message.text.unwrap(),
-        api: AsyncApi::new(&TELEGRAM_CONFIG.token),
+        url,
+        attempt: 0,
+        first_failed_at: None,
+        audio,
+        api: TELEGRAM_CONFIG.api(&TELEGRAM_CONFIG.token),
     };
 
     let bot_message_serialized = toml::to_string(&api).unwrap();
 
-    manager
-        .send_to_channel(&REDIS_CHANNEL, &bot_message_serialized)
+    match manager
+        .enqueue_job(&REDIS_CHANNEL, &bot_message_serialized)
         .await
-        .unwrap();
-
-    debug!("Published message: {:?}", api);
+    {
+        Ok(job_id) => debug!("Enqueued message as job `{}`: {:?}", job_id, api),
+        Err(e) => {
+            error!("Failed to enqueue message: {:?}", e);
+            let text = format!("{} Could not queue your request, try again!", CROSS_MARK);
+            if let Err(e) = reply_message(
+                message.chat.id,
+                message.message_id,
+                Some(text),
+                None,
+                None,
+                None,
+                None,
+                api.api,
+            )
+            .await
+            {
+                error!("Failed to send reply: {:?}", e);
+            }
+        }
+    }
 }
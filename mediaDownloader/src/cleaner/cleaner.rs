@@ -8,22 +8,23 @@
 
 use mediadownloader::{
     get_redis_manager,
-    services::{init_telemetry, RedisManager},
+    services::{init_telemetry, shutdown_signal, shutdown_telemetry, AudioFormat, RedisManager},
     IMAGE_EXTENSIONS_FORMAT, TARGET_DIRECTORY, TARGET_DIRECTORY_IMAGES, VIDEO_EXTENSIONS_FORMAT,
 };
 
 use opentelemetry::trace::FutureExt;
-use std::path::Path;
+use std::{path::Path, process::ExitCode};
 use tracing::{debug, error, instrument, span, warn};
 use tracing_opentelemetry::OpenTelemetrySpanExt;
 
 #[tokio::main]
 #[instrument(level = "debug", name = "main")]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+async fn main() -> Result<ExitCode, Box<dyn std::error::Error>> {
     init_telemetry(Some("cleaner".to_string())).await;
 
     let root_span = span!(tracing::Level::DEBUG, "Clean");
     let root_span_clone = root_span.clone();
+    let root_span_audio = root_span.clone();
 
     let redis_manager = get_redis_manager().await;
 
@@ -48,12 +49,39 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .await;
     });
 
-    let _ = tokio::join!(cleaning_videos_task, cleaning_images_task);
+    let cleaning_audio_task = tokio::spawn(async move {
+        let audio_dir = Path::new(TARGET_DIRECTORY);
+        for audio_extension in AudioFormat::VARIANTS {
+            let _ = tracing::Instrument::instrument(
+                start_cleaning_flow(audio_dir, audio_extension, redis_manager)
+                    .with_context(root_span_audio.context()),
+                root_span_audio.clone(),
+            )
+            .await;
+        }
+    });
 
-    // I know, I know, telemetry additional buffer...hang in there :)
-    tokio::time::sleep(tokio::time::Duration::from_secs(15)).await;
+    let outcome = tokio::select! {
+        _ = shutdown_signal() => {
+            warn!("Cleaning interrupted");
+            Err(())
+        }
+        (videos, images, audio) = async {
+            tokio::join!(cleaning_videos_task, cleaning_images_task, cleaning_audio_task)
+        } => {
+            videos
+                .and(images)
+                .and(audio)
+                .map_err(|e| error!("Cleaning task failed: {:?}", e))
+        }
+    };
 
-    Ok(())
+    shutdown_telemetry().await;
+
+    match outcome {
+        Ok(_) => Ok(ExitCode::SUCCESS),
+        Err(_) => Ok(ExitCode::FAILURE),
+    }
 }
 
 /// Starts the cleaning flow for a specific directory and file extension.
@@ -151,7 +179,7 @@ async fn compare_fs_remote(files: Vec<String>) -> Result<(), Box<dyn std::error:
     for file in files {
         let file_id = file
             .split('/')
-            .last()
+            .next_back()
             .unwrap_or_else(|| panic!("Could not split FILE_ID on `/` ~ `{:?}`", file))
             .split('.')
             .next()
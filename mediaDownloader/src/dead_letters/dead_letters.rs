@@ -0,0 +1,110 @@
+use mediadownloader::{
+    get_redis_manager,
+    services::{DeadLetter, RedisManager},
+    BotMessage, REDIS_CHANNELS,
+};
+
+use std::process::ExitCode;
+
+const USAGE: &str = "Usage: dead_letters <list | replay <id> | replay-all>";
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let redis_manager = get_redis_manager().await;
+
+    let outcome = match args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        ["list"] => list_dead_letters(redis_manager).await,
+        ["replay", id] => replay_dead_letters(redis_manager, Some(id)).await,
+        ["replay-all"] => replay_dead_letters(redis_manager, None).await,
+        _ => {
+            eprintln!("{}", USAGE);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match outcome {
+        Ok(_) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Prints the dead letters of every consumed channel
+/// # Arguments
+/// * `redis_manager` - The Redis manager instance
+async fn list_dead_letters(redis_manager: &RedisManager) -> Result<(), Box<dyn std::error::Error>> {
+    for channel in REDIS_CHANNELS.iter() {
+        let dead_letters = redis_manager.list_dead_letters(&channel.name).await?;
+        println!("[{}] {} dead letters", channel.name, dead_letters.len());
+
+        for dead_letter in dead_letters {
+            let url = toml::from_str::<BotMessage>(&dead_letter.payload)
+                .map(|m| m.url)
+                .unwrap_or_default();
+            println!(
+                "{} ~ {} ~ attempts: {} ~ first failed at: {} ~ last failed at: {} ~ {}",
+                dead_letter.id,
+                dead_letter.error,
+                dead_letter.attempts,
+                dead_letter.first_failed_at,
+                dead_letter.last_failed_at,
+                url
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Enqueues dead letters back on their channel, resetting their attempts
+/// # Arguments
+/// * `redis_manager` - The Redis manager instance
+/// * `id` - The dead letter to replay, all of them when `None`
+async fn replay_dead_letters(
+    redis_manager: &RedisManager,
+    id: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut replayed = 0;
+
+    for channel in REDIS_CHANNELS.iter() {
+        let dead_letters = match id {
+            Some(id) => redis_manager
+                .get_dead_letter(&channel.name, id)
+                .await?
+                .into_iter()
+                .collect(),
+            None => redis_manager.list_dead_letters(&channel.name).await?,
+        };
+
+        for dead_letter in dead_letters {
+            replay_dead_letter(redis_manager, &channel.name, &dead_letter).await?;
+            println!("Replayed `{}` on `{}`", dead_letter.id, channel.name);
+            replayed += 1;
+        }
+    }
+
+    if replayed == 0 {
+        return Err("No dead letters to replay".into());
+    }
+    Ok(())
+}
+
+async fn replay_dead_letter(
+    redis_manager: &RedisManager,
+    channel: &str,
+    dead_letter: &DeadLetter,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut bot_message: BotMessage = toml::from_str(&dead_letter.payload)?;
+    bot_message.attempt = 0;
+    bot_message.first_failed_at = None;
+
+    redis_manager
+        .enqueue_job(channel, &toml::to_string(&bot_message)?)
+        .await?;
+    redis_manager
+        .delete_dead_letter(channel, &dead_letter.id)
+        .await?;
+    Ok(())
+}
@@ -8,7 +8,6 @@
 
 #[macro_use]
 extern crate tracing;
-use tokio::{fs::File, io::AsyncReadExt};
 use tracing::{debug, error, instrument, Instrument};
 
 pub mod media_downloader;
@@ -16,23 +15,62 @@ pub mod services;
 
 use async_once::AsyncOnce;
 use frankenstein::{
-    AsyncApi, AsyncTelegramApi, FileUpload, InputFile, InputMediaPhoto, Media,
-    SendMediaGroupParams, SendMessageParams, SendVideoParams,
+    AsyncApi, AsyncTelegramApi, FileUpload, InputMediaPhoto, InputMediaVideo, Media, Message,
+    SendAudioParams, SendMediaGroupParams, SendMessageParams, SendPhotoParams, SendVideoParams,
 };
 use lazy_static::lazy_static;
-use media_downloader::{errors::MediaDownloaderError, site_validator::SupportedSites};
+use media_downloader::{
+    caption::{Caption, CaptionsConfig},
+    errors::MediaDownloaderError,
+    http_client::{HttpClient, HttpConfig},
+    retry_policy::{RetryConfig, RetryPolicy},
+    site_validator::SupportedSites,
+    transcoder::{reencode_to_fit, split_to_fit, TranscodingConfig},
+    video_metadata::VideoMetadata,
+    worker_pool::WorkersConfig,
+};
 use serde::{ser::SerializeMap, Deserialize, Serialize};
-use services::{Builder, RedisBuilder, RedisConfig, RedisManager, TelemetryConfig};
+use services::{
+    AudioFormat, Builder, CachedGroupItem, CachedMedia, ChannelConfig, OversizePolicy,
+    RedisBuilder, RedisConfig, RedisManager, TelemetryConfig,
+};
 use std::path::PathBuf;
+use std::sync::Arc;
 use std::time::Duration;
 use std::{collections::HashMap, error::Error};
 
-use crate::media_downloader::processors::{AwemeConfig, AwemeHeaders, AwemeParams};
+use crate::media_downloader::processors::{
+    AwemeConfig, ProcessorConfig, ProcessorRegistry, ProcessorsConfig, YtDlpConfig,
+};
 
 #[derive(Debug)]
 pub enum MessageContent {
-    File(InputFile),
+    File(FileUpload),
     Images(Vec<Media>),
+    /// Images followed by their background music
+    Slideshow(Vec<Media>, FileUpload),
+    Audio(AudioFile),
+}
+
+/// An audio message, along with the tags shown by Telegram
+#[derive(Debug, Clone)]
+pub struct AudioFile {
+    pub file: FileUpload,
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    /// In seconds
+    pub duration: Option<u32>,
+}
+
+impl From<FileUpload> for AudioFile {
+    fn from(file: FileUpload) -> Self {
+        AudioFile {
+            file,
+            title: None,
+            performer: None,
+            duration: None,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -42,6 +80,13 @@ pub struct Config {
     pub supported_sites: SupportedSites,
     pub telemetry: Option<TelemetryConfig>,
     pub aweme_api: Option<AwemeConfig>,
+    pub retry: Option<RetryConfig>,
+    pub workers: Option<WorkersConfig>,
+    pub processors: Option<ProcessorsConfig>,
+    pub http: Option<HttpConfig>,
+    pub yt_dlp: Option<YtDlpConfig>,
+    pub transcoding: Option<TranscodingConfig>,
+    pub captions: Option<CaptionsConfig>,
 }
 
 #[derive(Debug)]
@@ -49,17 +94,45 @@ pub struct BotMessage {
     pub chat_id: i64,
     pub message_id: i32,
     pub url: String,
+    pub attempt: u32,
+    pub first_failed_at: Option<u64>,
+    /// Whether only the audio track was requested, through `/audio`
+    pub audio: bool,
     pub api: AsyncApi,
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct TelegramConfig {
     pub token: String,
+    /// The URL of a self-hosted `telegram-bot-api` server, the public Bot API otherwise
+    pub api_url: Option<String>,
+    /// Whether the self-hosted server (run with `--local`) shares the filesystem of the
+    /// downloader, files are then uploaded by path
+    pub local_files: Option<bool>,
+}
+
+/// The size of the media that can be uploaded, in bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileSizeLimits {
+    pub video: u64,
+    pub photo: u64,
+}
+
+impl Default for FileSizeLimits {
+    /// The limits of the public Bot API
+    fn default() -> Self {
+        FileSizeLimits {
+            video: MAX_FILE_SIZE,
+            photo: MAX_FILE_SIZE_PHOTO,
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct MessageHandled {
     pub content: Option<MessageContent>,
+    /// What the processor learned about the media, to caption it with
+    pub metadata: Option<VideoMetadata>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -81,7 +154,41 @@ pub struct ImageURL {
 
 impl TelegramConfig {
     pub fn new(token: String) -> TelegramConfig {
-        TelegramConfig { token }
+        TelegramConfig {
+            token,
+            api_url: None,
+            local_files: None,
+        }
+    }
+
+    pub fn is_self_hosted(&self) -> bool {
+        self.api_url.is_some()
+    }
+
+    /// Whether files are uploaded by path rather than through multipart requests
+    pub fn uploads_by_path(&self) -> bool {
+        self.is_self_hosted() && self.local_files.unwrap_or(false)
+    }
+
+    /// Builds the api of the bot with the given token, on the configured endpoint
+    pub fn api(&self, token: &str) -> AsyncApi {
+        match &self.api_url {
+            Some(api_url) => {
+                AsyncApi::new_url(format!("{}/bot{}", api_url.trim_end_matches('/'), token))
+            }
+            None => AsyncApi::new(token),
+        }
+    }
+
+    /// The upload limits of the endpoint, photos are limited by Telegram itself
+    pub fn file_size_limits(&self) -> FileSizeLimits {
+        match self.is_self_hosted() {
+            true => FileSizeLimits {
+                video: MAX_FILE_SIZE_SELF_HOSTED,
+                ..Default::default()
+            },
+            false => FileSizeLimits::default(),
+        }
     }
 }
 
@@ -90,7 +197,7 @@ impl Serialize for BotMessage {
     where
         S: serde::Serializer,
     {
-        let mut map = serializer.serialize_map(Some(3))?;
+        let mut map = serializer.serialize_map(None)?;
         map.serialize_key("chat_id")?;
         map.serialize_value(&self.chat_id)?;
 
@@ -100,6 +207,19 @@ impl Serialize for BotMessage {
         map.serialize_key("url")?;
         map.serialize_value(&self.url)?;
 
+        map.serialize_key("attempt")?;
+        map.serialize_value(&self.attempt)?;
+
+        if let Some(first_failed_at) = self.first_failed_at {
+            map.serialize_key("first_failed_at")?;
+            map.serialize_value(&first_failed_at)?;
+        }
+
+        if self.audio {
+            map.serialize_key("audio")?;
+            map.serialize_value(&self.audio)?;
+        }
+
         map.end()
     }
 }
@@ -116,6 +236,9 @@ impl<'de> Deserialize<'de> for BotMessage {
             ChatId,
             MessageId,
             Url,
+            Attempt,
+            FirstFailedAt,
+            Audio,
         }
 
         struct BotMessageVisitor;
@@ -134,6 +257,9 @@ impl<'de> Deserialize<'de> for BotMessage {
                 let mut chat_id = None;
                 let mut message_id = None;
                 let mut url = None;
+                let mut attempt = None;
+                let mut first_failed_at = None;
+                let mut audio = None;
 
                 while let Some(key) = map.next_key()? {
                     match key {
@@ -146,6 +272,15 @@ impl<'de> Deserialize<'de> for BotMessage {
                         Field::Url => {
                             url = Some(map.next_value()?);
                         }
+                        Field::Attempt => {
+                            attempt = Some(map.next_value()?);
+                        }
+                        Field::FirstFailedAt => {
+                            first_failed_at = Some(map.next_value()?);
+                        }
+                        Field::Audio => {
+                            audio = Some(map.next_value()?);
+                        }
                     }
                 }
 
@@ -158,7 +293,10 @@ impl<'de> Deserialize<'de> for BotMessage {
                     chat_id,
                     message_id,
                     url,
-                    api: AsyncApi::new(&TELEGRAM_CONFIG.token),
+                    attempt: attempt.unwrap_or_default(),
+                    first_failed_at,
+                    audio: audio.unwrap_or_default(),
+                    api: TELEGRAM_CONFIG.api(&TELEGRAM_CONFIG.token),
                 })
             }
         }
@@ -176,11 +314,46 @@ pub fn load_config(path: &str) -> Result<Config, Box<dyn Error>> {
     Ok(config)
 }
 
+/// Current unix timestamp in seconds
+pub fn unix_timestamp() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
 #[instrument(level = "debug", name = "extract_id_from_url")]
 pub fn extract_id_from_url(url: &str) -> Result<&str, MediaDownloaderError> {
     url.split('/')
-        .last()
-        .ok_or_else(|| MediaDownloaderError::CouldNotExtractId)
+        .next_back()
+        .ok_or(MediaDownloaderError::CouldNotExtractId)
+}
+
+/// Identifies a requested resource regardless of the share trackers of its query string
+/// The URL ID alone is ambiguous across sites (e.g. `/status/<id>/photo/1`), and some
+/// sites identify the resource by its query string (e.g. `/watch?v=<id>`)
+/// # Arguments
+/// * `url` - The requested URL
+/// # Returns
+/// * `Option<String>` - The host, path and sorted query parameters of the resource
+pub fn media_cache_key(url: &str) -> Option<String> {
+    let parsed_url = url::Url::parse(url).ok()?;
+    let host = parsed_url.host_str()?.trim_start_matches("www.");
+    let mut query: Vec<String> = parsed_url
+        .query_pairs()
+        .filter(|(name, _)| {
+            !name.starts_with(TRACKING_QUERY_PREFIX)
+                && !TRACKING_QUERY_PARAMETERS.contains(&&**name)
+        })
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect();
+    query.sort();
+
+    let key = format!("{}{}", host, parsed_url.path().trim_end_matches('/'));
+    match query.is_empty() {
+        true => Some(key),
+        false => Some(format!("{}?{}", key, query.join("&"))),
+    }
 }
 
 /// Reply to client with the requested blob or an error message
@@ -190,19 +363,26 @@ pub fn extract_id_from_url(url: &str) -> Result<&str, MediaDownloaderError> {
 /// * `text` - (`Option`) The text to reply with
 /// * `blob` - (`Option`) The blob to reply with
 /// * `images` - (`Option`) The images to reply with
+/// * `audio` - (`Option`) The audio to follow the images with, or to send on its own
+/// * `caption` - (`Option`) The caption of the media, attached to the first image of a group
 /// * `api` - The api to use for sending the reply
 /// # Returns
-/// * `Result<(), Box<dyn Error>>` - The result of the operation
+/// * `Result<Option<CachedMedia>, Box<dyn Error>>` - The `file_id`s of the delivered media, if any
+#[allow(clippy::too_many_arguments)]
 #[instrument(level = "debug", name = "reply_message", skip_all)]
 pub async fn reply_message(
     chat_id: i64,
     message_id: i32,
     text: Option<String>,
-    blob: Option<InputFile>,
+    blob: Option<FileUpload>,
     images: Option<Vec<Media>>,
+    audio: Option<AudioFile>,
+    caption: Option<Caption>,
     api: AsyncApi,
-) -> Result<(), Box<dyn Error>> {
+) -> Result<Option<CachedMedia>, Box<dyn Error>> {
     debug!("Replying to [{}] @[{}]", message_id, chat_id);
+    let mut delivered = None;
+    let audio_alone = text.is_none() && blob.is_none() && images.is_none();
 
     match (text, blob, images) {
         (Some(t), None, None) => {
@@ -216,48 +396,172 @@ pub async fn reply_message(
             }
         }
         (None, Some(b), None) => {
-            let send_video_params = SendVideoParams::builder()
+            let mut send_video_params = SendVideoParams::builder()
                 .chat_id(chat_id)
                 .reply_to_message_id(message_id)
                 .video(b)
                 .build();
-            if let Err(err) = api.send_video(&send_video_params).await {
-                error!("Failed to send video: {err:?}");
+            if let Some(caption) = &caption {
+                send_video_params.caption = Some(caption.text.clone());
+                send_video_params.parse_mode = Some(caption.parse_mode);
+            }
+            match api.send_video(&send_video_params).await {
+                Ok(response) => {
+                    delivered = delivered_video(&response.result).map(CachedMedia::Video)
+                }
+                Err(err) => error!("Failed to send video: {err:?}"),
             }
         }
-        (None, None, Some(images)) => {
+        (None, None, Some(mut images)) => {
+            // Telegram shows the caption of the first item as the caption of the group
+            if let (Some(caption), Some(first)) = (&caption, images.first_mut()) {
+                caption.attach(first);
+            }
             let image_chunks: Vec<_> = images.chunks(IMAGE_BATCH_SIZE).collect();
+            let mut group_items = Some(Vec::new());
 
             for (batch_index, image_chunk) in image_chunks.iter().enumerate() {
-                let send_images_params = SendMediaGroupParams::builder()
-                    .chat_id(chat_id)
-                    .reply_to_message_id(message_id)
-                    .media(image_chunk.to_vec()) // Convert the chunk to Vec<InputFile>
-                    .build();
-
-                if let Err(err) = api.send_media_group(&send_images_params).await {
-                    error!(
-                        "Failed to send bulk photos (batch {}): {err:?}",
-                        batch_index
-                    );
+                match send_media_batch(chat_id, message_id, image_chunk, &api).await {
+                    Ok(messages) => {
+                        if let Some(items) = group_items.as_mut() {
+                            items.extend(messages.iter().filter_map(delivered_group_item));
+                        }
+                    }
+                    Err(err) => {
+                        error!(
+                            "Failed to send bulk photos (batch {}): {err:?}",
+                            batch_index
+                        );
+                        group_items = None;
+                    }
                 }
             }
+
+            // Partially delivered groups are not worth caching
+            delivered = group_items
+                .filter(|items| items.len() == images.len())
+                .map(CachedMedia::MediaGroup);
         }
         (Some(_), Some(_), Some(_)) => {
             error!("Text, blob and images are present!");
         }
         (None, None, None) => {
-            error!("Either text, blob or images must be specified!");
+            if audio.is_none() {
+                error!("Either text, blob, images or audio must be specified!");
+            }
         }
         _ => {
             error!("Unknown combination of text, blob and images!");
         }
     }
-    Ok(())
+
+    if let Some(audio) = audio {
+        let mut send_audio_params = SendAudioParams::builder()
+            .chat_id(chat_id)
+            .reply_to_message_id(message_id)
+            .audio(audio.file)
+            .build();
+        send_audio_params.title = audio.title;
+        send_audio_params.performer = audio.performer;
+        send_audio_params.duration = audio.duration;
+        if let Some(caption) = caption.filter(|_| audio_alone) {
+            send_audio_params.caption = Some(caption.text);
+            send_audio_params.parse_mode = Some(caption.parse_mode);
+        }
+        delivered = match (delivered, api.send_audio(&send_audio_params).await) {
+            (Some(CachedMedia::MediaGroup(items)), Ok(response)) => response
+                .result
+                .audio
+                .map(|a| CachedMedia::Slideshow(items, a.file_id.clone())),
+            (None, Ok(response)) if audio_alone => response
+                .result
+                .audio
+                .map(|a| CachedMedia::Audio(a.file_id.clone())),
+            (_, Err(err)) => {
+                error!("Failed to send audio: {err:?}");
+                None
+            }
+            _ => None,
+        };
+    }
+    Ok(delivered)
 }
 
-#[instrument(level = "debug", name = "download_images_from_map", skip(images))]
+/// Sends a batch of media, Telegram requires media groups to hold at least two items
+/// so a single item is sent on its own
+/// # Arguments
+/// * `chat_id` - The chat id to reply to
+/// * `message_id` - The message id to reply to
+/// * `batch` - The media to send
+/// * `api` - The api to use for sending the reply
+/// # Returns
+/// * `Vec<Message>` - The messages delivered
+async fn send_media_batch(
+    chat_id: i64,
+    message_id: i32,
+    batch: &[Media],
+    api: &AsyncApi,
+) -> Result<Vec<Message>, frankenstein::Error> {
+    match batch {
+        [Media::Photo(photo)] => {
+            let mut send_photo_params = SendPhotoParams::builder()
+                .chat_id(chat_id)
+                .reply_to_message_id(message_id)
+                .photo(photo.media.clone())
+                .build();
+            send_photo_params.caption = photo.caption.clone();
+            send_photo_params.parse_mode = photo.parse_mode;
+            Ok(vec![api.send_photo(&send_photo_params).await?.result])
+        }
+        [Media::Video(video)] => {
+            let mut send_video_params = SendVideoParams::builder()
+                .chat_id(chat_id)
+                .reply_to_message_id(message_id)
+                .video(video.media.clone())
+                .build();
+            send_video_params.caption = video.caption.clone();
+            send_video_params.parse_mode = video.parse_mode;
+            Ok(vec![api.send_video(&send_video_params).await?.result])
+        }
+        _ => {
+            let send_images_params = SendMediaGroupParams::builder()
+                .chat_id(chat_id)
+                .reply_to_message_id(message_id)
+                .media(batch.to_vec()) // Convert the chunk to Vec<InputFile>
+                .build();
+            Ok(api.send_media_group(&send_images_params).await?.result)
+        }
+    }
+}
+
+/// Extracts the `file_id` of the video delivered by Telegram
+/// Short clips might be delivered as animations or documents
+fn delivered_video(message: &Message) -> Option<String> {
+    message
+        .video
+        .as_ref()
+        .map(|v| v.file_id.clone())
+        .or_else(|| message.animation.as_ref().map(|a| a.file_id.clone()))
+        .or_else(|| message.document.as_ref().map(|d| d.file_id.clone()))
+}
+
+/// Extracts the `file_id` of an item delivered in a media group
+/// Photos are delivered in several sizes, the largest one is the last
+fn delivered_group_item(message: &Message) -> Option<CachedGroupItem> {
+    if let Some(photo) = message.photo.as_ref().and_then(|p| p.last()) {
+        return Some(CachedGroupItem::Photo(photo.file_id.clone()));
+    }
+    delivered_video(message).map(CachedGroupItem::Video)
+}
+
+#[instrument(
+    level = "debug",
+    name = "download_images_from_map",
+    skip(http_client, redis, images)
+)]
 pub async fn download_images_from_map(
+    http_client: &HttpClient,
+    redis: &RedisManager,
     images: HashMap<i32, String>,
     id: String,
 ) -> Result<i32, Box<dyn Error + Send>> {
@@ -272,24 +576,21 @@ pub async fn download_images_from_map(
 
     let tasks = images.into_iter().map(|(i, url)| {
         let id_clone = id.to_string();
+        let http_client = http_client.clone();
+        let redis = redis.clone();
         let root_span = span!(tracing::Level::DEBUG, "Image Processing");
         async move {
             debug!("Processing image: {}_{}", id_clone, i);
-            match media_downloader::downloader::fetch_resource(&url, None, None, None, None, None)
-                .await
-            {
+            match http_client.fetch(&url, None, None, None, None, None).await {
                 Ok(response) => {
                     if response.status().is_success() {
-                        match media_downloader::downloader::was_image_already_downloaded(
-                            &id_clone, i,
+                        if media_downloader::downloader::was_image_already_downloaded(
+                            &redis, &id_clone, i,
                         )
                         .await
                         {
-                            true => {
-                                info!("Image `{}_{}` already downloaded!", id_clone, i);
-                                return;
-                            }
-                            false => {}
+                            info!("Image `{}_{}` already downloaded!", id_clone, i);
+                            return;
                         }
                         let mut file = match tokio::fs::File::create(format!(
                             "{}{}{}_{}.jpeg",
@@ -338,6 +639,8 @@ pub async fn download_images_from_map(
 ///
 /// # Arguments
 ///
+/// * `redis` - Where the images are recorded
+/// * `config` - The configuration the images are retrieved with
 /// * `url_id` - A string slice that holds the identifier of the URL from which to retrieve images.
 /// * `number_of_images` - The number of images to retrieve.
 ///
@@ -349,8 +652,10 @@ pub async fn download_images_from_map(
 /// # Errors
 ///
 /// This function will return an error if the images cannot be retrieved for any reason (e.g., network issues, invalid URL ID, etc.).
-#[instrument(level = "debug", name = "retrieve_images")]
+#[instrument(level = "debug", name = "retrieve_images", skip(redis, config))]
 async fn retrieve_images(
+    redis: &RedisManager,
+    config: &ProcessorConfig,
     url_id: &str,
     number_of_images: i32,
 ) -> Result<Vec<Media>, Box<dyn Error + Send>> {
@@ -360,53 +665,15 @@ async fn retrieve_images(
     debug!("number_of_images: {}", number_of_images);
 
     for n in 0..number_of_images {
-        let image_file_name = format!("{}_{}", url_id, n);
-
-        let file_path = format!(
-            "{}{}{}.{}",
-            TARGET_DIRECTORY, TARGET_DIRECTORY_IMAGES, image_file_name, IMAGE_EXTENSIONS_FORMAT
-        );
-        debug!(
-            "Retrieving image for {} in path {}",
-            image_file_name, file_path
-        );
-
-        let mut file = match File::open(&file_path).await {
-            Ok(f) => f,
+        match retrieve_image(redis, config, url_id, n).await {
+            Ok(image) => images.push(image),
             Err(e) => {
-                error!("Error opening file `{}`: {}", file_path, e);
-                debug!("Removing key `{}`", image_file_name);
-                let redis_manager = get_redis_manager().await;
-                let _ = redis_manager.del(&image_file_name).await;
-                io_errors += 1;
+                if let Some(MediaDownloaderError::BlobRetrievingError) = e.downcast_ref() {
+                    io_errors += 1;
+                }
                 continue;
             }
-        };
-
-        let mut buffer = Vec::new();
-        file.read_to_end(&mut buffer).await.unwrap();
-        let file_size = buffer.len() as u64;
-
-        if file_size > MAX_FILE_SIZE_PHOTO {
-            error!(
-                "File size of {} [{}] is greater than {}!",
-                url_id, file_size, MAX_FILE_SIZE_PHOTO
-            );
-            continue;
         }
-
-        let file_size_h = human_file_size(file_size);
-        debug!("file size of {} = {}", url_id, file_size_h);
-
-        images.push(Media::Photo(InputMediaPhoto {
-            media: FileUpload::InputFile(InputFile {
-                path: PathBuf::from(&file_path),
-            }),
-            caption: None,
-            parse_mode: None,
-            caption_entities: None,
-            has_spoiler: None,
-        }));
     }
 
     if images.is_empty() {
@@ -420,39 +687,100 @@ async fn retrieve_images(
     Ok(images)
 }
 
+/// Retrieves a single image from the fs
+/// If the file is not found, the respective key is removed from Redis
+/// # Arguments
+/// * `redis` - Where the image is recorded
+/// * `config` - The configuration the image is retrieved with
+/// * `url_id` - The id of the resource the image belongs to
+/// * `n` - The index of the image
+/// # Returns
+/// * `Media` - The image to forward to the user
+/// # Errors
+/// * `MediaDownloaderError::BlobRetrievingError` - Error retrieving the image from the fs
+/// * `MediaDownloaderError::FileSizeExceeded` - File size is greater than the maximum allowed
+#[instrument(level = "debug", name = "retrieve_image", skip(redis, config))]
+pub async fn retrieve_image(
+    redis: &RedisManager,
+    config: &ProcessorConfig,
+    url_id: &str,
+    n: i32,
+) -> Result<Media, Box<dyn Error + Send>> {
+    let image_file_name = format!("{}_{}", url_id, n);
+
+    let file_path = format!(
+        "{}{}{}.{}",
+        TARGET_DIRECTORY, TARGET_DIRECTORY_IMAGES, image_file_name, IMAGE_EXTENSIONS_FORMAT
+    );
+    debug!(
+        "Retrieving image for {} in path {}",
+        image_file_name, file_path
+    );
+
+    let file_size = match tokio::fs::metadata(&file_path).await {
+        Ok(metadata) => metadata.len(),
+        Err(e) => {
+            error!("Error opening file `{}`: {}", file_path, e);
+            debug!("Removing key `{}`", image_file_name);
+            let _ = redis.del(&image_file_name).await;
+            return Err(Box::new(MediaDownloaderError::BlobRetrievingError));
+        }
+    };
+
+    if file_size > config.file_size_limits.photo {
+        error!(
+            "File size of {} [{}] is greater than {}!",
+            url_id, file_size, config.file_size_limits.photo
+        );
+        return Err(Box::new(MediaDownloaderError::FileSizeExceeded));
+    }
+
+    let file_size_h = human_file_size(file_size);
+    debug!("file size of {} = {}", url_id, file_size_h);
+
+    Ok(Media::Photo(InputMediaPhoto {
+        media: config.file_upload(PathBuf::from(&file_path)),
+        caption: None,
+        parse_mode: None,
+        caption_entities: None,
+        has_spoiler: None,
+    }))
+}
+
 /// Retrieves the blob from the fs
 /// If the file is not found, the respective key is removed from Redis
 /// # Arguments
+/// * `redis` - Where the video is recorded
+/// * `config` - The configuration the video is retrieved with
 /// * `url_id` - The id of the video
 /// # Returns
-/// * `InputFile` - The blob to forward to the user
+/// * `FileUpload` - The blob to forward to the user
 /// # Errors
 /// * `MediaDownloaderError::BlobRetrievingError` - Error retrieving the blob from the fs
-/// * `MediaDownloaderError::FileSizeExceeded` - File size is greater than the maximum allowed (50MB)
-#[instrument(level = "debug", name = "retrieve_blob", skip(url_id))]
-pub async fn retrieve_blob(url_id: &str) -> Result<InputFile, Box<dyn Error + Send>> {
+/// * `MediaDownloaderError::FileSizeExceeded` - File size is greater than the maximum allowed
+#[instrument(level = "debug", name = "retrieve_blob", skip(redis, config, url_id))]
+pub async fn retrieve_blob(
+    redis: &RedisManager,
+    config: &ProcessorConfig,
+    url_id: &str,
+) -> Result<FileUpload, Box<dyn Error + Send>> {
     let file_path = format!("{}{}.{}", TARGET_DIRECTORY, url_id, VIDEO_EXTENSIONS_FORMAT);
     debug!("Retrieving blob for {} in path {}", url_id, file_path);
 
-    let mut file = match File::open(&file_path).await {
-        Ok(f) => f,
+    let file_size = match tokio::fs::metadata(&file_path).await {
+        Ok(metadata) => metadata.len(),
         Err(e) => {
             error!("Error opening file `{}`: {}", file_path, e);
             debug!("Removing key `{}`", url_id);
-            let redis_manager = get_redis_manager().await;
-            let _ = redis_manager.del(url_id).await;
+            let _ = redis.del(url_id).await;
             return Err(Box::new(MediaDownloaderError::BlobRetrievingError));
         }
     };
 
-    let mut buffer = Vec::new();
-    file.read_to_end(&mut buffer).await.unwrap();
-    let file_size = buffer.len() as u64;
-
-    if file_size > MAX_FILE_SIZE {
+    if file_size > config.file_size_limits.video {
         error!(
             "File size of {} [{}] is greater than {}!",
-            url_id, file_size, MAX_FILE_SIZE
+            url_id, file_size, config.file_size_limits.video
         );
         return Err(Box::new(MediaDownloaderError::FileSizeExceeded));
     }
@@ -460,9 +788,130 @@ pub async fn retrieve_blob(url_id: &str) -> Result<InputFile, Box<dyn Error + Se
     let file_size_h = human_file_size(file_size);
     debug!("file size of {} = {}", url_id, file_size_h);
 
-    Ok(InputFile {
-        path: PathBuf::from(&file_path),
-    })
+    Ok(config.file_upload(PathBuf::from(&file_path)))
+}
+
+/// Retrieves an extracted audio track from the fs
+/// # Arguments
+/// * `redis` - Where the audio track is recorded
+/// * `config` - The configuration the audio track is retrieved with
+/// * `audio_id` - The id of the audio track
+/// * `format` - The format the audio track was extracted as
+/// # Returns
+/// * `FileUpload` - The audio track to forward to the user
+/// # Errors
+/// * `MediaDownloaderError::BlobRetrievingError` - Error retrieving the audio track from the fs
+/// * `MediaDownloaderError::FileSizeExceeded` - File size is greater than the maximum allowed
+#[instrument(level = "debug", name = "retrieve_audio", skip(redis, config))]
+pub async fn retrieve_audio(
+    redis: &RedisManager,
+    config: &ProcessorConfig,
+    audio_id: &str,
+    format: AudioFormat,
+) -> Result<FileUpload, Box<dyn Error + Send>> {
+    let file_path = format!("{}{}.{}", TARGET_DIRECTORY, audio_id, format);
+    let file_size = match tokio::fs::metadata(&file_path).await {
+        Ok(metadata) => metadata.len(),
+        Err(e) => {
+            error!("Error opening file `{}`: {}", file_path, e);
+            let _ = redis.del(audio_id).await;
+            return Err(Box::new(MediaDownloaderError::BlobRetrievingError));
+        }
+    };
+
+    if file_size > config.file_size_limits.video {
+        error!(
+            "File size of {} [{}] is greater than {}!",
+            audio_id, file_size, config.file_size_limits.video
+        );
+        return Err(Box::new(MediaDownloaderError::FileSizeExceeded));
+    }
+    debug!("file size of {} = {}", audio_id, human_file_size(file_size));
+
+    Ok(config.file_upload(PathBuf::from(&file_path)))
+}
+
+/// Retrieves a downloaded video, fitting it to the size limit according to the chat policy
+/// # Arguments
+/// * `redis` - Where the video is recorded
+/// * `config` - The configuration the video is retrieved and fitted with
+/// * `url_id` - The id of the video
+/// * `oversize` - How the video is delivered when exceeding the size limit
+/// # Returns
+/// * `MessageContent` - The video, or its numbered parts as a media group
+/// # Errors
+/// * `MediaDownloaderError::BlobRetrievingError` - Error retrieving the blob from the fs
+/// * `MediaDownloaderError::FileSizeExceeded` - The video exceeds the size limit and could not be fitted
+/// * `MediaDownloaderError::TranscodingError` - `ffmpeg` could not be run or failed
+#[instrument(level = "debug", name = "retrieve_video", skip(redis, config))]
+pub async fn retrieve_video(
+    redis: &RedisManager,
+    config: &ProcessorConfig,
+    url_id: &str,
+    oversize: OversizePolicy,
+) -> Result<MessageContent, Box<dyn Error + Send>> {
+    let error = match retrieve_blob(redis, config, url_id).await {
+        Ok(video) => return Ok(MessageContent::File(video)),
+        Err(e) => e,
+    };
+    if !matches!(
+        error.downcast_ref(),
+        Some(MediaDownloaderError::FileSizeExceeded)
+    ) {
+        return Err(error);
+    }
+
+    match oversize {
+        OversizePolicy::Reject => Err(error),
+        OversizePolicy::Reencode => {
+            info!("Re-encoding `{}` to fit the size limit", url_id);
+            let fit_id = reencode_to_fit(
+                redis,
+                url_id,
+                config.file_size_limits.video,
+                config.transcoding.timeout(),
+            )
+            .await?;
+            let video = retrieve_blob(redis, config, &fit_id).await?;
+            Ok(MessageContent::File(video))
+        }
+        OversizePolicy::Split => {
+            info!("Splitting `{}` to fit the size limit", url_id);
+            let file_path = format!("{}{}.{}", TARGET_DIRECTORY, url_id, VIDEO_EXTENSIONS_FORMAT);
+            let file_size = tokio::fs::metadata(&file_path)
+                .await
+                .map_err(|e| {
+                    Box::new(MediaDownloaderError::IoErrorDirectory(e)) as Box<dyn Error + Send>
+                })?
+                .len();
+            let part_ids = split_to_fit(
+                redis,
+                url_id,
+                file_size,
+                config.file_size_limits.video,
+                config.transcoding.timeout(),
+            )
+            .await?;
+
+            let mut parts = Vec::new();
+            for part_id in part_ids {
+                let part = retrieve_blob(redis, config, &part_id).await?;
+                parts.push(Media::Video(InputMediaVideo {
+                    media: part,
+                    thumbnail: None,
+                    caption: None,
+                    parse_mode: None,
+                    caption_entities: None,
+                    width: None,
+                    height: None,
+                    duration: None,
+                    supports_streaming: Some(true),
+                    has_spoiler: None,
+                }));
+            }
+            Ok(MessageContent::Images(parts))
+        }
+    }
 }
 
 pub const SERVICE_NAME: &str = env!("CARGO_PKG_NAME");
@@ -470,16 +919,50 @@ pub const ROOT_PATH: &str = "./";
 pub const TARGET_DIRECTORY: &str = "/tmp/media_downloaded/";
 pub const TARGET_DIRECTORY_IMAGES: &str = "images/";
 pub const DEFAULT_REDIS_TTL: usize = 24 * 3600; // 24 hours
+pub const FILE_ID_CACHE_TTL: usize = 30 * 24 * 3600; // 30 days
 pub const VIDEO_EXTENSIONS_FORMAT: &str = "mp4";
+/// Query parameters added when sharing a link, not identifying the resource
+pub const TRACKING_QUERY_PARAMETERS: [&str; 7] = [
+    "si",
+    "igsh",
+    "igshid",
+    "feature",
+    "is_from_webapp",
+    "sender_device",
+    "share_id",
+];
+pub const TRACKING_QUERY_PREFIX: &str = "utm_";
 pub const IMAGE_EXTENSIONS_FORMAT: &str = "jpeg";
+pub const AUDIO_EXTENSIONS_FORMAT: &str = "mp3";
 pub const CONFIG_FILE_PATH: &str = "config.toml";
 pub const TIKTOK_GENERAL_DOMAIN: &str = "tiktok.com";
 pub const TIKTOK_MOBILE_DOMAIN: &str = "vm.tiktok.com";
 pub const YOUTUBE_MOBILE: &str = "youtu.be";
+pub const INSTAGRAM_DOMAIN: &str = "instagram.com";
+pub const TWITTER_DOMAINS: [&str; 5] = [
+    "x.com",
+    "twitter.com",
+    "fxtwitter.com",
+    "vxtwitter.com",
+    "fixupx.com",
+];
+pub const REDDIT_DOMAINS: [&str; 2] = ["reddit.com", "redd.it"];
 const IMAGE_BATCH_SIZE: usize = 10;
 pub const EXPONENTIAL_BACKOFF_SECONDS: Duration = Duration::from_secs(30);
 pub const BACKOFF_SECONDS: Duration = Duration::from_secs(3);
 pub const RETRIES_ATTEMPTS: u32 = 3;
+pub const DEFAULT_CONSUMER_GROUP: &str = "media_downloader";
+pub const JOBS_BATCH_SIZE: usize = 10;
+pub const JOBS_BLOCK_MS: usize = 5000;
+pub const JOBS_RECLAIM_INTERVAL: Duration = Duration::from_secs(60);
+pub const JOBS_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(60);
+/// Jobs idle for longer were abandoned by their consumer, running jobs being kept claimed
+/// by the heartbeats however long they take: a few are let slip before claiming them
+pub const JOBS_MIN_IDLE_MS: usize = 5 * JOBS_HEARTBEAT_INTERVAL.as_millis() as usize;
+pub const MAX_JOB_DELIVERIES: usize = 5;
+pub const JOBS_BACKPRESSURE_WAIT: Duration = Duration::from_secs(1);
+pub const SHUTDOWN_DRAIN_DEADLINE: Duration = Duration::from_secs(60);
+pub const PROGRESS_UPDATE_INTERVAL: Duration = Duration::from_secs(3);
 
 lazy_static! {
     pub static ref CONFIG_FILE_SYNC: Config = {
@@ -490,68 +973,62 @@ lazy_static! {
         let redis_builder = RedisBuilder::from_config(&CONFIG_FILE_SYNC.redis);
         RedisManager::build(redis_builder).await.unwrap()
     });
-    pub static ref TELEGRAM_CONFIG: TelegramConfig = {
-        let telegram_config = CONFIG_FILE_SYNC.telegram.clone();
-        TelegramConfig::new(telegram_config.token)
+    pub static ref HTTP_CLIENT: HttpClient = {
+        let http_config = CONFIG_FILE_SYNC.http.clone().unwrap_or_default();
+        HttpClient::new(&http_config).unwrap()
     };
-    pub static ref AWEME_CONFIG: Option<AwemeConfig> = {
-        let aweme_config = match CONFIG_FILE_SYNC.aweme_api.clone() {
-            Some(aweme_config) => aweme_config,
-            None => return None,
-        };
-        let headers = aweme_config.headers;
-        let params = aweme_config.params;
-        Some(AwemeConfig {
-            url: aweme_config.url,
-            app_name: aweme_config.app_name,
-            ua: aweme_config.ua,
-            headers: AwemeHeaders {
-                accept_language: headers.accept_language,
-                accept: headers.accept,
-            },
-            params: AwemeParams {
-                iid: params.iid,
-                app_version: params.app_version,
-                manifest_app_version: params.manifest_app_version,
-                app_name: params.app_name,
-                aid: params.aid,
-                lower_bound: params.lower_bound,
-                upper_bound: params.upper_bound,
-                version_code: params.version_code,
-                device_brand: params.device_brand,
-                device_type: params.device_type,
-                resolution: params.resolution,
-                dpi: params.dpi,
-                os_version: params.os_version,
-                os_api: params.os_api,
-                sys_region: params.sys_region,
-                region: params.region,
-                app_language: params.app_language,
-                language: params.language,
-                timezone_name: params.timezone_name,
-                timezone_offset: params.timezone_offset,
-                ac: params.ac,
-                ac2: params.ac2,
-                ssmix: params.ssmix,
-                os: params.os,
-                app_type: params.app_type,
-                residence: params.residence,
-                host_abi: params.host_abi,
-                locale: params.locale,
-                uoo: params.uoo,
-                op_region: params.op_region,
-                channel: params.channel,
-                is_pad: params.is_pad,
-            },
-        })
+    pub static ref PROCESSOR_REGISTRY: ProcessorRegistry = {
+        let processors_config = CONFIG_FILE_SYNC.processors.clone().unwrap_or_default();
+        ProcessorRegistry::new(&processors_config)
     };
+    pub static ref TELEGRAM_CONFIG: TelegramConfig = CONFIG_FILE_SYNC.telegram.clone();
+    /// Handed to the processors through their `ProcessorContext`
+    pub static ref PROCESSOR_CONFIG: Arc<ProcessorConfig> =
+        Arc::new(ProcessorConfig::from_config(&CONFIG_FILE_SYNC));
     pub static ref REDIS_CHANNEL: String = CONFIG_FILE_SYNC.redis.channel.clone();
+    pub static ref CAPTIONS_CONFIG: CaptionsConfig =
+        CONFIG_FILE_SYNC.captions.clone().unwrap_or_default();
+    pub static ref RETRY_POLICY: RetryPolicy =
+        RetryPolicy::new(&CONFIG_FILE_SYNC.retry.clone().unwrap_or_default());
+    pub static ref REDIS_CHANNELS: Vec<ChannelConfig> =
+        CONFIG_FILE_SYNC.redis.channels.clone().unwrap_or_else(|| {
+            vec![ChannelConfig {
+                name: REDIS_CHANNEL.clone(),
+                token: None,
+            }]
+        });
+    pub static ref REDIS_CONSUMER_GROUP: String = CONFIG_FILE_SYNC
+        .redis
+        .consumer_group
+        .clone()
+        .unwrap_or(DEFAULT_CONSUMER_GROUP.to_string());
+    pub static ref REDIS_CONSUMER_NAME: String = CONFIG_FILE_SYNC
+        .redis
+        .consumer_name
+        .clone()
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
 }
 
 pub async fn get_redis_manager() -> &'static RedisManager {
     REDIS_MANAGER.get().await
 }
 
+/// Builds the api of the bot publishing on the given channel
+/// Falls back to the `telegram` token when the channel has no dedicated one
+/// # Arguments
+/// * `channel` - The channel the job was read from
+/// # Returns
+/// * `AsyncApi` - The api to use for replying
+pub fn channel_api(channel: &str) -> AsyncApi {
+    let token = REDIS_CHANNELS
+        .iter()
+        .find(|c| c.name == channel)
+        .and_then(|c| c.token.clone())
+        .unwrap_or(TELEGRAM_CONFIG.token.clone());
+    TELEGRAM_CONFIG.api(&token)
+}
+
 // Emojis
 pub const CHECK_MARK: &str = "✅";
 pub const CROSS_MARK: &str = "❌";
@@ -561,9 +1038,11 @@ pub const MONKEY: &str = "🙈";
 pub const RADIOACTIVE: &str = "☢️";
 pub const FAILED: &str = "😩";
 pub const CHONK: &str = "🐈";
+pub const DOWNLOADING: &str = "⏬";
 
 // File size-related
 pub const MAX_FILE_SIZE: u64 = 50 * 1024 * 1024; // 50MB
+pub const MAX_FILE_SIZE_SELF_HOSTED: u64 = 2000 * 1024 * 1024; // 2000MB
 pub const MAX_FILE_SIZE_PHOTO: u64 = 10 * 1024 * 1024; // 10MB
 const KB: f64 = 1024.0;
 const MB: f64 = KB * KB;
@@ -580,3 +1059,63 @@ pub fn human_file_size(size: u64) -> String {
         format!("{:.2} GB", size as f64 / GB)
     }
 }
+
+#[cfg(test)]
+mod telegram_config_test {
+    use super::*;
+
+    #[test]
+    fn test_endpoint_and_limits() {
+        let public = TelegramConfig::new("123:abc".to_string());
+        assert_eq!(
+            public.api(&public.token).api_url,
+            "https://api.telegram.org/bot123:abc"
+        );
+        assert_eq!(public.file_size_limits().video, MAX_FILE_SIZE);
+        assert!(!public.uploads_by_path());
+
+        let self_hosted: TelegramConfig = toml::from_str(
+            r#"
+    token = "123:abc"
+    api_url = "http://telegram-bot-api:8081/"
+    local_files = true
+    "#,
+        )
+        .unwrap();
+        assert_eq!(
+            self_hosted.api(&self_hosted.token).api_url,
+            "http://telegram-bot-api:8081/bot123:abc"
+        );
+        assert_eq!(
+            self_hosted.file_size_limits(),
+            FileSizeLimits {
+                video: MAX_FILE_SIZE_SELF_HOSTED,
+                photo: MAX_FILE_SIZE_PHOTO,
+            }
+        );
+        assert!(self_hosted.uploads_by_path());
+    }
+}
+
+#[cfg(test)]
+mod media_cache_key_test {
+    use super::*;
+
+    #[test]
+    fn test_media_cache_key() {
+        assert_eq!(
+            media_cache_key("https://www.youtube.com/watch?v=abc&si=tracker&t=42").as_deref(),
+            Some("youtube.com/watch?t=42&v=abc")
+        );
+        assert_ne!(
+            media_cache_key("https://youtube.com/watch?v=abc"),
+            media_cache_key("https://youtube.com/watch?v=def")
+        );
+        assert_eq!(
+            media_cache_key("https://www.instagram.com/reel/xyz/?igsh=tracker&utm_source=ig")
+                .as_deref(),
+            Some("instagram.com/reel/xyz")
+        );
+        assert_eq!(media_cache_key("not a url"), None);
+    }
+}
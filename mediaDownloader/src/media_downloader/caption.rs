@@ -0,0 +1,337 @@
+use frankenstein::{Media, ParseMode};
+use serde::{Deserialize, Serialize};
+
+use super::video_metadata::VideoMetadata;
+
+/// Telegram's limit, counted in UTF-16 code units once the markup is parsed
+pub const MAX_CAPTION_LENGTH: usize = 1024;
+const DEFAULT_CAPTION_TEMPLATE: &str = "{title}\n{author}\n{duration} {resolution}\n{link}";
+const TRUNCATION_MARK: &str = "…";
+const MARKDOWN_V2_RESERVED: &str = "_*[]()~`>#+-=|{}.!\\";
+
+/// The syntax of the caption template, values filled in are escaped accordingly
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CaptionParseMode {
+    #[default]
+    Html,
+    MarkdownV2,
+}
+
+impl CaptionParseMode {
+    pub fn parse_mode(&self) -> ParseMode {
+        match self {
+            CaptionParseMode::Html => ParseMode::Html,
+            CaptionParseMode::MarkdownV2 => ParseMode::MarkdownV2,
+        }
+    }
+
+    pub fn escape(&self, value: &str) -> String {
+        match self {
+            CaptionParseMode::Html => value
+                .replace('&', "&amp;")
+                .replace('<', "&lt;")
+                .replace('>', "&gt;"),
+            CaptionParseMode::MarkdownV2 => {
+                let mut escaped = String::with_capacity(value.len());
+                for c in value.chars() {
+                    if MARKDOWN_V2_RESERVED.contains(c) {
+                        escaped.push('\\');
+                    }
+                    escaped.push(c);
+                }
+                escaped
+            }
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct CaptionsConfig {
+    /// Whether the delivered media is captioned, it is by default
+    pub enabled: Option<bool>,
+    /// Placeholders: `{author}`, `{title}`, `{link}`, `{duration}` and `{resolution}`
+    /// Lines whose placeholders are all unknown are left out
+    pub template: Option<String>,
+    pub parse_mode: Option<CaptionParseMode>,
+}
+
+/// A caption, ready to be attached to the delivered media
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Caption {
+    pub text: String,
+    pub parse_mode: ParseMode,
+}
+
+impl Caption {
+    /// Attaches the caption to a media, e.g. the first item of a media group
+    pub fn attach(&self, media: &mut Media) {
+        let (caption, parse_mode) = match media {
+            Media::Photo(photo) => (&mut photo.caption, &mut photo.parse_mode),
+            Media::Video(video) => (&mut video.caption, &mut video.parse_mode),
+            Media::Audio(audio) => (&mut audio.caption, &mut audio.parse_mode),
+            Media::Document(document) => (&mut document.caption, &mut document.parse_mode),
+        };
+        *caption = Some(self.text.clone());
+        *parse_mode = Some(self.parse_mode);
+    }
+}
+
+impl CaptionsConfig {
+    /// Fills the template with the metadata of the media, the title being truncated
+    /// so that the caption fits Telegram's limit
+    /// # Arguments
+    /// * `metadata` - The metadata of the media
+    /// * `url` - The requested URL, linked when the metadata has no canonical one
+    /// # Returns
+    /// * `Option<Caption>` - `None` when captions are disabled, nothing is known about the media
+    ///   or the caption exceeds the limit even without its title
+    pub fn render(&self, metadata: &VideoMetadata, url: &str) -> Option<Caption> {
+        if !self.enabled.unwrap_or(true) {
+            return None;
+        }
+        let parse_mode = self.parse_mode.unwrap_or_default();
+        let template = self.template.as_deref().unwrap_or(DEFAULT_CAPTION_TEMPLATE);
+
+        let title = metadata
+            .title
+            .as_deref()
+            .or(metadata.description.as_deref())
+            .map(|t| t.trim().to_string())
+            .filter(|t| !t.is_empty());
+        let author = metadata.author();
+        let duration = metadata.duration.map(format_duration);
+        let resolution = metadata.resolution();
+        if title.is_none() && author.is_none() && duration.is_none() && resolution.is_none() {
+            return None;
+        }
+
+        let link = metadata.webpage_url.as_deref().unwrap_or(url).to_string();
+        let mut values = [
+            ("author", author),
+            ("title", title.as_ref().map(|_| String::new())),
+            ("link", Some(link)),
+            ("duration", duration),
+            ("resolution", resolution),
+        ];
+        // Measured unescaped, the markup of the template is counted in, so it errs on the short side
+        if let Some(title) = title {
+            let rest = fill(template, &values, |v| v.to_string());
+            let budget = MAX_CAPTION_LENGTH.saturating_sub(utf16_len(&rest));
+            values[1].1 = truncate(&title, budget);
+        }
+        if utf16_len(&fill(template, &values, |v| v.to_string())) > MAX_CAPTION_LENGTH {
+            return None;
+        }
+
+        let text = fill(template, &values, |v| parse_mode.escape(v));
+        let text = text.trim();
+        (!text.is_empty()).then(|| Caption {
+            text: text.to_string(),
+            parse_mode: parse_mode.parse_mode(),
+        })
+    }
+}
+
+/// Replaces the placeholders of the template line by line, leaving out the lines
+/// whose placeholders are all unknown
+/// The template is scanned once, so placeholders within the values are left as they are
+fn fill(
+    template: &str,
+    values: &[(&str, Option<String>)],
+    escape: impl Fn(&str) -> String,
+) -> String {
+    template
+        .lines()
+        .filter_map(|line| {
+            let mut filled = String::with_capacity(line.len());
+            let (mut known, mut unknown) = (0, 0);
+            let mut rest = line;
+            while let Some(start) = rest.find('{') {
+                filled.push_str(&rest[..start]);
+                rest = &rest[start..];
+                let placeholder = rest.find('}').and_then(|end| {
+                    values
+                        .iter()
+                        .find(|(name, _)| *name == &rest[1..end])
+                        .map(|(_, value)| (value, end))
+                });
+                match placeholder {
+                    Some((value, end)) => {
+                        match value {
+                            Some(value) => {
+                                filled.push_str(&escape(value));
+                                known += 1;
+                            }
+                            None => unknown += 1,
+                        }
+                        rest = &rest[end + 1..];
+                    }
+                    None => {
+                        filled.push('{');
+                        rest = &rest[1..];
+                    }
+                }
+            }
+            filled.push_str(rest);
+
+            if known == 0 && unknown > 0 {
+                return None;
+            }
+            Some(filled.trim().to_string())
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn utf16_len(text: &str) -> usize {
+    text.encode_utf16().count()
+}
+
+/// Shortens the text to the given length, in UTF-16 code units, marking the cut
+fn truncate(text: &str, max_length: usize) -> Option<String> {
+    if utf16_len(text) <= max_length {
+        return Some(text.to_string());
+    }
+    let max_length = max_length.checked_sub(utf16_len(TRUNCATION_MARK))?;
+
+    let mut length = 0;
+    let truncated: String = text
+        .chars()
+        .take_while(|c| {
+            length += c.len_utf16();
+            length <= max_length
+        })
+        .collect();
+    let truncated = truncated.trim_end();
+    (!truncated.is_empty()).then(|| format!("{}{}", truncated, TRUNCATION_MARK))
+}
+
+/// e.g. `4:05` or `1:02:03`
+fn format_duration(seconds: f64) -> String {
+    let seconds = seconds.round() as u64;
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    match hours {
+        0 => format!("{}:{:02}", minutes, seconds),
+        _ => format!("{}:{:02}:{:02}", hours, minutes, seconds),
+    }
+}
+
+#[cfg(test)]
+mod caption_test {
+    use super::*;
+
+    #[test]
+    fn test_render_caption() {
+        let metadata = VideoMetadata {
+            title: Some("Rock & <Roll>".to_string()),
+            uploader: Some("Fixture Channel".to_string()),
+            uploader_id: Some("@fixture".to_string()),
+            duration: Some(3723.4),
+            webpage_url: Some("https://youtube.com/watch?v=a_b".to_string()),
+            width: Some(1920),
+            height: Some(1080),
+            ..Default::default()
+        };
+
+        let html = CaptionsConfig::default()
+            .render(&metadata, "https://youtu.be/a_b")
+            .unwrap();
+        assert_eq!(
+            html.text,
+            "Rock &amp; &lt;Roll&gt;\n@fixture\n1:02:03 1920x1080\nhttps://youtube.com/watch?v=a_b"
+        );
+        assert_eq!(html.parse_mode, ParseMode::Html);
+
+        let markdown = CaptionsConfig {
+            template: Some("*{title}*\n{resolution}\n{link}".to_string()),
+            parse_mode: Some(CaptionParseMode::MarkdownV2),
+            ..Default::default()
+        };
+        let tweet = VideoMetadata {
+            description: Some("Hello (world)!".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            markdown
+                .render(&tweet, "https://x.com/a/status/1")
+                .unwrap()
+                .text,
+            "*Hello \\(world\\)\\!*\nhttps://x\\.com/a/status/1"
+        );
+        assert_eq!(
+            markdown.render(&VideoMetadata::default(), "https://x.com"),
+            None
+        );
+
+        let long = VideoMetadata {
+            title: Some("🎬".repeat(2000)),
+            ..Default::default()
+        };
+        let caption = CaptionsConfig::default()
+            .render(&long, "https://youtu.be/a_b")
+            .unwrap();
+        assert_eq!(utf16_len(&caption.text), MAX_CAPTION_LENGTH);
+        assert!(caption.text.ends_with("…\nhttps://youtu.be/a_b"));
+
+        let disabled = CaptionsConfig {
+            enabled: Some(false),
+            ..Default::default()
+        };
+        assert_eq!(disabled.render(&metadata, "https://youtu.be/a_b"), None);
+    }
+
+    #[test]
+    fn test_render_caption_fills_template_once() {
+        let metadata = VideoMetadata {
+            title: Some("Look {link} {author}".to_string()),
+            uploader: Some("{title}".to_string()),
+            ..Default::default()
+        };
+        let config = CaptionsConfig {
+            template: Some("{title} {unknown} {\n{author}\n{link}".to_string()),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            config
+                .render(&metadata, "https://youtu.be/a_b")
+                .unwrap()
+                .text,
+            "Look {link} {author} {unknown} {\n{title}\nhttps://youtu.be/a_b"
+        );
+    }
+
+    #[test]
+    fn test_render_caption_within_limit() {
+        let long_author = VideoMetadata {
+            title: Some("Title".to_string()),
+            uploader: Some("a".repeat(1002)),
+            ..Default::default()
+        };
+        let caption = CaptionsConfig::default()
+            .render(&long_author, "https://youtu.be/a_b")
+            .unwrap();
+        assert!(caption.text.starts_with("aaa"));
+        assert_eq!(utf16_len(&caption.text), MAX_CAPTION_LENGTH - 1);
+
+        let longer_author = VideoMetadata {
+            uploader: Some("a".repeat(2000)),
+            ..long_author
+        };
+        assert_eq!(
+            CaptionsConfig::default().render(&longer_author, "https://youtu.be/a_b"),
+            None
+        );
+
+        let long_template = CaptionsConfig {
+            template: Some(format!("{}\n{{title}}", "#".repeat(1100))),
+            ..Default::default()
+        };
+        let title = VideoMetadata {
+            title: Some("Title".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(long_template.render(&title, "https://youtu.be/a_b"), None);
+    }
+}
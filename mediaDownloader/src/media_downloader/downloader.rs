@@ -1,80 +1,650 @@
 use std::error::Error;
-use std::io::{BufRead, BufReader};
-use std::process::{Command, Stdio};
-use std::str::FromStr;
-use std::sync::Arc;
+use std::process::{Output, Stdio};
+use std::time::Duration;
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
 
-use reqwest::header::{self, HeaderValue};
 use tracing::instrument;
-use url::Url;
 
 use super::errors::MediaDownloaderError;
+use super::http_client::HttpClient;
+use super::progress::{parse_yt_dlp_progress, ProgressReporter};
+use super::video_metadata::VideoMetadata;
+use crate::services::{AudioFormat, RedisManager};
 use crate::TARGET_DIRECTORY_IMAGES;
-use crate::{
-    get_redis_manager, media_downloader::formatter::UrlFormatter, TARGET_DIRECTORY,
-    VIDEO_EXTENSIONS_FORMAT,
-};
+use crate::{media_downloader::formatter::UrlFormatter, TARGET_DIRECTORY, VIDEO_EXTENSIONS_FORMAT};
+
+const SLIDESHOW_RESOLUTION: (u32, u32) = (1080, 1920);
+/// Appended to the file of a direct download until it completes
+const PARTIAL_DOWNLOAD_SUFFIX: &str = ".part";
+/// Printed by `yt-dlp` when it cannot go through its proxy
+const YT_DLP_PROXY_ERRORS: [&str; 6] = [
+    "ProxyError",
+    "Unable to connect to proxy",
+    "Tunnel connection failed",
+    "Failed to establish a new connection",
+    "Connection refused",
+    "SOCKS",
+];
 
 /// Downloads a video from the given `UrlFormatter` inside the `TARGET_DIRECTORY`
 /// If the video was already downloaded, it will return the video ID directly
 /// # Arguments
+/// * `redis` - Where the download is recorded
 /// * `url` - The `UrlFormatter` to download
 /// * `url_id` - The ID of the video
-#[instrument(level = "debug", name = "download_video", skip(url))]
+/// * `proxy` - (`Option`) The proxy `yt-dlp` goes through
+/// * `format` - (`Option`) The `yt-dlp` format selector, the best `mp4` otherwise
+/// * `timeout` - How long `yt-dlp` is given before being killed
+/// * `progress` - Where to report the progress printed by `yt-dlp` to
+/// # Errors
+/// * `MediaDownloaderError::Timeout` - `yt-dlp` did not finish in time
+/// * `MediaDownloaderError::ProxyError` - `yt-dlp` could not go through the proxy
+/// * `MediaDownloaderError::DownloadError` - `yt-dlp` could not be run or failed
+#[instrument(level = "debug", name = "download_video", skip(redis, url))]
 pub async fn download_video(
+    redis: &RedisManager,
     url: &UrlFormatter,
     url_id: String,
-) -> Result<(), Box<dyn Error + Send + Sync>> {
+    proxy: Option<String>,
+    format: Option<String>,
+    timeout: Duration,
+    progress: &ProgressReporter,
+) -> Result<(), Box<dyn Error + Send>> {
     let url = url.get_url_string().unwrap();
 
-    match was_video_already_downloaded(&url_id).await {
-        true => {
-            debug!("Video already downloaded!");
-            return Ok(());
+    if was_video_already_downloaded(redis, &url_id).await {
+        debug!("Video already downloaded!");
+        return Ok(());
+    }
+
+    let proxied = proxy.is_some();
+    let mut command = tokio::process::Command::new("yt-dlp");
+    if let Some(proxy) = proxy {
+        command.arg("--proxy").arg(proxy);
+    }
+    let format = format.unwrap_or(format!(
+        "bestvideo[ext={}]+bestaudio[ext=m4a]/{}",
+        VIDEO_EXTENSIONS_FORMAT, VIDEO_EXTENSIONS_FORMAT
+    ));
+    command
+        .arg(url)
+        .args(["-P", TARGET_DIRECTORY])
+        .args(["-f", &format])
+        .args(["-o", &format!("{}.%(ext)s", url_id)])
+        .arg("--no-mtime")
+        .arg("--newline");
+
+    let output = run_with_timeout(&mut command, timeout, |line| {
+        if let Some(percent) = parse_yt_dlp_progress(line) {
+            progress.report(percent);
         }
-        false => {}
+    })
+    .await;
+    let output = match output {
+        Ok(output) if output.status.success() => output,
+        Ok(output) => {
+            forget_download(redis, &url_id).await;
+            return Err(yt_dlp_failure(&output, proxied));
+        }
+        Err(e) => {
+            forget_download(redis, &url_id).await;
+            return Err(e);
+        }
+    };
+
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|line| line.contains("[download]"))
+        .for_each(|line| debug!("\n{}\n", line));
+
+    Ok(())
+}
+
+/// Extracts the audio track of a video through `yt-dlp` inside the `TARGET_DIRECTORY`,
+/// tagged with the title and uploader of the video and its thumbnail as cover
+/// If the audio track was already extracted, it will return directly
+/// # Arguments
+/// * `redis` - Where the audio track is recorded
+/// * `url` - The `UrlFormatter` of the video
+/// * `audio_id` - The ID the audio track is stored as
+/// * `format` - The format to extract the audio track as
+/// * `proxy` - (`Option`) The proxy `yt-dlp` goes through
+/// * `timeout` - How long `yt-dlp` is given before being killed
+/// * `progress` - Where to report the progress printed by `yt-dlp` to
+/// # Errors
+/// * `MediaDownloaderError::Timeout` - `yt-dlp` did not finish in time
+/// * `MediaDownloaderError::ProxyError` - `yt-dlp` could not go through the proxy
+/// * `MediaDownloaderError::DownloadError` - `yt-dlp` could not be run or failed
+#[instrument(level = "debug", name = "download_audio", skip(redis, url))]
+pub async fn download_audio(
+    redis: &RedisManager,
+    url: &UrlFormatter,
+    audio_id: &str,
+    format: AudioFormat,
+    proxy: Option<String>,
+    timeout: Duration,
+    progress: &ProgressReporter,
+) -> Result<(), Box<dyn Error + Send>> {
+    let url = url.get_url_string().unwrap();
+    let output_path = format!("{}{}.{}", TARGET_DIRECTORY, audio_id, format);
+
+    if tokio::fs::try_exists(&output_path).await.unwrap_or(false) {
+        debug!("Audio already extracted!");
+        return Ok(());
     }
 
-    let output = Command::new("yt-dlp")
+    let proxied = proxy.is_some();
+    let mut command = tokio::process::Command::new("yt-dlp");
+    if let Some(proxy) = proxy {
+        command.arg("--proxy").arg(proxy);
+    }
+    command
         .arg(url)
-        .arg(format!("-P {}", TARGET_DIRECTORY))
-        .arg(format!(
-            "-f bestvideo[ext={}]+bestaudio[ext=m4a]/{}",
-            VIDEO_EXTENSIONS_FORMAT, VIDEO_EXTENSIONS_FORMAT
-        ))
-        .arg(format!("-o{}.%(ext)s", url_id))
+        .args(["-P", TARGET_DIRECTORY])
+        .args(["-f", "bestaudio/best"])
+        .args(["-x", "--audio-format", &format.to_string()])
+        .args(["--embed-metadata", "--embed-thumbnail"])
+        .args(["-o", &format!("{}.%(ext)s", audio_id)])
+        .arg("--no-playlist")
         .arg("--no-mtime")
+        .arg("--newline");
+
+    let output = run_with_timeout(&mut command, timeout, |line| {
+        if let Some(percent) = parse_yt_dlp_progress(line) {
+            progress.report(percent);
+        }
+    })
+    .await?;
+
+    if !output.status.success() {
+        return Err(yt_dlp_failure(&output, proxied));
+    }
+
+    // Kept by the `cleaner` as long as the key lives
+    let _ = redis.set(audio_id, &output_path).await;
+    Ok(())
+}
+
+/// Extracts the metadata of a video through `yt-dlp`, without downloading it
+/// # Arguments
+/// * `url` - The `UrlFormatter` of the video
+/// * `proxy` - (`Option`) The proxy `yt-dlp` goes through
+/// * `timeout` - How long `yt-dlp` is given before being killed
+/// # Errors
+/// * `MediaDownloaderError::Timeout` - `yt-dlp` did not finish in time
+/// * `MediaDownloaderError::ProxyError` - `yt-dlp` could not go through the proxy
+/// * `MediaDownloaderError::DownloadError` - `yt-dlp` could not be run or failed
+/// * `MediaDownloaderError::ParsingError` - The metadata could not be parsed
+#[instrument(level = "debug", name = "fetch_video_metadata", skip(url))]
+pub async fn fetch_video_metadata(
+    url: &UrlFormatter,
+    proxy: Option<String>,
+    timeout: Duration,
+) -> Result<VideoMetadata, Box<dyn Error + Send>> {
+    let url = url.get_url_string().unwrap();
+
+    let proxied = proxy.is_some();
+    let mut command = tokio::process::Command::new("yt-dlp");
+    if let Some(proxy) = proxy {
+        command.arg("--proxy").arg(proxy);
+    }
+    command.arg("-J").arg("--no-playlist").arg(url);
+
+    let output = run_with_timeout(&mut command, timeout, |_| {}).await?;
+    if !output.status.success() {
+        return Err(yt_dlp_failure(&output, proxied));
+    }
+
+    serde_json::from_slice(&output.stdout).map_err(|e| {
+        error!("Error parsing video metadata: {}", e);
+        Box::new(MediaDownloaderError::ParsingError) as Box<dyn Error + Send>
+    })
+}
+
+/// The error of a failed `yt-dlp` run, telling the proxy apart from the site
+/// # Arguments
+/// * `output` - The output of the run
+/// * `proxied` - Whether `yt-dlp` went through a proxy
+fn yt_dlp_failure(output: &Output, proxied: bool) -> Box<dyn Error + Send> {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    error!("yt-dlp exited with status {} ~ {}", output.status, stderr);
+
+    if proxied && is_proxy_error(&stderr) {
+        return Box::new(MediaDownloaderError::ProxyError);
+    }
+    Box::new(MediaDownloaderError::DownloadError)
+}
+
+/// Whether `yt-dlp` failed to reach or go through its proxy
+fn is_proxy_error(stderr: &str) -> bool {
+    YT_DLP_PROXY_ERRORS
+        .iter()
+        .any(|marker| stderr.contains(marker))
+}
+
+/// Runs the command to completion, capturing its output
+/// The lines printed to `stdout` are handed to `on_line` as soon as they are printed,
+/// `stderr` is drained alongside so that a chatty process never blocks on it
+/// The process is killed once the timeout elapses or when the caller is cancelled
+/// # Errors
+/// * `MediaDownloaderError::Timeout` - The process did not finish in time
+/// * `MediaDownloaderError::DownloadError` - The process could not be run
+pub(crate) async fn run_with_timeout(
+    command: &mut tokio::process::Command,
+    timeout: Duration,
+    mut on_line: impl FnMut(&str),
+) -> Result<Output, Box<dyn Error + Send>> {
+    let program = command.as_std().get_program().to_os_string();
+    let mut child = command
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
-        .spawn()?
-        .wait_with_output()
-        .expect("Failure in capturing output!");
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| {
+            error!("Failed to run {:?}: {}", program, e);
+            Box::new(MediaDownloaderError::DownloadError) as Box<dyn Error + Send>
+        })?;
+
+    let mut stdout_lines = BufReader::new(child.stdout.take().unwrap()).lines();
+    let mut stderr_pipe = child.stderr.take().unwrap();
+    // Dropping the child on timeout kills it
+    let run = async move {
+        let read_stdout = async {
+            let mut stdout = Vec::new();
+            while let Some(line) = stdout_lines.next_line().await? {
+                on_line(&line);
+                stdout.extend_from_slice(line.as_bytes());
+                stdout.push(b'\n');
+            }
+            Ok::<_, std::io::Error>(stdout)
+        };
+        let read_stderr = async {
+            let mut stderr = Vec::new();
+            stderr_pipe.read_to_end(&mut stderr).await?;
+            Ok(stderr)
+        };
+        let (stdout, stderr) = tokio::try_join!(read_stdout, read_stderr)?;
+        let status = child.wait().await?;
+        Ok::<_, std::io::Error>(Output {
+            status,
+            stdout,
+            stderr,
+        })
+    };
+
+    match tokio::time::timeout(timeout, run).await {
+        Ok(Ok(output)) => Ok(output),
+        Ok(Err(e)) => {
+            error!("Failed to capture output: {}", e);
+            Err(Box::new(MediaDownloaderError::DownloadError))
+        }
+        Err(_) => {
+            error!("{:?} killed after {:?}", program, timeout);
+            Err(Box::new(MediaDownloaderError::Timeout))
+        }
+    }
+}
+
+/// Downloads a video from its direct URL inside the `TARGET_DIRECTORY`
+/// If the video was already downloaded, it will return directly
+/// The video is only moved to its file once complete, a failed or cancelled download
+/// is forgotten along with what was written of it
+/// # Arguments
+/// * `http_client` - The client to download with
+/// * `redis` - Where the download is recorded
+/// * `source_url` - The URL of the page hosting the video, used as referer
+/// * `download_url` - The direct URL of the video
+/// * `id` - The ID the video is stored as
+/// * `cookies` - (`Option`) The cookies to inject
+/// * `progress` - (`Option`) Where to report the bytes downloaded to
+#[instrument(level = "debug", name = "download_video_from_url", skip_all)]
+pub async fn download_video_from_url(
+    http_client: &HttpClient,
+    redis: &RedisManager,
+    source_url: &str,
+    download_url: &str,
+    id: &str,
+    cookies: Option<Vec<String>>,
+    progress: Option<&ProgressReporter>,
+) -> Result<(), Box<dyn Error + Send>> {
+    let output_path = format!("{}{}.{}", TARGET_DIRECTORY, id, VIDEO_EXTENSIONS_FORMAT);
+    // The key outlives a download interrupted along with the process
+    if was_video_already_downloaded(redis, id).await
+        && tokio::fs::try_exists(&output_path).await.unwrap_or(false)
+    {
+        debug!("Video already downloaded!");
+        return Ok(());
+    }
+
+    let mut pending = PendingDownload::new(redis, id);
+    let downloaded = stream_video_to_file(
+        http_client,
+        source_url,
+        download_url,
+        &output_path,
+        cookies,
+        progress,
+    )
+    .await;
+    if downloaded.is_err() {
+        forget_download(redis, id).await;
+    }
+    pending.settle();
+    downloaded
+}
+
+/// A direct download in progress, forgotten along with what was written of it
+/// when dropped before being settled (e.g. its task being aborted)
+struct PendingDownload {
+    redis: RedisManager,
+    id: String,
+    settled: bool,
+}
+
+impl PendingDownload {
+    fn new(redis: &RedisManager, id: &str) -> Self {
+        PendingDownload {
+            redis: redis.clone(),
+            id: id.to_string(),
+            settled: false,
+        }
+    }
+
+    fn settle(&mut self) {
+        self.settled = true;
+    }
+}
+
+impl Drop for PendingDownload {
+    fn drop(&mut self) {
+        if self.settled {
+            return;
+        }
+        warn!("Download of `{}` cancelled", self.id);
+        let _ = std::fs::remove_file(format!(
+            "{}{}.{}{}",
+            TARGET_DIRECTORY, self.id, VIDEO_EXTENSIONS_FORMAT, PARTIAL_DOWNLOAD_SUFFIX
+        ));
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            let redis = self.redis.clone();
+            let id = self.id.clone();
+            runtime.spawn(async move {
+                let _ = redis.del(&id).await;
+            });
+        }
+    }
+}
+
+/// Streams a video from its direct URL to the given path, through a partial file
+/// only renamed once the download is complete
+async fn stream_video_to_file(
+    http_client: &HttpClient,
+    source_url: &str,
+    download_url: &str,
+    output_path: &str,
+    cookies: Option<Vec<String>>,
+    progress: Option<&ProgressReporter>,
+) -> Result<(), Box<dyn Error + Send>> {
+    let headers = vec![
+        ("Accept-Language", "en-US,en;q=0.5"),
+        (
+            "Accept",
+            "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8",
+        ),
+        ("Accept-Encoding", "identity"),
+        ("Referer", source_url),
+    ];
+
+    let content = http_client
+        .fetch(download_url, None, None, cookies, None, Some(headers))
+        .await
+        .map_err(|e| {
+            error!("Error fetching video: {}", e);
+            Box::new(MediaDownloaderError::UnreachableResource) as Box<dyn Error + Send>
+        })?;
+
+    if !content.status().is_success() {
+        error!(
+            "Error: Request failed with status code {:?}",
+            content.status()
+        );
+        return Err(Box::new(MediaDownloaderError::UnreachableResource));
+    }
+
+    let _ = tokio::fs::create_dir_all(TARGET_DIRECTORY)
+        .await
+        .map_err(MediaDownloaderError::IoErrorDirectory);
+
+    let partial_path = format!("{}{}", output_path, PARTIAL_DOWNLOAD_SUFFIX);
+    let mut file = match tokio::fs::File::create(&partial_path).await {
+        Ok(file) => file,
+        Err(err) => {
+            error!("Error creating file: {}", err);
+            return Err(Box::new(MediaDownloaderError::IoErrorDirectory(err)));
+        }
+    };
+
+    let total = content.content_length();
+    let mut downloaded = 0;
+    let mut stream = content.bytes_stream();
+    while let Some(chunk) = futures::StreamExt::next(&mut stream).await {
+        let chunk = chunk.map_err(|e| {
+            error!("Download interrupted: {}", e);
+            Box::new(MediaDownloaderError::DownloadError) as Box<dyn Error + Send>
+        })?;
+        tokio::io::AsyncWriteExt::write_all(&mut file, &chunk)
+            .await
+            .map_err(|e| {
+                Box::new(MediaDownloaderError::IoErrorDirectory(e)) as Box<dyn Error + Send>
+            })?;
+
+        downloaded += chunk.len() as u64;
+        if let Some(progress) = progress {
+            progress.report_bytes(downloaded, total);
+        }
+    }
+
+    let moved = async {
+        tokio::io::AsyncWriteExt::flush(&mut file).await?;
+        drop(file);
+        tokio::fs::rename(&partial_path, output_path).await
+    };
+    if let Err(e) = moved.await {
+        error!("Error moving the download to `{}`: {}", output_path, e);
+        let _ = tokio::fs::remove_file(&partial_path).await;
+        return Err(Box::new(MediaDownloaderError::IoErrorDirectory(e)));
+    }
+    Ok(())
+}
+
+/// Downloads a resource from its direct URL to the given path, streaming it to disk
+/// # Arguments
+/// * `http_client` - The client to download with
+/// * `url` - The direct URL of the resource
+/// * `path` - The path to store the resource at
+/// * `user_agent` - (`Option`) The user agent to use
+/// # Errors
+/// * `MediaDownloaderError::UnreachableResource` - The resource could not be fetched
+/// * `MediaDownloaderError::DownloadError` - The download was interrupted
+pub async fn download_file(
+    http_client: &HttpClient,
+    url: &str,
+    path: &str,
+    user_agent: Option<String>,
+) -> Result<(), Box<dyn Error + Send>> {
+    let content = http_client
+        .fetch(url, None, None, None, user_agent, None)
+        .await
+        .map_err(|e| {
+            error!("Error fetching file: {}", e);
+            Box::new(MediaDownloaderError::UnreachableResource) as Box<dyn Error + Send>
+        })?;
+
+    if !content.status().is_success() {
+        debug!("File {:?} not available ~ {:?}", url, content.status());
+        return Err(Box::new(MediaDownloaderError::UnreachableResource));
+    }
+
+    let _ = tokio::fs::create_dir_all(TARGET_DIRECTORY).await;
+    let mut file = tokio::fs::File::create(path).await.map_err(|e| {
+        Box::new(MediaDownloaderError::IoErrorDirectory(e)) as Box<dyn Error + Send>
+    })?;
+
+    let mut stream = content.bytes_stream();
+    while let Some(chunk) = futures::StreamExt::next(&mut stream).await {
+        let chunk = chunk.map_err(|e| {
+            error!("Error downloading file: {}", e);
+            Box::new(MediaDownloaderError::DownloadError) as Box<dyn Error + Send>
+        })?;
+        tokio::io::AsyncWriteExt::write_all(&mut file, &chunk)
+            .await
+            .map_err(|e| {
+                Box::new(MediaDownloaderError::IoErrorDirectory(e)) as Box<dyn Error + Send>
+            })?;
+    }
+    Ok(())
+}
+
+/// Merges separate video and audio tracks (e.g. DASH) into a single file, without re-encoding
+/// # Arguments
+/// * `video_path` - The path of the video track
+/// * `audio_path` - The path of the audio track
+/// * `output_path` - The path of the merged file
+/// * `timeout` - How long `ffmpeg` is given before being killed
+/// # Errors
+/// * `MediaDownloaderError::MuxingError` - `ffmpeg` could not be run or failed
+/// * `MediaDownloaderError::Timeout` - `ffmpeg` did not finish in time
+#[instrument(level = "debug", name = "mux_video_audio")]
+pub async fn mux_video_audio(
+    video_path: &str,
+    audio_path: &str,
+    output_path: &str,
+    timeout: Duration,
+) -> Result<(), Box<dyn Error + Send>> {
+    let mut command = tokio::process::Command::new("ffmpeg");
+    command
+        .args(["-y", "-loglevel", "error"])
+        .args(["-i", video_path, "-i", audio_path])
+        .args(["-map", "0:v:0", "-map", "1:a:0", "-c", "copy"])
+        .args(["-movflags", "+faststart"])
+        .arg(output_path);
+    let output = run_with_timeout(&mut command, timeout, |_| {})
+        .await
+        .map_err(|e| match e.downcast_ref() {
+            Some(MediaDownloaderError::Timeout) => e,
+            _ => Box::new(MediaDownloaderError::MuxingError) as Box<dyn Error + Send>,
+        })?;
 
     if !output.status.success() {
+        error!(
+            "ffmpeg exited with status {} ~ {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        return Err(Box::new(MediaDownloaderError::MuxingError));
+    }
+    Ok(())
+}
+
+/// Renders images into a video, each shown for `seconds_per_image`, with an optional soundtrack
+/// Images are letterboxed to the `SLIDESHOW_RESOLUTION`
+/// # Arguments
+/// * `image_paths` - The paths of the images, in order
+/// * `audio_path` - (`Option`) The path of the soundtrack
+/// * `seconds_per_image` - How long each image is shown
+/// * `output_path` - The path of the rendered video
+/// * `timeout` - How long the rendering may take before `ffmpeg` is killed
+/// # Errors
+/// * `MediaDownloaderError::RenderingError` - `ffmpeg` could not be run or failed
+/// * `MediaDownloaderError::Timeout` - `ffmpeg` did not finish in time
+#[instrument(level = "debug", name = "render_slideshow")]
+pub async fn render_slideshow(
+    image_paths: &[String],
+    audio_path: Option<&str>,
+    seconds_per_image: u32,
+    output_path: &str,
+    timeout: Duration,
+) -> Result<(), Box<dyn Error + Send>> {
+    if image_paths.is_empty() {
+        return Err(Box::new(MediaDownloaderError::RenderingError));
     }
 
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let reader = BufReader::new(stdout.as_bytes());
+    let mut command = tokio::process::Command::new("ffmpeg");
+    command.args(["-y", "-loglevel", "error"]);
+    for image_path in image_paths {
+        command
+            .args(["-loop", "1", "-t", &seconds_per_image.to_string()])
+            .args(["-i", image_path]);
+    }
+    if let Some(audio_path) = audio_path {
+        command.args(["-i", audio_path]);
+    }
 
-    reader
-        .lines()
-        .map_while(Result::ok)
-        .filter(|line| line.contains("[download]"))
-        .for_each(|line| debug!("\n{}\n", line));
+    command
+        .args(["-filter_complex", &slideshow_filter(image_paths.len())])
+        .args(["-map", "[v]"]);
+    if audio_path.is_some() {
+        command
+            .args(["-map", &format!("{}:a:0", image_paths.len())])
+            .args(["-c:a", "aac", "-b:a", "128k"]);
+    }
 
+    let duration = seconds_per_image as usize * image_paths.len();
+    command
+        .args(["-c:v", "libx264", "-pix_fmt", "yuv420p"])
+        .args(["-t", &duration.to_string()])
+        .args(["-movflags", "+faststart"])
+        .arg(output_path);
+    let output = run_with_timeout(&mut command, timeout, |_| {})
+        .await
+        .map_err(|e| match e.downcast_ref() {
+            Some(MediaDownloaderError::Timeout) => e,
+            _ => Box::new(MediaDownloaderError::RenderingError) as Box<dyn Error + Send>,
+        })?;
+
+    if !output.status.success() {
+        error!(
+            "ffmpeg exited with status {} ~ {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        return Err(Box::new(MediaDownloaderError::RenderingError));
+    }
     Ok(())
 }
 
+/// Builds the `ffmpeg` filter fitting every image to the slideshow size and concatenating them
+fn slideshow_filter(images: usize) -> String {
+    let (width, height) = SLIDESHOW_RESOLUTION;
+    let mut filter = String::new();
+
+    for i in 0..images {
+        filter.push_str(&format!(
+            "[{i}:v]scale={width}:{height}:force_original_aspect_ratio=decrease,\
+             pad={width}:{height}:(ow-iw)/2:(oh-ih)/2,setsar=1,fps=30[v{i}];"
+        ));
+    }
+    for i in 0..images {
+        filter.push_str(&format!("[v{i}]"));
+    }
+    filter.push_str(&format!("concat=n={images}:v=1:a=0[v]"));
+    filter
+}
+
 /// From a URL ID, verify that the key is already present in Redis
 /// If it is not, it will be set
 /// # Arguments
+/// * `redis_manager` - Where the downloads are recorded
 /// * `url_id` - The ID of the video
 /// # Returns
 /// * `bool` - Whether the video was already downloaded or not
-#[instrument(level = "debug", name = "was_video_already_downloaded")]
-pub async fn was_video_already_downloaded(url_id: &str) -> bool {
-    let redis_manager = get_redis_manager().await;
-
+#[instrument(
+    level = "debug",
+    name = "was_video_already_downloaded",
+    skip(redis_manager)
+)]
+pub async fn was_video_already_downloaded(redis_manager: &RedisManager, url_id: &str) -> bool {
     let output_path = format!("{}{}.{}", TARGET_DIRECTORY, url_id, VIDEO_EXTENSIONS_FORMAT);
 
     match redis_manager.get(url_id).await {
@@ -88,16 +658,36 @@ pub async fn was_video_already_downloaded(url_id: &str) -> bool {
     }
 }
 
+/// Forgets a failed download along with what was written of it,
+/// so that the next attempt is not skipped as already downloaded
+/// # Arguments
+/// * `redis` - Where the download was recorded
+/// * `url_id` - The ID of the video
+pub async fn forget_download(redis: &RedisManager, url_id: &str) {
+    let _ = redis.del(url_id).await;
+    let output_path = format!("{}{}.{}", TARGET_DIRECTORY, url_id, VIDEO_EXTENSIONS_FORMAT);
+    let _ = tokio::fs::remove_file(format!("{}{}", output_path, PARTIAL_DOWNLOAD_SUFFIX)).await;
+    let _ = tokio::fs::remove_file(output_path).await;
+}
+
 /// From a URL ID and counter, verify that the key is already present in Redis
 /// If it is not, it will be set
 /// # Arguments
+/// * `redis_manager` - Where the downloads are recorded
 /// * `url_id` - The ID of the image
 /// * `c` - The counter of the image
 /// # Returns
 /// * `bool` - Whether the image was already downloaded or not
-#[instrument(level = "debug", name = "was_image_already_downloaded")]
-pub async fn was_image_already_downloaded(url_id: &str, c: i32) -> bool {
-    let redis_manager = get_redis_manager().await;
+#[instrument(
+    level = "debug",
+    name = "was_image_already_downloaded",
+    skip(redis_manager)
+)]
+pub async fn was_image_already_downloaded(
+    redis_manager: &RedisManager,
+    url_id: &str,
+    c: i32,
+) -> bool {
     let key = &format!("{}_{}", url_id, c);
 
     debug!("Looking up key: {:?}", key);
@@ -121,79 +711,115 @@ pub async fn was_image_already_downloaded(url_id: &str, c: i32) -> bool {
     }
 }
 
-#[instrument(level = "debug", name = "fetch_resource", skip_all)]
-pub async fn fetch_resource(
-    url: &str,
-    query: Option<Vec<(&str, String)>>,
-    referer: Option<&str>,
-    cookies: Option<Vec<(String, Option<Url>)>>,
-    user_agent: Option<String>,
-    headers: Option<Vec<(&str, &str)>>,
-) -> Result<reqwest::Response, reqwest::Error> {
-    let client = reqwest::Client::builder();
-    let mut headers_map = reqwest::header::HeaderMap::new();
-    let jar = Arc::new(reqwest::cookie::Jar::default());
-
-    let ua = if let Some(ua_as_string) = user_agent {
-        ua_as_string.parse::<HeaderValue>().unwrap()
-    } else {
-        retrieve_random_user_agent().await
-    };
+#[cfg(test)]
+mod downloader_test {
+    use super::*;
+    use crate::media_downloader::mock_server::MockRedis;
+    use tokio::io::AsyncWriteExt;
+
+    #[tokio::test]
+    async fn test_run_with_timeout_kills_hung_process() {
+        let mut command = tokio::process::Command::new("sleep");
+        command.arg("30");
 
-    headers_map.insert("user-agent", ua);
+        let started = std::time::Instant::now();
+        let error = run_with_timeout(&mut command, Duration::from_millis(100), |_| {})
+            .await
+            .unwrap_err();
 
-    if referer.is_some() {
-        debug!("Injecting referer");
-        headers_map.insert("referer", referer.unwrap().parse().unwrap());
+        assert!(matches!(
+            error.downcast_ref::<MediaDownloaderError>(),
+            Some(MediaDownloaderError::Timeout)
+        ));
+        assert!(started.elapsed() < Duration::from_secs(5));
     }
 
-    if cookies.is_some() {
-        debug!("Injecting cookies");
-        let cookies = cookies.unwrap();
+    #[tokio::test]
+    async fn test_run_with_timeout_drains_stderr() {
+        // Far more than a pipe buffer holds
+        let mut command = tokio::process::Command::new("sh");
+        command.args(["-c", "head -c 1000000 /dev/zero >&2; echo done"]);
 
-        cookies.iter().for_each(|(cookie_str, u)| {
-            let url = u
-                .as_ref()
-                .map_or_else(|| url::Url::parse(url).unwrap(), |u| u.clone());
-            jar.add_cookie_str(&cookie_str, &url);
-        });
+        let mut lines = Vec::new();
+        let output = run_with_timeout(&mut command, Duration::from_secs(5), |line| {
+            lines.push(line.to_string())
+        })
+        .await
+        .unwrap();
+
+        assert!(output.status.success());
+        assert_eq!(output.stderr.len(), 1_000_000);
+        assert_eq!(lines, vec!["done"]);
     }
 
-    if headers.is_some() {
-        debug!("Injecting headers");
-        let headers_unpacked = headers.unwrap();
+    #[tokio::test]
+    async fn test_yt_dlp_proxy_failure() {
+        let fail_with = |stderr: &str| {
+            let mut command = tokio::process::Command::new("sh");
+            command.args(["-c", &format!("echo '{}' >&2; exit 1", stderr)]);
+            command
+        };
+        let is_proxy_error = |error: Box<dyn Error + Send>| {
+            matches!(
+                error.downcast_ref::<MediaDownloaderError>(),
+                Some(MediaDownloaderError::ProxyError)
+            )
+        };
 
-        headers_unpacked.iter().for_each(|&(header, value)| {
-            headers_map.insert(
-                header::HeaderName::from_str(header).unwrap(),
-                value.parse().unwrap(),
-            );
-        });
+        let proxy_failure = "ERROR: Unable to download webpage: ('Unable to connect to proxy', OSError('Tunnel connection failed: 407'))";
+        let output = run_with_timeout(
+            &mut fail_with(proxy_failure),
+            Duration::from_secs(5),
+            |_| {},
+        )
+        .await
+        .unwrap();
+        assert!(is_proxy_error(yt_dlp_failure(&output, true)));
+        assert!(!is_proxy_error(yt_dlp_failure(&output, false)));
+
+        let site_failure = "ERROR: [youtube] abc: Video unavailable";
+        let output = run_with_timeout(&mut fail_with(site_failure), Duration::from_secs(5), |_| {})
+            .await
+            .unwrap();
+        assert!(!is_proxy_error(yt_dlp_failure(&output, true)));
     }
 
-    let response = client
-        .cookie_provider(jar)
-        .build()
-        .unwrap()
-        .get(url)
-        .query(&query)
-        .headers(headers_map)
-        .send()
-        .await?;
+    #[tokio::test]
+    async fn test_cancelled_download_is_forgotten() {
+        let id = "7300000000000000020";
+        let output_path = format!("{}{}.{}", TARGET_DIRECTORY, id, VIDEO_EXTENSIONS_FORMAT);
+        let partial_path = format!("{}{}", output_path, PARTIAL_DOWNLOAD_SUFFIX);
 
-    Ok(response)
-}
+        // Sends the start of the video, then stalls
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/video.mp4", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = [0u8; 1024];
+            let _ = stream.read(&mut request).await;
+            let _ = stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 1000\r\n\r\nstart")
+                .await;
+            tokio::time::sleep(Duration::from_secs(30)).await;
+        });
 
-#[instrument(level = "debug", name = "retrieve_random_user_agent")]
-async fn retrieve_random_user_agent() -> HeaderValue {
-    let user_agent = match reqwest::get("https://randua.somespecial.one/").await {
-        Ok(r) => r.text().await.unwrap(),
-        Err(e) => {
-            error!("Error retrieving user agent: {}", e);
-            "user-agent=Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/121.0.0.0 Safari/537.36".to_string()
+        let redis = MockRedis::start().await;
+        let manager = redis.manager().await;
+        let download = tokio::spawn(async move {
+            download_video_from_url(&HttpClient::default(), &manager, &url, &url, id, None, None)
+                .await
+        });
+        while !tokio::fs::try_exists(&partial_path).await.unwrap() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
         }
-    };
+        assert!(redis.get(id).is_some());
 
-    debug!("Using user agent: {}", user_agent);
-    user_agent.parse().unwrap()
+        download.abort();
+        let _ = download.await;
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        assert!(!tokio::fs::try_exists(&partial_path).await.unwrap());
+        assert!(!tokio::fs::try_exists(&output_path).await.unwrap());
+        assert_eq!(redis.get(id), None);
+    }
 }
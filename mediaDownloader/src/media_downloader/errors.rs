@@ -19,10 +19,51 @@ pub enum MediaDownloaderError {
     ParsingError,
     UnreachableResource,
     DriverError,
+    MuxingError,
+    RenderingError,
+    Timeout,
+    TranscodingError,
+    ProxyError,
 }
 
 impl Error for MediaDownloaderError {}
 
+impl MediaDownloaderError {
+    pub fn variant_name(&self) -> &'static str {
+        match self {
+            MediaDownloaderError::GenericError => "GenericError",
+            MediaDownloaderError::UnsupportedDomain => "UnsupportedDomain",
+            MediaDownloaderError::BlobRetrievingError => "BlobRetrievingError",
+            MediaDownloaderError::DownloadError => "DownloadError",
+            MediaDownloaderError::CouldNotExtractId => "CouldNotExtractId",
+            MediaDownloaderError::InvalidUrl => "InvalidUrl",
+            MediaDownloaderError::FileSizeExceeded => "FileSizeExceeded",
+            MediaDownloaderError::ImagesNotDownloaded => "ImagesNotDownloaded",
+            MediaDownloaderError::IoErrorDirectory(_) => "IoErrorDirectory",
+            MediaDownloaderError::CustomParsingError(_) => "CustomParsingError",
+            MediaDownloaderError::ParsingError => "ParsingError",
+            MediaDownloaderError::UnreachableResource => "UnreachableResource",
+            MediaDownloaderError::DriverError => "DriverError",
+            MediaDownloaderError::MuxingError => "MuxingError",
+            MediaDownloaderError::RenderingError => "RenderingError",
+            MediaDownloaderError::Timeout => "Timeout",
+            MediaDownloaderError::TranscodingError => "TranscodingError",
+            MediaDownloaderError::ProxyError => "ProxyError",
+        }
+    }
+
+    /// Whether retrying the same request later could succeed
+    pub fn is_retryable(&self) -> bool {
+        !matches!(
+            self,
+            MediaDownloaderError::UnsupportedDomain
+                | MediaDownloaderError::CouldNotExtractId
+                | MediaDownloaderError::InvalidUrl
+                | MediaDownloaderError::FileSizeExceeded
+        )
+    }
+}
+
 impl From<io::Error> for MediaDownloaderError {
     fn from(error: io::Error) -> Self {
         MediaDownloaderError::IoErrorDirectory(error)
@@ -59,12 +100,25 @@ impl Display for MediaDownloaderError {
             MediaDownloaderError::IoErrorDirectory(_) => {
                 write!(f, "{} Error creating `images` directory!", MONKEY)
             }
-            MediaDownloaderError::CustomParsingError(_) => {
-                write!(f, "{}", self)
+            MediaDownloaderError::CustomParsingError(e) => {
+                write!(f, "{} {}", FAILED, e)
             }
             MediaDownloaderError::ParsingError => MediaDownloaderError::GenericError.fmt(f),
             MediaDownloaderError::UnreachableResource => MediaDownloaderError::GenericError.fmt(f),
             MediaDownloaderError::DriverError => MediaDownloaderError::GenericError.fmt(f),
+            MediaDownloaderError::MuxingError => {
+                write!(f, "{} Error merging video and audio!", RADIOACTIVE)
+            }
+            MediaDownloaderError::RenderingError => {
+                write!(f, "{} Error rendering slideshow!", RADIOACTIVE)
+            }
+            MediaDownloaderError::Timeout => {
+                write!(f, "{} Download timed out!", FAILED)
+            }
+            MediaDownloaderError::TranscodingError => {
+                write!(f, "{} Error shrinking video!", RADIOACTIVE)
+            }
+            MediaDownloaderError::ProxyError => MediaDownloaderError::GenericError.fmt(f),
         }
     }
 }
@@ -0,0 +1,317 @@
+use std::{collections::HashMap, error::Error, str::FromStr, sync::Arc, time::Duration};
+
+use reqwest::header::{self, HeaderMap};
+use serde::Deserialize;
+use tracing::instrument;
+
+use super::proxy_pool::{ProxyPool, ProxyPoolConfig};
+use super::user_agent::{UserAgentConfig, UserAgentPool};
+
+const DEFAULT_CONNECT_TIMEOUT_SECONDS: u64 = 10;
+const DEFAULT_TIMEOUT_SECONDS: u64 = 300;
+const DEFAULT_POOL_IDLE_TIMEOUT_SECONDS: u64 = 90;
+const DEFAULT_POOL_MAX_IDLE_PER_HOST: usize = 8;
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct HttpConfig {
+    pub connect_timeout_seconds: Option<u64>,
+    /// Covers the whole request, body included
+    pub timeout_seconds: Option<u64>,
+    pub pool_idle_timeout_seconds: Option<u64>,
+    pub pool_max_idle_per_host: Option<usize>,
+    /// The proxy of the requests not routed through the proxy pool
+    pub proxy: Option<String>,
+    pub proxies: Option<ProxyPoolConfig>,
+    /// PEM files of additional root certificates to trust
+    pub ca_certificates: Option<Vec<String>>,
+    pub accept_invalid_certs: Option<bool>,
+    /// Overrides of the base URLs called by the processors, by name
+    pub base_urls: Option<HashMap<String, String>>,
+    pub user_agents: Option<UserAgentConfig>,
+}
+
+/// HTTP client shared by the processors, pooling connections across requests
+/// Cloning is cheap, the clones share the same pool
+#[derive(Debug, Clone)]
+pub struct HttpClient {
+    client: reqwest::Client,
+    /// A client per proxy of the pool, by URL
+    proxied_clients: Arc<HashMap<String, reqwest::Client>>,
+    proxies: ProxyPool,
+    /// The job the requests belong to, for sticky proxies
+    job_key: Option<String>,
+    base_urls: HashMap<String, String>,
+    user_agents: UserAgentPool,
+}
+
+impl HttpClient {
+    /// Builds the client from the configuration
+    /// # Errors
+    /// * The proxy, one of the proxies of the pool, one of the certificates
+    ///   or one of the user agents is invalid
+    pub fn new(config: &HttpConfig) -> Result<Self, Box<dyn Error>> {
+        let proxies = ProxyPool::new(&config.proxies.clone().unwrap_or_default())?;
+        let proxied_clients = proxies
+            .urls()
+            .map(|proxy| Ok((proxy.to_string(), build_client(config, Some(proxy))?)))
+            .collect::<Result<HashMap<_, _>, Box<dyn Error>>>()?;
+
+        Ok(HttpClient {
+            client: build_client(config, config.proxy.as_deref())?,
+            proxied_clients: Arc::new(proxied_clients),
+            proxies,
+            job_key: None,
+            base_urls: config.base_urls.clone().unwrap_or_default(),
+            user_agents: UserAgentPool::new(&config.user_agents.clone().unwrap_or_default())?,
+        })
+    }
+
+    /// A client sending the requests of the given job, sticky proxies stay the same across them
+    pub fn for_job(&self, job_key: &str) -> HttpClient {
+        HttpClient {
+            job_key: Some(job_key.to_string()),
+            ..self.clone()
+        }
+    }
+
+    /// The proxy the URL would be requested through, if any
+    pub fn proxy_for(&self, url: &str) -> Option<String> {
+        self.proxies
+            .select(url, self.job_key.as_deref())
+            .into_iter()
+            .next()
+    }
+
+    /// The proxies to try for the URL, in order, `None` being a direct connection
+    pub fn proxies_for(&self, url: &str) -> Vec<Option<String>> {
+        let proxies = self.proxies.select(url, self.job_key.as_deref());
+        if proxies.is_empty() {
+            return vec![None];
+        }
+        proxies.into_iter().map(Some).collect()
+    }
+
+    /// Skips the proxy until the retry delay of the pool elapses, for the requests
+    /// made outside of the client (e.g. by `yt-dlp`)
+    pub fn mark_proxy_dead(&self, proxy: &str) {
+        self.proxies.mark_dead(proxy);
+    }
+
+    /// The base URL registered under `name`, unless overridden by the configuration
+    /// # Arguments
+    /// * `name` - The name of the base URL (e.g. `fxtwitter`)
+    /// * `default` - The base URL to use when not overridden
+    pub fn base_url(&self, name: &str, default: &str) -> String {
+        self.base_urls
+            .get(name)
+            .cloned()
+            .unwrap_or_else(|| default.to_string())
+    }
+
+    /// Sends a `GET` request through the shared pool
+    /// # Arguments
+    /// * `url` - The URL to fetch
+    /// * `query` - (`Option`) The query parameters to append
+    /// * `referer` - (`Option`) The referer to send
+    /// * `cookies` - (`Option`) The cookies to send, as `name=value`
+    /// * `user_agent` - (`Option`) The user agent, one from the pool is used otherwise
+    /// * `headers` - (`Option`) Additional headers
+    #[instrument(level = "debug", name = "fetch_resource", skip_all)]
+    pub async fn fetch(
+        &self,
+        url: &str,
+        query: Option<Vec<(&str, String)>>,
+        referer: Option<&str>,
+        cookies: Option<Vec<String>>,
+        user_agent: Option<String>,
+        headers: Option<Vec<(&str, &str)>>,
+    ) -> Result<reqwest::Response, reqwest::Error> {
+        let mut headers_map = HeaderMap::new();
+
+        let ua = user_agent.unwrap_or_else(|| self.user_agents.pick(url));
+        debug!("Using user agent: {}", ua);
+        headers_map.insert(header::USER_AGENT, ua.parse().unwrap());
+
+        if let Some(referer) = referer {
+            debug!("Injecting referer");
+            headers_map.insert(header::REFERER, referer.parse().unwrap());
+        }
+
+        // The pool is shared, so cookies are sent per request instead of through a jar
+        if let Some(cookies) = cookies.filter(|c| !c.is_empty()) {
+            debug!("Injecting cookies");
+            let cookie_header = cookies
+                .iter()
+                .map(|c| c.trim_end_matches(';'))
+                .collect::<Vec<_>>()
+                .join("; ");
+            headers_map.insert(header::COOKIE, cookie_header.parse().unwrap());
+        }
+
+        if let Some(headers_unpacked) = headers {
+            debug!("Injecting headers");
+            headers_unpacked.iter().for_each(|&(header, value)| {
+                headers_map.insert(
+                    header::HeaderName::from_str(header).unwrap(),
+                    value.parse().unwrap(),
+                );
+            });
+        }
+
+        let send = |client: &reqwest::Client| {
+            client
+                .get(url)
+                .query(&query)
+                .headers(headers_map.clone())
+                .send()
+        };
+
+        let proxies = self.proxies.select(url, self.job_key.as_deref());
+        if proxies.is_empty() {
+            return send(&self.client).await;
+        }
+
+        let mut last_error = None;
+        for proxy in proxies {
+            debug!("Requesting through proxy {:?}", proxy);
+            match send(&self.proxied_clients[&proxy]).await {
+                Err(e) if e.is_connect() || e.is_timeout() => {
+                    error!("Error requesting through proxy {:?}: {}", proxy, e);
+                    self.proxies.mark_dead(&proxy);
+                    last_error = Some(e);
+                }
+                result => return result,
+            }
+        }
+        Err(last_error.unwrap())
+    }
+}
+
+/// Builds a client from the configuration, sending its requests through the given proxy
+fn build_client(
+    config: &HttpConfig,
+    proxy: Option<&str>,
+) -> Result<reqwest::Client, Box<dyn Error>> {
+    let mut builder = reqwest::Client::builder()
+        .connect_timeout(Duration::from_secs(
+            config
+                .connect_timeout_seconds
+                .unwrap_or(DEFAULT_CONNECT_TIMEOUT_SECONDS),
+        ))
+        .timeout(Duration::from_secs(
+            config.timeout_seconds.unwrap_or(DEFAULT_TIMEOUT_SECONDS),
+        ))
+        .pool_idle_timeout(Duration::from_secs(
+            config
+                .pool_idle_timeout_seconds
+                .unwrap_or(DEFAULT_POOL_IDLE_TIMEOUT_SECONDS),
+        ))
+        .pool_max_idle_per_host(
+            config
+                .pool_max_idle_per_host
+                .unwrap_or(DEFAULT_POOL_MAX_IDLE_PER_HOST),
+        )
+        .danger_accept_invalid_certs(config.accept_invalid_certs.unwrap_or_default());
+
+    if let Some(proxy) = proxy {
+        builder = builder.proxy(reqwest::Proxy::all(proxy)?);
+    }
+    for path in config.ca_certificates.iter().flatten() {
+        let pem = std::fs::read(path)
+            .map_err(|e| format!("Failed to read certificate {}: {}", path, e))?;
+        builder = builder.add_root_certificate(reqwest::Certificate::from_pem(&pem)?);
+    }
+    Ok(builder.build()?)
+}
+
+impl Default for HttpClient {
+    fn default() -> Self {
+        HttpClient::new(&HttpConfig::default()).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod http_client_test {
+    use super::*;
+    use crate::media_downloader::mock_server::{MockResponse, MockServer};
+
+    #[tokio::test]
+    async fn test_fetch_sends_cookies_and_follows_redirects() {
+        let server = MockServer::start(vec![
+            ("/short", MockResponse::redirect("/full")),
+            ("/full", MockResponse::html("ok".to_string())),
+        ])
+        .await;
+        let config: HttpConfig = toml::from_str(
+            r#"
+    timeout_seconds = 5
+    [base_urls]
+    fxtwitter = "http://127.0.0.1:1/"
+    "#,
+        )
+        .unwrap();
+        let http_client = HttpClient::new(&config).unwrap();
+
+        let content = http_client
+            .fetch(
+                &server.url("/short"),
+                None,
+                None,
+                Some(vec!["a=1;".to_string(), "b=2;".to_string()]),
+                Some("Mozilla/5.0".to_string()),
+                None,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(content.url().path(), "/full");
+        assert_eq!(
+            server.request_header(1, "cookie"),
+            Some("a=1; b=2".to_string())
+        );
+        assert_eq!(content.text().await.unwrap(), "ok");
+        assert_eq!(
+            http_client.base_url("fxtwitter", "https://api.fxtwitter.com/"),
+            "http://127.0.0.1:1/"
+        );
+        assert_eq!(
+            http_client.base_url("reddit", "https://www.reddit.com/"),
+            "https://www.reddit.com/"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fetch_skips_unreachable_proxies() {
+        // Plain HTTP requests go through the proxy with their absolute URL as target
+        let proxy = MockServer::start(vec![(
+            "http://media.test/clip",
+            MockResponse::html("through proxy".to_string()),
+        )])
+        .await;
+        let config: HttpConfig = toml::from_str(&format!(
+            r#"
+    [proxies]
+    [[proxies.pool]]
+    url = "http://127.0.0.1:1"
+    sites = ["media.test"]
+    [[proxies.pool]]
+    url = "{}"
+    sites = ["media.test"]
+    "#,
+            proxy.url("")
+        ))
+        .unwrap();
+        let http_client = HttpClient::new(&config).unwrap();
+
+        let content = http_client
+            .fetch("http://media.test/clip", None, None, None, None, None)
+            .await
+            .unwrap();
+
+        assert_eq!(content.text().await.unwrap(), "through proxy");
+        assert_eq!(
+            http_client.proxy_for("http://media.test/clip"),
+            Some(proxy.url(""))
+        );
+    }
+}
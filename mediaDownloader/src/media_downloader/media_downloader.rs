@@ -7,22 +7,47 @@
 )]
 
 use futures::{StreamExt, TryFutureExt};
-use mediadownloader::media_downloader::processors::{route_to_processor, Processor, ProcessorType};
+use mediadownloader::media_downloader::processors::ProcessorContext;
+use mediadownloader::media_downloader::progress::ProgressTracker;
 use mediadownloader::media_downloader::{
-    downloader::download_video, errors::MediaDownloaderError, formatter::UrlFormatter,
+    downloader::{download_audio, download_video, fetch_video_metadata},
+    errors::MediaDownloaderError,
+    formatter::UrlFormatter,
     site_validator::SupportedSites,
+    worker_pool::WorkerPool,
+};
+use mediadownloader::services::{
+    drain_tasks, init_telemetry, shutdown_signal, shutdown_telemetry, AudioFormat, ChatSettings,
+    DeadLetter, QueuedJob, RedisManager,
 };
-use mediadownloader::services::init_telemetry;
 use mediadownloader::{
-    extract_id_from_url, get_redis_manager, reply_message, retrieve_blob, BotMessage,
-    MessageContent, MessageHandled, CONFIG_FILE_SYNC, EXPONENTIAL_BACKOFF_SECONDS,
-    RETRIES_ATTEMPTS, TARGET_DIRECTORY,
+    channel_api, extract_id_from_url, get_redis_manager, media_cache_key, reply_message,
+    retrieve_audio, retrieve_blob, unix_timestamp, AudioFile, BotMessage, MessageContent,
+    MessageHandled, BACKOFF_SECONDS, CAPTIONS_CONFIG, CONFIG_FILE_SYNC,
+    EXPONENTIAL_BACKOFF_SECONDS, HTTP_CLIENT, INFO, JOBS_BACKPRESSURE_WAIT, JOBS_BATCH_SIZE,
+    JOBS_BLOCK_MS, JOBS_HEARTBEAT_INTERVAL, JOBS_MIN_IDLE_MS, JOBS_RECLAIM_INTERVAL,
+    MAX_JOB_DELIVERIES, PROCESSOR_CONFIG, PROCESSOR_REGISTRY, REDIS_CHANNELS, REDIS_CONSUMER_GROUP,
+    REDIS_CONSUMER_NAME, RETRIES_ATTEMPTS, RETRY_POLICY, SHUTDOWN_DRAIN_DEADLINE, TARGET_DIRECTORY,
 };
 use opentelemetry::trace::FutureExt;
-use std::{error::Error, fs, path::Path, sync::Arc};
-use tracing::{debug, error, info, instrument, span};
+use redis::RedisError;
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fs,
+    path::Path,
+    process::ExitCode,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+use tokio::task::JoinSet;
+use tracing::{debug, error, info, instrument, span, warn};
 use tracing_opentelemetry::OpenTelemetrySpanExt;
 
+const MAX_DELIVERIES_EXCEEDED: &str = "MaxDeliveriesExceeded";
+const UNKNOWN_ERROR: &str = "Unknown";
+const AUDIO_ID_SUFFIX: &str = "_audio";
+
 /// Removes a directory recursively (`DEBUG` only!)
 /// # Arguments
 /// * `path` - The path to remove
@@ -46,7 +71,7 @@ fn remove_directory_recursive(path: &Path) -> Result<(), std::io::Error> {
 
 #[tokio::main]
 #[instrument(level = "debug", name = "main")]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+async fn main() -> Result<ExitCode, Box<dyn std::error::Error>> {
     init_telemetry(None).await;
 
     let redis_manager = get_redis_manager().await;
@@ -67,104 +92,622 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         let _ = redis_manager.flushdb().await;
     }
 
-    let supported_sites = Arc::new(SupportedSites::new(&CONFIG_FILE_SYNC));
+    let mut dispatcher = Dispatcher {
+        supported_sites: Arc::new(SupportedSites::new(&CONFIG_FILE_SYNC)),
+        worker_pool: Arc::new(WorkerPool::new(
+            &CONFIG_FILE_SYNC.workers.clone().unwrap_or_default(),
+        )),
+        tasks: JoinSet::new(),
+        running: RunningJobs::default(),
+    };
+
+    let channels: Vec<&str> = REDIS_CHANNELS.iter().map(|c| c.name.as_str()).collect();
+
+    for channel in &channels {
+        redis_manager
+            .create_consumer_group(channel, &REDIS_CONSUMER_GROUP)
+            .await?;
+    }
 
-    let conn = deadpool_redis::Connection::take(redis_manager.retrieve_connection().await.unwrap());
-    let mut pubsub = conn.into_pubsub();
-    let _ = pubsub.subscribe("channel_1").await;
+    info!(
+        "Consuming {:?} as `{}` of `{}`",
+        channels, *REDIS_CONSUMER_NAME, *REDIS_CONSUMER_GROUP
+    );
+
+    // Jobs delivered to this consumer before a restart and never acknowledged
+    match redis_manager
+        .read_jobs(
+            &channels,
+            &REDIS_CONSUMER_GROUP,
+            &REDIS_CONSUMER_NAME,
+            "0",
+            JOBS_BATCH_SIZE,
+            None,
+        )
+        .await
+    {
+        Ok(jobs) => {
+            if !jobs.is_empty() {
+                info!("Resuming {} unacknowledged jobs", jobs.len());
+            }
+            dispatcher.dispatch(jobs);
+        }
+        Err(e) => error!("Failed to read pending jobs: {:?}", e),
+    }
 
     info!("Awaiting for messages...");
 
-    let mut stream = pubsub.on_message();
+    tokio::select! {
+        _ = shutdown_signal() => {}
+        _ = consume_jobs(redis_manager, &channels, &mut dispatcher) => {}
+    }
+
+    // Jobs not handled before the deadline are left unacknowledged,
+    // they are resumed on restart or reclaimed by another consumer
+    info!("Shutting down, no more jobs are accepted");
+    let drained = drain_tasks(&mut dispatcher.tasks, SHUTDOWN_DRAIN_DEADLINE).await;
+    shutdown_telemetry().await;
+
+    match drained {
+        true => Ok(ExitCode::SUCCESS),
+        false => Ok(ExitCode::FAILURE),
+    }
+}
+
+/// Keeps track of the in-flight jobs and of what is needed to process them
+struct Dispatcher {
+    supported_sites: Arc<SupportedSites>,
+    worker_pool: Arc<WorkerPool>,
+    tasks: JoinSet<()>,
+    running: RunningJobs,
+}
+
+/// The ids of the jobs being processed (or waiting for a worker), by stream
+#[derive(Debug, Clone, Default)]
+struct RunningJobs(Arc<Mutex<HashMap<String, HashSet<String>>>>);
+
+impl RunningJobs {
+    /// Tracks the job until the returned guard is dropped
+    fn start(&self, job: &QueuedJob) -> RunningJob {
+        self.0
+            .lock()
+            .unwrap()
+            .entry(job.stream.clone())
+            .or_default()
+            .insert(job.id.clone());
+        RunningJob {
+            jobs: self.clone(),
+            stream: job.stream.clone(),
+            id: job.id.clone(),
+        }
+    }
+
+    fn ids(&self, stream: &str) -> HashSet<String> {
+        self.0
+            .lock()
+            .unwrap()
+            .get(stream)
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+struct RunningJob {
+    jobs: RunningJobs,
+    stream: String,
+    id: String,
+}
+
+impl Drop for RunningJob {
+    fn drop(&mut self) {
+        if let Some(ids) = self.jobs.0.lock().unwrap().get_mut(&self.stream) {
+            ids.remove(&self.id);
+        }
+    }
+}
+
+impl Dispatcher {
+    fn dispatch(&mut self, jobs: Vec<QueuedJob>) {
+        while let Some(outcome) = self.tasks.try_join_next() {
+            if let Err(e) = outcome {
+                error!("Job task failed: {:?}", e);
+            }
+        }
+
+        for job in jobs {
+            let running = self.running.start(&job);
+            dispatch_job(
+                &mut self.tasks,
+                job,
+                running,
+                self.supported_sites.clone(),
+                self.worker_pool.clone(),
+            );
+        }
+    }
+}
+
+/// Consumes the jobs enqueued on the given channels until cancelled
+/// # Arguments
+/// * `redis_manager` - The Redis manager instance
+/// * `channels` - The channels to consume, by priority
+/// * `dispatcher` - The dispatcher spawning the jobs
+async fn consume_jobs(
+    redis_manager: &RedisManager,
+    channels: &[&str],
+    dispatcher: &mut Dispatcher,
+) {
+    let mut last_reclaim = Instant::now();
+    let mut last_heartbeat = Instant::now();
 
     loop {
-        let msg = stream.next().await.unwrap();
-        let bot_message: String = msg.get_payload().unwrap();
-        let supported_sites_arc_clone = supported_sites.clone();
-
-        let bot_message_deserialized: BotMessage = toml::from_str(&bot_message).unwrap();
-        let root_span = span!(tracing::Level::DEBUG, "Request");
-
-        tokio::spawn(async move {
-            match tracing::Instrument::instrument(
-                handle_received_message(&bot_message_deserialized.url, &supported_sites_arc_clone)
-                    .with_context(root_span.context()),
-                root_span.clone(),
-            )
-            .await
+        // Long jobs would otherwise look abandoned and be claimed by other consumers
+        if last_heartbeat.elapsed() >= JOBS_HEARTBEAT_INTERVAL {
+            last_heartbeat = Instant::now();
+            for channel in channels {
+                let ids = dispatcher.running.ids(channel);
+                let ids: Vec<&str> = ids.iter().map(String::as_str).collect();
+                if let Err(e) = redis_manager
+                    .heartbeat_jobs(channel, &REDIS_CONSUMER_GROUP, &REDIS_CONSUMER_NAME, &ids)
+                    .await
+                {
+                    error!("Failed to keep jobs on `{}` claimed: {:?}", channel, e);
+                }
+            }
+        }
+
+        for channel in channels {
+            if let Err(e) = redis_manager
+                .enqueue_due_jobs(channel, unix_timestamp(), JOBS_BATCH_SIZE as isize)
+                .await
             {
-                Ok(message) => match message.content {
-                    Some(MessageContent::File(file)) => {
-                        let mut attempt = 0;
-                        tryhard::retry_fn(move || {
-                            attempt += 1;
-                            debug!("Attempt #{attempt}");
-                            reply_message(
-                                bot_message_deserialized.chat_id,
-                                bot_message_deserialized.message_id,
-                                None,
-                                Some(file.clone()),
-                                None,
-                                bot_message_deserialized.api.clone(),
-                            )
-                        })
-                        .retries(RETRIES_ATTEMPTS)
-                        .exponential_backoff(EXPONENTIAL_BACKOFF_SECONDS)
-                        .with_context(root_span.context())
-                        .await
-                        .unwrap_or_else(|e| {
-                            error!("Failed to send reply: {:?}", e);
-                        })
-                    }
-                    Some(MessageContent::Images(images)) => {
-                        debug!("Ready to Send bulk photos");
-                        let mut attempt = 0;
-                        tryhard::retry_fn(move || {
-                            attempt += 1;
-                            debug!("Attempt #{attempt}");
-                            reply_message(
-                                bot_message_deserialized.chat_id,
-                                bot_message_deserialized.message_id,
-                                None,
-                                None,
-                                Some(images.clone()),
-                                bot_message_deserialized.api.clone(),
-                            )
-                        })
-                        .retries(RETRIES_ATTEMPTS)
-                        .exponential_backoff(EXPONENTIAL_BACKOFF_SECONDS)
-                        .with_context(root_span.context())
-                        .await
-                        .unwrap_or_else(|e| {
-                            error!("Failed to send reply: {:?}", e);
-                        })
-                    }
-                    None => {
-                        error!(
-                            "MessageContent is not populated correctly ~ {:?}",
-                            message.content
-                        );
-                    }
-                },
-                Err(e) => {
-                    let err_msg = e.to_string();
-                    error!("Error: {:?} ~ {}", &e, err_msg);
-                    reply_message(
-                        bot_message_deserialized.chat_id,
-                        bot_message_deserialized.message_id,
-                        Some(err_msg),
-                        None,
-                        None,
-                        bot_message_deserialized.api.clone(),
+                error!("Failed to enqueue scheduled jobs on `{}`: {:?}", channel, e);
+            }
+        }
+
+        // Jobs are left in the stream while the worker pool is saturated
+        let capacity = dispatcher.worker_pool.capacity().min(JOBS_BATCH_SIZE);
+        if capacity == 0 {
+            debug!("Worker pool is saturated, waiting...");
+            tokio::time::sleep(JOBS_BACKPRESSURE_WAIT).await;
+            continue;
+        }
+
+        if last_reclaim.elapsed() >= JOBS_RECLAIM_INTERVAL {
+            last_reclaim = Instant::now();
+            for channel in channels {
+                match redis_manager
+                    .reclaim_stale_jobs(
+                        channel,
+                        &REDIS_CONSUMER_GROUP,
+                        &REDIS_CONSUMER_NAME,
+                        JOBS_MIN_IDLE_MS,
+                        capacity,
+                        &dispatcher.running.ids(channel),
                     )
-                    .with_context(root_span.context())
-                    .unwrap_or_else(|e| {
-                        error!("Failed to send error reply: {:?}", e);
-                    })
-                    .await;
+                    .await
+                {
+                    Ok(jobs) => dispatcher.dispatch(jobs),
+                    Err(e) => error!("Failed to reclaim stale jobs on `{}`: {:?}", channel, e),
+                }
+            }
+        }
+
+        match read_next_jobs(redis_manager, channels, capacity).await {
+            Ok(jobs) => dispatcher.dispatch(jobs),
+            Err(e) => {
+                error!("Failed to read jobs: {:?}", e);
+                tokio::time::sleep(BACKOFF_SECONDS).await;
+            }
+        }
+    }
+}
+
+/// Reads the next batch of jobs
+/// Channels are listed by priority, so they are drained in order
+/// before blocking on all of them at once
+/// # Arguments
+/// * `redis_manager` - The Redis manager instance
+/// * `channels` - The channels to consume, by priority
+/// * `count` - The maximum number of jobs to read
+/// # Returns
+/// * `Result<Vec<QueuedJob>, RedisError>` - The jobs read
+async fn read_next_jobs(
+    redis_manager: &RedisManager,
+    channels: &[&str],
+    count: usize,
+) -> Result<Vec<QueuedJob>, RedisError> {
+    if channels.len() > 1 {
+        for channel in channels {
+            let jobs = redis_manager
+                .read_jobs(
+                    &[channel],
+                    &REDIS_CONSUMER_GROUP,
+                    &REDIS_CONSUMER_NAME,
+                    ">",
+                    count,
+                    None,
+                )
+                .await?;
+            if !jobs.is_empty() {
+                return Ok(jobs);
+            }
+        }
+    }
+
+    redis_manager
+        .read_jobs(
+            channels,
+            &REDIS_CONSUMER_GROUP,
+            &REDIS_CONSUMER_NAME,
+            ">",
+            count,
+            Some(JOBS_BLOCK_MS),
+        )
+        .await
+}
+
+/// Spawns the processing of a queued job, acknowledging it once handled
+/// Jobs that cannot be deserialized are dropped, jobs that were delivered too many times
+/// (e.g. crashing the worker) are moved to the dead-letter queue
+/// # Arguments
+/// * `tasks` - The in-flight tasks
+/// * `job` - The job read from the stream
+/// * `running` - Keeps the job tracked as running until the task ends
+/// * `supported_sites` - The supported sites to check against for validation purposes
+/// * `worker_pool` - The pool bounding the jobs processed concurrently
+fn dispatch_job(
+    tasks: &mut JoinSet<()>,
+    job: QueuedJob,
+    running: RunningJob,
+    supported_sites: Arc<SupportedSites>,
+    worker_pool: Arc<WorkerPool>,
+) {
+    let in_flight = worker_pool.track();
+
+    tasks.spawn(async move {
+        let _in_flight = in_flight;
+        let _running = running;
+        let redis_manager = get_redis_manager().await;
+
+        if job.deliveries > MAX_JOB_DELIVERIES {
+            error!(
+                "Job `{}` delivered {} times, moving it to the dead-letter queue",
+                job.id, job.deliveries
+            );
+            let now = unix_timestamp();
+            let dead_letter = DeadLetter {
+                id: job.id.clone(),
+                payload: job.payload.clone(),
+                error: MAX_DELIVERIES_EXCEEDED.to_string(),
+                attempts: job.deliveries as u32,
+                first_failed_at: now,
+                last_failed_at: now,
+            };
+            if let Err(e) = redis_manager
+                .dead_letter_job(&job.stream, &dead_letter)
+                .await
+            {
+                error!("Failed to dead-letter job `{}`: {:?}", job.id, e);
+            }
+        } else {
+            match toml::from_str::<BotMessage>(&job.payload) {
+                Ok(mut bot_message) => {
+                    bot_message.api = channel_api(&job.stream);
+
+                    let site = UrlFormatter::new(&bot_message.url)
+                        .get_domain_string()
+                        .map(str::to_string)
+                        .unwrap_or_default();
+                    let permit = worker_pool
+                        .acquire(&site, |position| notify_queued(&bot_message, position))
+                        .await;
+                    let outcome = process_job(&job.stream, &bot_message, supported_sites).await;
+                    drop(permit);
+
+                    if let Err(e) = outcome {
+                        handle_failed_job(&job.stream, bot_message, e).await;
+                    }
                 }
+                Err(e) => error!("Failed to deserialize job `{}`: {:?}", job.id, e),
             }
+        }
+
+        if let Err(e) = redis_manager
+            .ack_job(&job.stream, &REDIS_CONSUMER_GROUP, &job.id)
+            .await
+        {
+            error!("Failed to acknowledge job `{}`: {:?}", job.id, e);
+        }
+    });
+}
+
+/// Lets the user know that the request is waiting for a free worker
+/// # Arguments
+/// * `bot_message` - The message received from the bot
+/// * `position` - The position of the request in the queue
+fn notify_queued(bot_message: &BotMessage, position: usize) {
+    let text = format!("{} Your request is queued at position {}", INFO, position);
+    let reply = reply_message(
+        bot_message.chat_id,
+        bot_message.message_id,
+        Some(text),
+        None,
+        None,
+        None,
+        None,
+        bot_message.api.clone(),
+    );
+    tokio::spawn(async move {
+        if let Err(e) = reply.await {
+            error!("Failed to send queued reply: {:?}", e);
+        }
+    });
+}
+
+/// Handles the received message and replies with the requested media
+/// Media already delivered on the same channel is sent again through its cached `file_id`s
+/// # Arguments
+/// * `stream` - The stream the job was read from
+/// * `bot_message_deserialized` - The message received from the bot
+/// * `supported_sites` - The supported sites to check against for validation purposes
+/// # Returns
+/// * `Result<(), Box<dyn Error + Send>>` - The error raised while handling the message
+async fn process_job(
+    stream: &str,
+    bot_message_deserialized: &BotMessage,
+    supported_sites: Arc<SupportedSites>,
+) -> Result<(), Box<dyn Error + Send>> {
+    let root_span = span!(tracing::Level::DEBUG, "Request");
+    let redis_manager = get_redis_manager().await;
+
+    let chat_settings = redis_manager
+        .get_chat_settings(stream, bot_message_deserialized.chat_id)
+        .await
+        .unwrap_or_else(|e| {
+            error!("Failed to look up chat settings: {:?}", e);
+            ChatSettings::default()
         });
+
+    let audio_format = match bot_message_deserialized.audio {
+        true => Some(chat_settings.audio_default.unwrap_or_default()),
+        false => chat_settings.audio_default,
+    };
+
+    // Chats with different preferences are delivered different media for the same resource
+    let media_key = media_cache_key(&bot_message_deserialized.url).map(|media_key| {
+        match (audio_format, chat_settings.delivery_variant()) {
+            (Some(format), _) => format!("{}#audio={}", media_key, format),
+            (None, Some(variant)) => format!("{}#{}", media_key, variant),
+            (None, None) => media_key,
+        }
+    });
+    let cached_media = match &media_key {
+        Some(media_key) => redis_manager
+            .get_cached_media(stream, media_key)
+            .await
+            .unwrap_or_else(|e| {
+                error!("Failed to look up cached media: {:?}", e);
+                None
+            }),
+        None => None,
+    };
+    let from_cache = cached_media.is_some();
+
+    // Dropped once the media is sent, along with the status message
+    let progress_tracker = (!from_cache).then(|| {
+        ProgressTracker::start(
+            bot_message_deserialized.chat_id,
+            bot_message_deserialized.message_id,
+            bot_message_deserialized.api.clone(),
+        )
+    });
+
+    let (content, caption) = match cached_media {
+        Some(media) => {
+            info!("Delivering `{}` from cache", bot_message_deserialized.url);
+            let caption = match &media_key {
+                Some(media_key) => redis_manager
+                    .get_cached_caption(stream, media_key)
+                    .await
+                    .unwrap_or_else(|e| {
+                        error!("Failed to look up cached caption: {:?}", e);
+                        None
+                    }),
+                None => None,
+            };
+            (Some(media.into()), caption)
+        }
+        None => {
+            let context = ProcessorContext {
+                chat_settings,
+                http_client: HTTP_CLIENT.for_job(&format!(
+                    "{}:{}",
+                    bot_message_deserialized.chat_id, bot_message_deserialized.message_id
+                )),
+                progress: progress_tracker
+                    .as_ref()
+                    .map(ProgressTracker::reporter)
+                    .unwrap_or_default(),
+                redis: redis_manager.clone(),
+                config: PROCESSOR_CONFIG.clone(),
+            };
+            let message_handled = match audio_format {
+                Some(format) => {
+                    tracing::Instrument::instrument(
+                        handle_audio_request(
+                            &bot_message_deserialized.url,
+                            &supported_sites,
+                            format,
+                            context,
+                        )
+                        .with_context(root_span.context()),
+                        root_span.clone(),
+                    )
+                    .await?
+                }
+                None => {
+                    tracing::Instrument::instrument(
+                        handle_received_message(
+                            &bot_message_deserialized.url,
+                            &supported_sites,
+                            context,
+                        )
+                        .with_context(root_span.context()),
+                        root_span.clone(),
+                    )
+                    .await?
+                }
+            };
+            let caption = message_handled
+                .metadata
+                .and_then(|m| CAPTIONS_CONFIG.render(&m, &bot_message_deserialized.url));
+            (message_handled.content, caption)
+        }
+    };
+
+    let (blob, images, audio) = match content {
+        Some(MessageContent::File(file)) => (Some(file), None, None),
+        Some(MessageContent::Images(images)) => {
+            debug!("Ready to Send bulk photos");
+            (None, Some(images), None)
+        }
+        Some(MessageContent::Slideshow(images, audio)) => {
+            debug!("Ready to Send bulk photos along with their audio");
+            (None, Some(images), Some(audio.into()))
+        }
+        Some(MessageContent::Audio(audio)) => (None, None, Some(audio)),
+        None => {
+            error!("MessageContent is not populated correctly ~ {:?}", content);
+            return Ok(());
+        }
+    };
+
+    let mut attempt = 0;
+    let delivered_caption = caption.clone();
+    let delivered = tryhard::retry_fn(move || {
+        attempt += 1;
+        debug!("Attempt #{attempt}");
+        reply_message(
+            bot_message_deserialized.chat_id,
+            bot_message_deserialized.message_id,
+            None,
+            blob.clone(),
+            images.clone(),
+            audio.clone(),
+            caption.clone(),
+            bot_message_deserialized.api.clone(),
+        )
+    })
+    .retries(RETRIES_ATTEMPTS)
+    .exponential_backoff(EXPONENTIAL_BACKOFF_SECONDS)
+    .with_context(root_span.context())
+    .await
+    .unwrap_or_else(|e| {
+        error!("Failed to send reply: {:?}", e);
+        None
+    });
+    drop(progress_tracker);
+
+    let Some(media_key) = media_key else {
+        return Ok(());
+    };
+    match (from_cache, delivered) {
+        (false, Some(media)) => {
+            if let Err(e) = redis_manager.cache_media(stream, &media_key, &media).await {
+                error!("Failed to cache media: {:?}", e);
+            }
+            if let Some(caption) = delivered_caption {
+                if let Err(e) = redis_manager
+                    .cache_caption(stream, &media_key, &caption)
+                    .await
+                {
+                    error!("Failed to cache caption: {:?}", e);
+                }
+            }
+        }
+        // Telegram rejected the cached `file_id`s, the media is downloaded again on retry
+        (true, None) => {
+            warn!("Cached media for `{}` was not delivered", media_key);
+            let _ = redis_manager
+                .invalidate_cached_media(stream, &media_key)
+                .await;
+            return Err(Box::new(MediaDownloaderError::BlobRetrievingError));
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Handles a job whose processing failed
+/// Retryable errors are scheduled again with backoff until the retry policy is exhausted,
+/// the job then ends up in the dead-letter queue and the user is notified
+/// # Arguments
+/// * `stream` - The stream the job was read from
+/// * `bot_message` - The message received from the bot
+/// * `error` - The error raised while handling the message
+#[instrument(level = "debug", name = "handle_failed_job", skip(bot_message))]
+async fn handle_failed_job(
+    stream: &str,
+    mut bot_message: BotMessage,
+    error: Box<dyn Error + Send>,
+) {
+    let redis_manager = get_redis_manager().await;
+    let media_downloader_error = error.downcast_ref::<MediaDownloaderError>();
+    let retryable = media_downloader_error.is_none_or(|e| e.is_retryable());
+
+    let now = unix_timestamp();
+    bot_message.attempt += 1;
+    let first_failed_at = *bot_message.first_failed_at.get_or_insert(now);
+
+    if retryable && RETRY_POLICY.should_retry(bot_message.attempt) {
+        let backoff = RETRY_POLICY.backoff(bot_message.attempt);
+        info!(
+            "Attempt #{} failed ~ {:?}, retrying in {:?}",
+            bot_message.attempt, error, backoff
+        );
+        let payload = toml::to_string(&bot_message).unwrap();
+        match redis_manager
+            .schedule_job(stream, &payload, now + backoff.as_secs())
+            .await
+        {
+            Ok(_) => return,
+            Err(e) => error!("Failed to schedule retry: {:?}", e),
+        }
+    }
+
+    if retryable {
+        let dead_letter = DeadLetter {
+            id: String::new(),
+            payload: toml::to_string(&bot_message).unwrap(),
+            error: media_downloader_error
+                .map_or(UNKNOWN_ERROR, |e| e.variant_name())
+                .to_string(),
+            attempts: bot_message.attempt,
+            first_failed_at,
+            last_failed_at: now,
+        };
+        match redis_manager.dead_letter_job(stream, &dead_letter).await {
+            Ok(id) => info!("Job dead-lettered as `{}`", id),
+            Err(e) => error!("Failed to dead-letter job: {:?}", e),
+        }
+    }
+
+    let err_msg = error.to_string();
+    error!("Error: {:?} ~ {}", &error, err_msg);
+    if let Err(e) = reply_message(
+        bot_message.chat_id,
+        bot_message.message_id,
+        Some(err_msg),
+        None,
+        None,
+        None,
+        None,
+        bot_message.api.clone(),
+    )
+    .await
+    {
+        error!("Failed to send error reply: {:?}", e);
     }
 }
 
@@ -172,8 +715,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 /// # Arguments
 /// * `message_url` - The url received from the user
 /// * `supported_sites` - The supported sites to check against for validation purposes
+/// * `context` - The state of the request, handed to the processors
 /// # Returns
-/// * `InputFile` - The blob to forward to the user
+/// * `MessageHandled` - The content to forward to the user
 /// # Errors
 /// * `MediaDownloaderError::UnsupportedDomain` - The domain is not supported
 /// * `MediaDownloaderError::DownloadError` - Error downloading the video
@@ -182,11 +726,12 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 #[instrument(
     level = "debug",
     name = "handle_received_message",
-    skip(supported_sites, message_url)
+    skip(supported_sites, message_url, context)
 )]
 async fn handle_received_message(
     message_url: &str,
     supported_sites: &Arc<SupportedSites>,
+    context: ProcessorContext,
 ) -> Result<MessageHandled, Box<dyn Error + Send>> {
     let url_formatted = UrlFormatter::new(message_url);
 
@@ -198,54 +743,125 @@ async fn handle_received_message(
             }
 
             let url_id = extract_id_from_url(message_url).unwrap();
-            let processor = route_to_processor(&message_url, url_id);
-
-            match processor {
-                Some(ProcessorType::TikTok(mut tiktok_processor)) => {
-                    debug!("TikTok processor!");
-                    let processing_outcome = tiktok_processor.process().await;
-
-                    match processing_outcome {
-                        Ok(Some(content)) => {
-                            return Ok(MessageHandled {
-                                content: Some(content),
-                            });
-                        }
-                        Ok(None) => {
-                            debug!("No content to process received from the TikTok processor!");
-                        }
-                        Err(e) => {
-                            error!("Error processing TikTok resource: {:?}", e);
-                            return Err(e);
-                        }
-                    }
-                }
-                _ => {
-                    debug!("Unspecified processor!")
-                }
-            };
+            let mut first_error: Option<Box<dyn Error + Send>> = None;
+
+            for (name, mut processor) in PROCESSOR_REGISTRY.route(message_url, url_id, &context) {
+                debug!("Trying the `{}` processor", name);
 
-            match download_video(&url_formatted, url_id.to_string()).await {
-                Ok(_) => {
-                    debug!("Successfully obtained video: `{}`", message_url);
-                    match retrieve_blob(&url_id).await {
-                        Ok(file) => {
-                            return Ok(MessageHandled {
-                                content: Some(MessageContent::File(file)),
-                            })
-                        }
-                        Err(e) => {
-                            error!("Error retrieving video: {:?}", e);
-                            return Err(e);
-                        }
+                match processor.process().await {
+                    Ok(Some(content)) => {
+                        return Ok(MessageHandled {
+                            content: Some(content),
+                            metadata: processor.metadata(),
+                        });
+                    }
+                    Ok(None) => {
+                        debug!("No content to process received from `{}`!", name);
+                    }
+                    Err(e) => {
+                        error!("Error processing resource with `{}`: {:?}", name, e);
+                        // An oversized media is still worth a fallback, as `yt-dlp` picks a
+                        // format within the size limit
+                        first_error.get_or_insert(e);
                     }
-                }
-                Err(e) => {
-                    error!("Error downloading video `{}`: {}", message_url, e);
-                    return Err(Box::new(MediaDownloaderError::DownloadError));
                 }
             }
+
+            Err(first_error.unwrap_or_else(|| Box::new(MediaDownloaderError::DownloadError)))
         }
         UrlFormatter::NotValid => Err(Box::new(MediaDownloaderError::InvalidUrl)),
     }
 }
+
+/// Takes a message and replies with the audio track of the requested video,
+/// extracted through `yt-dlp` whatever the site
+/// # Arguments
+/// * `message_url` - The url received from the user
+/// * `supported_sites` - The supported sites to check against for validation purposes
+/// * `format` - The format to extract the audio track as
+/// * `context` - The state of the request
+/// # Returns
+/// * `MessageHandled` - The audio track to forward to the user
+/// # Errors
+/// * `MediaDownloaderError::UnsupportedDomain` - The domain is not supported
+/// * `MediaDownloaderError::DownloadError` - Error extracting the audio track
+/// * `MediaDownloaderError::FileSizeExceeded` - The audio track exceeds the size limit
+/// * `MediaDownloaderError::InvalidUrl` - The URL is invalid
+#[instrument(
+    level = "debug",
+    name = "handle_audio_request",
+    skip(supported_sites, message_url, context)
+)]
+async fn handle_audio_request(
+    message_url: &str,
+    supported_sites: &Arc<SupportedSites>,
+    format: AudioFormat,
+    context: ProcessorContext,
+) -> Result<MessageHandled, Box<dyn Error + Send>> {
+    let url_formatted = UrlFormatter::new(message_url);
+    let UrlFormatter::Valid(_, d) = &url_formatted else {
+        return Err(Box::new(MediaDownloaderError::InvalidUrl));
+    };
+    if !supported_sites.is_supported(url_formatted.get_domain_string().unwrap()) {
+        error!("`{:?}` is NOT supported!", d);
+        return Err(Box::new(MediaDownloaderError::UnsupportedDomain));
+    }
+
+    let url_id = extract_id_from_url(message_url).unwrap();
+    let audio_id = format!("{}{}", url_id, AUDIO_ID_SUFFIX);
+    let mut metadata = context
+        .redis
+        .get_video_metadata(url_id)
+        .await
+        .ok()
+        .flatten();
+    let mut extracted: Result<(), Box<dyn Error + Send>> =
+        Err(Box::new(MediaDownloaderError::DownloadError));
+    for proxy in context.http_client.proxies_for(message_url) {
+        // Not stored, the `yt_dlp` processor picks the format of the video along with it
+        if metadata.is_none() {
+            metadata = fetch_video_metadata(
+                &url_formatted,
+                proxy.clone(),
+                context.config.yt_dlp.timeout(),
+            )
+            .await
+            .map_err(|e| warn!("Extracting the audio without metadata ~ {:?}", e))
+            .ok();
+        }
+
+        extracted = download_audio(
+            &context.redis,
+            &url_formatted,
+            &audio_id,
+            format,
+            proxy.clone(),
+            context.config.yt_dlp.timeout(),
+            &context.progress,
+        )
+        .await;
+        match (&extracted, proxy) {
+            (Err(e), Some(proxy))
+                if matches!(e.downcast_ref(), Some(MediaDownloaderError::ProxyError)) =>
+            {
+                context.http_client.mark_proxy_dead(&proxy);
+            }
+            _ => break,
+        }
+    }
+    extracted?;
+    let file = retrieve_audio(&context.redis, &context.config, &audio_id, format).await?;
+
+    Ok(MessageHandled {
+        content: Some(MessageContent::Audio(AudioFile {
+            file,
+            title: metadata.as_ref().and_then(|m| m.title.clone()),
+            performer: metadata.as_ref().and_then(|m| m.uploader.clone()),
+            duration: metadata
+                .as_ref()
+                .and_then(|m| m.duration)
+                .map(|d| d.round() as u32),
+        })),
+        metadata,
+    })
+}
@@ -0,0 +1,323 @@
+//! Local stand-ins for the remote resources and Redis, serving pinned fixtures to offline tests
+
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+    task::JoinHandle,
+};
+
+use super::{http_client::HttpClient, processors::ProcessorContext};
+use crate::services::{RedisBuilder, RedisManager};
+
+const FIXTURES_DIRECTORY: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/");
+
+/// Reads a fixture checked into `tests/fixtures`
+/// # Arguments
+/// * `name` - The path of the fixture, relative to `tests/fixtures`
+pub fn fixture(name: &str) -> String {
+    std::fs::read_to_string(format!("{}{}", FIXTURES_DIRECTORY, name))
+        .unwrap_or_else(|e| panic!("Missing fixture `{}`: {}", name, e))
+}
+
+#[derive(Debug, Clone)]
+pub struct MockResponse {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+impl MockResponse {
+    pub fn html(body: String) -> Self {
+        Self::with_content_type(200, "text/html; charset=utf-8", body)
+    }
+
+    pub fn json(body: String) -> Self {
+        Self::with_content_type(200, "application/json", body)
+    }
+
+    pub fn redirect(location: &str) -> Self {
+        MockResponse {
+            status: 302,
+            headers: vec![("Location".to_string(), location.to_string())],
+            body: String::new(),
+        }
+    }
+
+    pub fn video(body: String) -> Self {
+        Self::with_content_type(200, "video/mp4", body)
+    }
+
+    pub fn status(status: u16) -> Self {
+        Self::with_content_type(status, "text/plain", String::new())
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    fn with_content_type(status: u16, content_type: &str, body: String) -> Self {
+        MockResponse {
+            status,
+            headers: vec![("Content-Type".to_string(), content_type.to_string())],
+            body,
+        }
+    }
+}
+
+/// HTTP server answering `GET` requests with the response routed by path, `404` otherwise
+pub struct MockServer {
+    address: SocketAddr,
+    requests: Arc<Mutex<Vec<(String, String)>>>,
+    handle: JoinHandle<()>,
+}
+
+impl MockServer {
+    /// Starts the server on a random local port
+    /// # Arguments
+    /// * `routes` - The responses, by path (the query string is ignored)
+    pub async fn start(routes: Vec<(&str, MockResponse)>) -> MockServer {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let routes: Arc<Vec<(String, MockResponse)>> = Arc::new(
+            routes
+                .into_iter()
+                .map(|(path, response)| (path.to_string(), response))
+                .collect(),
+        );
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let requests_clone = requests.clone();
+        let handle = tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let routes = routes.clone();
+                let requests = requests_clone.clone();
+                tokio::spawn(async move {
+                    let Some((target, head)) = read_request(&mut stream).await else {
+                        return;
+                    };
+                    requests.lock().unwrap().push((target.clone(), head));
+
+                    let path = target.split('?').next().unwrap_or_default();
+                    let response = routes
+                        .iter()
+                        .find(|(route, _)| route == path)
+                        .map(|(_, response)| response.clone())
+                        .unwrap_or_else(|| MockResponse::status(404));
+                    let _ = stream.write_all(&encode_response(&response)).await;
+                    let _ = stream.shutdown().await;
+                });
+            }
+        });
+
+        MockServer {
+            address,
+            requests,
+            handle,
+        }
+    }
+
+    /// The URL of the given path on the server
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.address, path)
+    }
+
+    /// The targets (path and query string) requested so far
+    pub fn requests(&self) -> Vec<String> {
+        self.requests
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(target, _)| target.clone())
+            .collect()
+    }
+
+    /// The value of a header of the `index`-th request
+    pub fn request_header(&self, index: usize, name: &str) -> Option<String> {
+        let requests = self.requests.lock().unwrap();
+        let (_, head) = requests.get(index)?;
+        head.lines().skip(1).find_map(|line| {
+            let (header, value) = line.split_once(':')?;
+            header
+                .eq_ignore_ascii_case(name)
+                .then(|| value.trim().to_string())
+        })
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+/// Redis server keeping the values in memory, answering the commands the downloads rely on:
+/// `GET`, `SET` and `DEL`, along with those opening a connection
+/// Other commands are answered with the replies set up for them
+pub struct MockRedis {
+    address: SocketAddr,
+    values: Arc<Mutex<HashMap<String, String>>>,
+    replies: Arc<Mutex<HashMap<String, String>>>,
+    handle: JoinHandle<()>,
+}
+
+impl MockRedis {
+    /// Starts the server on a random local port
+    pub async fn start() -> MockRedis {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let values = Arc::new(Mutex::new(HashMap::new()));
+        let replies = Arc::new(Mutex::new(HashMap::new()));
+
+        let values_clone = values.clone();
+        let replies_clone = replies.clone();
+        let handle = tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let values = values_clone.clone();
+                let replies = replies_clone.clone();
+                tokio::spawn(async move {
+                    let mut pending = Vec::new();
+                    let mut buffer = [0u8; 1024];
+                    loop {
+                        while let Some((command, length)) = parse_command(&pending) {
+                            pending.drain(..length);
+                            let reply = replies
+                                .lock()
+                                .unwrap()
+                                .get(&command[0].to_uppercase())
+                                .cloned()
+                                .unwrap_or_else(|| execute_command(&values, &command));
+                            if stream.write_all(reply.as_bytes()).await.is_err() {
+                                return;
+                            }
+                        }
+                        match stream.read(&mut buffer).await {
+                            Ok(0) | Err(_) => return,
+                            Ok(read) => pending.extend_from_slice(&buffer[..read]),
+                        }
+                    }
+                });
+            }
+        });
+
+        MockRedis {
+            address,
+            values,
+            replies,
+            handle,
+        }
+    }
+
+    /// A manager connected to the server
+    pub async fn manager(&self) -> RedisManager {
+        let mut builder = RedisBuilder::default();
+        builder
+            .host(&self.address.ip().to_string())
+            .port(self.address.port());
+        RedisManager::build(builder).await.unwrap()
+    }
+
+    /// The context of a request recorded in the server, with the default configuration
+    pub async fn context(&self) -> ProcessorContext {
+        ProcessorContext::new(HttpClient::default(), self.manager().await, Arc::default())
+    }
+
+    /// Answers every following `command` with the reply, encoded in RESP
+    pub fn reply(&self, command: &str, reply: &str) {
+        self.replies
+            .lock()
+            .unwrap()
+            .insert(command.to_uppercase(), reply.to_string());
+    }
+
+    /// The value stored under the key, if any
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.values.lock().unwrap().get(key).cloned()
+    }
+}
+
+impl Drop for MockRedis {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+/// Parses a command sent as an array of bulk strings, returning its arguments along with
+/// the length it was encoded in, `None` until it is received in full
+fn parse_command(pending: &[u8]) -> Option<(Vec<String>, usize)> {
+    fn line(pending: &[u8], from: usize) -> Option<(&str, usize)> {
+        let end = from + pending.get(from..)?.windows(2).position(|w| w == b"\r\n")?;
+        Some((std::str::from_utf8(&pending[from..end]).ok()?, end + 2))
+    }
+
+    let (header, mut position) = line(pending, 0)?;
+    let count: usize = header.strip_prefix('*')?.parse().ok()?;
+    let mut arguments = Vec::with_capacity(count);
+    for _ in 0..count {
+        let (header, start) = line(pending, position)?;
+        let length: usize = header.strip_prefix('$')?.parse().ok()?;
+        let argument = pending.get(start..start + length)?;
+        arguments.push(String::from_utf8_lossy(argument).to_string());
+        position = start + length + 2;
+    }
+    (pending.len() >= position).then_some((arguments, position))
+}
+
+fn execute_command(values: &Mutex<HashMap<String, String>>, command: &[String]) -> String {
+    let mut values = values.lock().unwrap();
+    match (command[0].to_uppercase().as_str(), &command[1..]) {
+        ("GET", [key]) => match values.get(key) {
+            Some(value) => format!("${}\r\n{}\r\n", value.len(), value),
+            None => "$-1\r\n".to_string(),
+        },
+        ("SET", [key, value, ..]) => {
+            values.insert(key.clone(), value.clone());
+            "+OK\r\n".to_string()
+        }
+        ("DEL", keys) => {
+            let deleted = keys.iter().filter(|k| values.remove(*k).is_some()).count();
+            format!(":{}\r\n", deleted)
+        }
+        ("PING", [message]) => format!("${}\r\n{}\r\n", message.len(), message),
+        ("PING", _) => "+PONG\r\n".to_string(),
+        ("AUTH" | "CLIENT" | "UNWATCH", _) => "+OK\r\n".to_string(),
+        (name, _) => format!("-ERR unknown command '{}'\r\n", name),
+    }
+}
+
+/// Reads the request head, returning the target of its request line along with the head
+async fn read_request(stream: &mut tokio::net::TcpStream) -> Option<(String, String)> {
+    let mut head = Vec::new();
+    let mut buffer = [0u8; 1024];
+
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        let read = stream.read(&mut buffer).await.ok()?;
+        if read == 0 {
+            return None;
+        }
+        head.extend_from_slice(&buffer[..read]);
+    }
+
+    let head = String::from_utf8_lossy(&head).to_string();
+    let target = head.lines().next()?.split_whitespace().nth(1)?.to_string();
+    Some((target, head))
+}
+
+fn encode_response(response: &MockResponse) -> Vec<u8> {
+    let mut encoded = format!("HTTP/1.1 {} Mock\r\n", response.status);
+    for (name, value) in &response.headers {
+        encoded.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    encoded.push_str(&format!(
+        "Content-Length: {}\r\nConnection: close\r\n\r\n",
+        response.body.len()
+    ));
+    encoded.push_str(&response.body);
+    encoded.into_bytes()
+}
@@ -1,5 +1,16 @@
+pub mod caption;
 pub mod downloader;
 pub mod errors;
 pub mod formatter;
+pub mod http_client;
+#[cfg(test)]
+pub(crate) mod mock_server;
 pub mod processors;
+pub mod progress;
+pub mod proxy_pool;
+pub mod retry_policy;
 pub mod site_validator;
+pub mod transcoder;
+pub mod user_agent;
+pub mod video_metadata;
+pub mod worker_pool;
@@ -0,0 +1,303 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    error::Error,
+    hash::{Hash, Hasher},
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use scraper::{Html, Selector};
+use serde::Deserialize;
+use tracing::{debug, instrument};
+use url::Url;
+
+use super::media_group::{download_media_group, RemoteMedia};
+use super::processor::{Processor, ProcessorConfig, ProcessorContext};
+use super::registry::{FallbackStage, ProcessorDescriptor};
+use crate::media_downloader::http_client::HttpClient;
+use crate::{
+    media_downloader::{
+        downloader::download_video_from_url, errors::MediaDownloaderError,
+        video_metadata::VideoMetadata,
+    },
+    retrieve_video,
+    services::{OversizePolicy, RedisManager},
+    MessageContent,
+};
+
+const GENERIC_ID_PREFIX: &str = "generic_";
+const VIDEO_META_SELECTORS: [&str; 4] = [
+    r#"meta[property="og:video:secure_url"]"#,
+    r#"meta[property="og:video:url"]"#,
+    r#"meta[property="og:video"]"#,
+    r#"meta[name="twitter:player:stream"]"#,
+];
+const IMAGE_META_SELECTOR: &str = r#"meta[property="og:image"]"#;
+const TITLE_META_SELECTOR: &str = r#"meta[property="og:title"]"#;
+const DESCRIPTION_META_SELECTOR: &str = r#"meta[property="og:description"]"#;
+const AUTHOR_META_SELECTOR: &str = r#"meta[name="author"]"#;
+const OEMBED_LINK_SELECTOR: &str = r#"link[rel="alternate"][type="application/json+oembed"]"#;
+const MAX_GENERIC_IMAGES: usize = 10;
+
+/// Fallback processor for sites without a dedicated one, delivering the media advertised
+/// by the page through OpenGraph/Twitter cards or its oEmbed endpoint
+#[derive(Clone, Debug)]
+pub struct GenericProcessor {
+    id: String,
+    url: String,
+    http_client: HttpClient,
+    redis: RedisManager,
+    config: Arc<ProcessorConfig>,
+    oversize: OversizePolicy,
+    metadata: Option<VideoMetadata>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct PageMedia {
+    videos: Vec<String>,
+    images: Vec<String>,
+    oembed_url: Option<String>,
+    metadata: VideoMetadata,
+}
+
+#[derive(Debug, Deserialize)]
+struct OEmbed {
+    #[serde(rename = "type")]
+    kind: String,
+    url: Option<String>,
+}
+
+impl GenericProcessor {
+    pub fn new(url: String, context: &ProcessorContext) -> GenericProcessor {
+        // The last path segment is not reliable across arbitrary sites
+        let mut hasher = DefaultHasher::new();
+        url.hash(&mut hasher);
+
+        GenericProcessor {
+            id: format!("{}{:x}", GENERIC_ID_PREFIX, hasher.finish()),
+            url,
+            http_client: context.http_client.clone(),
+            redis: context.redis.clone(),
+            config: context.config.clone(),
+            oversize: context.chat_settings.oversize,
+            metadata: None,
+        }
+    }
+
+    pub fn get_id(&self) -> String {
+        self.id.to_string()
+    }
+
+    pub fn descriptor() -> ProcessorDescriptor {
+        ProcessorDescriptor {
+            name: "generic",
+            stage: FallbackStage::Generic,
+            priority: 0,
+            claims: |_| true,
+            build: |_, url, context| Box::new(GenericProcessor::new(url.to_string(), context)),
+        }
+    }
+
+    /// Looks up the photo advertised by the oEmbed endpoint, other types are embeds only
+    #[instrument(level = "debug", name = "fetch_oembed", skip(self))]
+    async fn fetch_oembed(&self, oembed_url: &str) -> Option<String> {
+        let content = self
+            .http_client
+            .fetch(oembed_url, None, Some(&self.url), None, None, None)
+            .await
+            .ok()?;
+        let oembed: OEmbed = content.json().await.ok()?;
+        debug!("oEmbed type: {}", oembed.kind);
+
+        match oembed.kind.as_str() {
+            "photo" => oembed.url,
+            _ => None,
+        }
+    }
+}
+
+#[async_trait]
+impl Processor for GenericProcessor {
+    #[instrument(level = "debug", name = "process_generic", skip(self))]
+    async fn process(&mut self) -> Result<Option<MessageContent>, Box<dyn Error + Send>> {
+        debug!("Processing generic resource: {}", self.url);
+
+        let headers = vec![(
+            "Accept",
+            "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8",
+        )];
+        let content = self
+            .http_client
+            .fetch(&self.url, None, None, None, None, Some(headers))
+            .await
+            .map_err(|e| {
+                error!("Error fetching page: {}", e);
+                Box::new(MediaDownloaderError::UnreachableResource) as Box<dyn Error + Send>
+            })?;
+
+        if !content.status().is_success() {
+            error!(
+                "Error: Request failed with status code {:?}",
+                content.status()
+            );
+            return Err(Box::new(MediaDownloaderError::UnreachableResource));
+        }
+
+        let page_url = content.url().clone();
+        let body = content.text().await.unwrap_or_default();
+        let mut page_media = parse_page_media(&body, &page_url);
+        debug!("Page media: {:?}", page_media);
+        self.metadata = Some(page_media.metadata.clone());
+
+        if let Some(video_url) = page_media.videos.first() {
+            download_video_from_url(
+                &self.http_client,
+                &self.redis,
+                &self.url,
+                video_url,
+                &self.id,
+                None,
+                None,
+            )
+            .await?;
+            return Ok(Some(
+                retrieve_video(&self.redis, &self.config, &self.id, self.oversize).await?,
+            ));
+        }
+
+        if page_media.images.is_empty() {
+            if let Some(oembed_url) = page_media.oembed_url.take() {
+                page_media
+                    .images
+                    .extend(self.fetch_oembed(&oembed_url).await);
+            }
+        }
+
+        if page_media.images.is_empty() {
+            debug!("No media advertised by {:?}", self.url);
+            return Ok(None);
+        }
+
+        let images = page_media
+            .images
+            .into_iter()
+            .take(MAX_GENERIC_IMAGES)
+            .map(RemoteMedia::Photo)
+            .collect();
+        let group = download_media_group(
+            &self.http_client,
+            &self.redis,
+            &self.config,
+            &self.url,
+            &self.id,
+            images,
+        )
+        .await?;
+        Ok(Some(MessageContent::Images(group)))
+    }
+
+    fn metadata(&self) -> Option<VideoMetadata> {
+        self.metadata.clone()
+    }
+}
+
+/// Collects the media advertised by a page, relative URLs are resolved against it,
+/// along with its title, description and author
+/// # Arguments
+/// * `body` - The HTML of the page
+/// * `page_url` - The URL of the page, after redirects
+#[instrument(level = "debug", name = "parse_page_media", skip(body))]
+fn parse_page_media(body: &str, page_url: &Url) -> PageMedia {
+    let document = Html::parse_document(body);
+    let resolve = |value: &str| page_url.join(value.trim()).ok().map(String::from);
+
+    let select_all = |selector: &str, attribute: &str| -> Vec<String> {
+        let selector = Selector::parse(selector).unwrap();
+        document
+            .select(&selector)
+            .filter_map(|element| element.value().attr(attribute))
+            .filter_map(resolve)
+            .collect()
+    };
+
+    let mut videos = Vec::<String>::new();
+    for selector in VIDEO_META_SELECTORS {
+        for video in select_all(selector, "content") {
+            if !videos.contains(&video) {
+                videos.push(video);
+            }
+        }
+    }
+
+    let mut images = Vec::<String>::new();
+    for image in select_all(IMAGE_META_SELECTOR, "content") {
+        if !images.contains(&image) {
+            images.push(image);
+        }
+    }
+
+    let select_text = |selector: &str| -> Option<String> {
+        let selector = Selector::parse(selector).unwrap();
+        document
+            .select(&selector)
+            .filter_map(|element| element.value().attr("content"))
+            .map(|text| text.trim().to_string())
+            .find(|text| !text.is_empty())
+    };
+
+    PageMedia {
+        videos,
+        images,
+        oembed_url: select_all(OEMBED_LINK_SELECTOR, "href").into_iter().next(),
+        metadata: VideoMetadata {
+            title: select_text(TITLE_META_SELECTOR),
+            description: select_text(DESCRIPTION_META_SELECTOR),
+            uploader: select_text(AUTHOR_META_SELECTOR),
+            ..Default::default()
+        },
+    }
+}
+
+#[cfg(test)]
+mod generic_processor_test {
+    use super::*;
+    use crate::media_downloader::mock_server::MockRedis;
+
+    #[test]
+    fn test_parse_page_media() {
+        let body = r#"<html><head>
+            <meta property="og:title" content=" A clip ">
+            <meta name="author" content="Someone">
+            <meta property="og:video" content="/media/clip.mp4">
+            <meta name="twitter:player:stream" content="https://cdn.site.com/media/clip.mp4">
+            <meta property="og:image" content="https://cdn.site.com/cover.jpg">
+            <meta property="og:image" content="https://cdn.site.com/cover.jpg">
+            <link rel="alternate" type="application/json+oembed" href="/oembed?url=x">
+        </head></html>"#;
+        let page_url = Url::parse("https://cdn.site.com/post/1").unwrap();
+
+        assert_eq!(
+            parse_page_media(body, &page_url),
+            PageMedia {
+                videos: vec!["https://cdn.site.com/media/clip.mp4".to_string()],
+                images: vec!["https://cdn.site.com/cover.jpg".to_string()],
+                oembed_url: Some("https://cdn.site.com/oembed?url=x".to_string()),
+                metadata: VideoMetadata {
+                    title: Some("A clip".to_string()),
+                    uploader: Some("Someone".to_string()),
+                    ..Default::default()
+                },
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_generic_id_is_stable() {
+        let context = MockRedis::start().await.context().await;
+        let url = "https://site.com/watch?v=1".to_string();
+        assert_eq!(
+            GenericProcessor::new(url.clone(), &context).get_id(),
+            GenericProcessor::new(url, &context).get_id()
+        );
+    }
+}
@@ -0,0 +1,341 @@
+use std::{error::Error, sync::Arc};
+
+use async_trait::async_trait;
+use regex::Regex;
+use serde::Deserialize;
+use tracing::{debug, instrument};
+
+use super::media_group::{download_media_group, RemoteMedia};
+use super::processor::{url_matches_domains, Processor, ProcessorConfig, ProcessorContext};
+use super::registry::{FallbackStage, ProcessorDescriptor, DEDICATED_PROCESSOR_PRIORITY};
+use crate::media_downloader::http_client::HttpClient;
+use crate::{
+    media_downloader::{
+        downloader::download_video_from_url, errors::MediaDownloaderError,
+        video_metadata::VideoMetadata,
+    },
+    retrieve_video,
+    services::{OversizePolicy, RedisManager},
+    MessageContent, INSTAGRAM_DOMAIN,
+};
+
+const INSTAGRAM_GRAPHQL_URL: &str = "https://www.instagram.com/graphql/query/";
+const INSTAGRAM_GRAPHQL_DOC_ID: &str = "8845758582119845";
+const INSTAGRAM_APP_ID: &str = "936619743392459";
+
+#[derive(Clone, Debug)]
+pub struct InstagramProcessor {
+    id: String,
+    url: String,
+    http_client: HttpClient,
+    redis: RedisManager,
+    config: Arc<ProcessorConfig>,
+    oversize: OversizePolicy,
+    metadata: Option<VideoMetadata>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphqlResponse {
+    data: Option<GraphqlData>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphqlData {
+    #[serde(alias = "shortcode_media")]
+    xdt_shortcode_media: Option<ShortcodeMedia>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ShortcodeMedia {
+    #[serde(flatten)]
+    node: MediaNode,
+    edge_sidecar_to_children: Option<SidecarChildren>,
+    owner: Option<Owner>,
+    edge_media_to_caption: Option<CaptionEdges>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Owner {
+    username: Option<String>,
+    full_name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CaptionEdges {
+    edges: Vec<CaptionEdge>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CaptionEdge {
+    node: CaptionNode,
+}
+
+#[derive(Debug, Deserialize)]
+struct CaptionNode {
+    text: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct SidecarChildren {
+    edges: Vec<SidecarEdge>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SidecarEdge {
+    node: MediaNode,
+}
+
+#[derive(Debug, Deserialize)]
+struct MediaNode {
+    is_video: bool,
+    video_url: Option<String>,
+    display_url: String,
+}
+
+impl MediaNode {
+    fn into_media(self) -> RemoteMedia {
+        match (self.is_video, self.video_url) {
+            (true, Some(video_url)) => RemoteMedia::Video(video_url),
+            _ => RemoteMedia::Photo(self.display_url),
+        }
+    }
+}
+
+impl InstagramProcessor {
+    pub fn new(id: String, url: String, context: &ProcessorContext) -> InstagramProcessor {
+        InstagramProcessor {
+            id,
+            url,
+            http_client: context.http_client.clone(),
+            redis: context.redis.clone(),
+            config: context.config.clone(),
+            oversize: context.chat_settings.oversize,
+            metadata: None,
+        }
+    }
+
+    pub fn get_id(&self) -> String {
+        self.id.to_string()
+    }
+
+    pub fn descriptor() -> ProcessorDescriptor {
+        ProcessorDescriptor {
+            name: "instagram",
+            stage: FallbackStage::Dedicated,
+            priority: DEDICATED_PROCESSOR_PRIORITY,
+            claims: |url| url_matches_domains(url, &[INSTAGRAM_DOMAIN]),
+            build: |url_id, url, context| {
+                Box::new(InstagramProcessor::new(
+                    url_id.to_string(),
+                    url.to_string(),
+                    context,
+                ))
+            },
+        }
+    }
+}
+
+#[async_trait]
+impl Processor for InstagramProcessor {
+    #[instrument(level = "debug", name = "process_instagram", skip(self))]
+    async fn process(&mut self) -> Result<Option<MessageContent>, Box<dyn Error + Send>> {
+        debug!("Processing Instagram: {}", self.url);
+
+        match extract_instagram_shortcode(&self.url) {
+            Some(shortcode) => self.id = shortcode.to_string(),
+            None => {
+                error!("Failed to extract shortcode from {:?}", self.url);
+                return Err(Box::new(MediaDownloaderError::CouldNotExtractId));
+            }
+        }
+
+        let variables = format!(r#"{{"shortcode":"{}"}}"#, self.id);
+        let query = vec![
+            ("doc_id", INSTAGRAM_GRAPHQL_DOC_ID.to_string()),
+            ("variables", variables),
+        ];
+        let headers = vec![
+            ("X-IG-App-ID", INSTAGRAM_APP_ID),
+            ("X-Requested-With", "XMLHttpRequest"),
+            ("Accept", "*/*"),
+        ];
+
+        let content = self
+            .http_client
+            .fetch(
+                &self
+                    .http_client
+                    .base_url("instagram_graphql", INSTAGRAM_GRAPHQL_URL),
+                Some(query),
+                Some(&self.url),
+                None,
+                None,
+                Some(headers),
+            )
+            .await
+            .map_err(|e| {
+                error!("Error fetching Instagram resource: {}", e);
+                Box::new(MediaDownloaderError::UnreachableResource) as Box<dyn Error + Send>
+            })?;
+
+        if !content.status().is_success() {
+            error!(
+                "Error: Request failed with status code {:?}",
+                content.status()
+            );
+            return Err(Box::new(MediaDownloaderError::UnreachableResource));
+        }
+
+        let body = content.text().await.unwrap_or_default();
+        let (media, metadata) = parse_instagram_media(&body)?;
+        self.metadata = Some(metadata);
+
+        if let [RemoteMedia::Video(video_url)] = media.as_slice() {
+            debug!("Instagram resource {:?} is a reel!", self.id);
+            download_video_from_url(
+                &self.http_client,
+                &self.redis,
+                &self.url,
+                video_url,
+                &self.id,
+                None,
+                None,
+            )
+            .await?;
+            return Ok(Some(
+                retrieve_video(&self.redis, &self.config, &self.id, self.oversize).await?,
+            ));
+        }
+
+        debug!(
+            "Instagram resource {:?} is a post with {} items!",
+            self.id,
+            media.len()
+        );
+        let group = download_media_group(
+            &self.http_client,
+            &self.redis,
+            &self.config,
+            &self.url,
+            &self.id,
+            media,
+        )
+        .await?;
+        Ok(Some(MessageContent::Images(group)))
+    }
+
+    fn metadata(&self) -> Option<VideoMetadata> {
+        self.metadata.clone()
+    }
+}
+
+/// Extracts the shortcode of a post or reel from its URL
+#[instrument(level = "debug", name = "extract_instagram_shortcode")]
+fn extract_instagram_shortcode(url: &str) -> Option<&str> {
+    let re = Regex::new(r"instagram\.com/(?:[\w.]+/)?(?:p|reels?|tv)/([\w-]+)").unwrap();
+    re.captures(url)
+        .and_then(|captures| captures.get(1))
+        .map(|m| m.as_str())
+}
+
+/// Parses the GraphQL response of a post into its media, in order, and its caption and author
+/// Carousels (sidecars) list their children, any other post is a single photo or video
+#[instrument(level = "debug", name = "parse_instagram_media", skip_all)]
+fn parse_instagram_media(
+    body: &str,
+) -> Result<(Vec<RemoteMedia>, VideoMetadata), Box<dyn Error + Send>> {
+    let response: GraphqlResponse = serde_json::from_str(body).map_err(|e| {
+        error!("Error parsing Instagram response: {}", e);
+        Box::new(MediaDownloaderError::ParsingError) as Box<dyn Error + Send>
+    })?;
+
+    let Some(shortcode_media) = response.data.and_then(|d| d.xdt_shortcode_media) else {
+        error!("Instagram response has no media, the post might be private");
+        return Err(Box::new(MediaDownloaderError::UnreachableResource));
+    };
+
+    let metadata = VideoMetadata {
+        description: shortcode_media
+            .edge_media_to_caption
+            .and_then(|caption| caption.edges.into_iter().next())
+            .map(|edge| edge.node.text),
+        uploader: shortcode_media
+            .owner
+            .as_ref()
+            .and_then(|o| o.full_name.clone())
+            .filter(|name| !name.is_empty()),
+        uploader_id: shortcode_media
+            .owner
+            .and_then(|o| o.username)
+            .map(|username| format!("@{}", username)),
+        ..Default::default()
+    };
+    let media = match shortcode_media.edge_sidecar_to_children {
+        Some(children) if !children.edges.is_empty() => children
+            .edges
+            .into_iter()
+            .map(|edge| edge.node.into_media())
+            .collect(),
+        _ => vec![shortcode_media.node.into_media()],
+    };
+    debug!("Found {:?} media", media.len());
+    Ok((media, metadata))
+}
+
+#[cfg(test)]
+mod instagram_processor_test {
+    use super::*;
+
+    #[test]
+    fn test_extract_shortcode() {
+        let urls = [
+            "https://www.instagram.com/reel/C5abc-_12/?igsh=MWQ1ZGUxMzBkMA==",
+            "https://www.instagram.com/p/C5abc-_12/",
+            "https://instagram.com/someone/p/C5abc-_12",
+            "https://www.instagram.com/reels/C5abc-_12/",
+        ];
+
+        for url in urls {
+            assert_eq!(extract_instagram_shortcode(url), Some("C5abc-_12"));
+        }
+        assert_eq!(
+            extract_instagram_shortcode("https://www.instagram.com/someone/"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_parse_carousel() {
+        let body = r#"{"data":{"xdt_shortcode_media":{
+            "__typename":"XDTGraphSidecar","is_video":false,"display_url":"https://cdn/cover.jpg",
+            "edge_sidecar_to_children":{"edges":[
+                {"node":{"is_video":false,"display_url":"https://cdn/1.jpg"}},
+                {"node":{"is_video":true,"display_url":"https://cdn/2.jpg","video_url":"https://cdn/2.mp4"}}
+            ]}}}}"#;
+
+        assert_eq!(
+            parse_instagram_media(body).unwrap().0,
+            vec![
+                RemoteMedia::Photo("https://cdn/1.jpg".to_string()),
+                RemoteMedia::Video("https://cdn/2.mp4".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_reel() {
+        let body = r#"{"data":{"xdt_shortcode_media":{
+            "__typename":"XDTGraphVideo","is_video":true,"display_url":"https://cdn/cover.jpg",
+            "video_url":"https://cdn/reel.mp4","edge_sidecar_to_children":null,
+            "owner":{"username":"someone","full_name":"Some One"},
+            "edge_media_to_caption":{"edges":[{"node":{"text":"Reel caption"}}]}}}}"#;
+
+        let (media, metadata) = parse_instagram_media(body).unwrap();
+        assert_eq!(
+            media,
+            vec![RemoteMedia::Video("https://cdn/reel.mp4".to_string())]
+        );
+        assert_eq!(metadata.author().as_deref(), Some("@someone"));
+        assert_eq!(metadata.description.as_deref(), Some("Reel caption"));
+    }
+}
@@ -0,0 +1,105 @@
+use std::{collections::HashMap, error::Error};
+
+use frankenstein::{InputMediaVideo, Media};
+use tracing::{debug, instrument};
+
+use super::processor::ProcessorConfig;
+use crate::media_downloader::http_client::HttpClient;
+use crate::{
+    download_images_from_map,
+    media_downloader::{downloader::download_video_from_url, errors::MediaDownloaderError},
+    retrieve_blob, retrieve_image,
+    services::RedisManager,
+};
+
+/// A remote photo or video, referenced by its direct URL
+#[derive(Debug, Clone, PartialEq)]
+pub enum RemoteMedia {
+    Photo(String),
+    Video(String),
+}
+
+/// Downloads mixed media, photos and videos are stored as `<id>_<index>`
+/// Items that cannot be downloaded are skipped
+/// # Arguments
+/// * `http_client` - The client to download with
+/// * `redis` - Where the downloads are recorded
+/// * `config` - The configuration the media is retrieved with
+/// * `source_url` - The URL of the page hosting the media, used as referer
+/// * `id` - The ID of the resource
+/// * `media` - The media to download, in order
+/// # Returns
+/// * `Vec<Media>` - The media group to forward to the user
+/// # Errors
+/// * `MediaDownloaderError::ImagesNotDownloaded` - None of the items could be downloaded
+#[instrument(
+    level = "debug",
+    name = "download_media_group",
+    skip(http_client, redis, config, media)
+)]
+pub async fn download_media_group(
+    http_client: &HttpClient,
+    redis: &RedisManager,
+    config: &ProcessorConfig,
+    source_url: &str,
+    id: &str,
+    media: Vec<RemoteMedia>,
+) -> Result<Vec<Media>, Box<dyn Error + Send>> {
+    let photos: HashMap<i32, String> = media
+        .iter()
+        .enumerate()
+        .filter_map(|(i, m)| match m {
+            RemoteMedia::Photo(url) => Some((i as i32, url.to_string())),
+            RemoteMedia::Video(_) => None,
+        })
+        .collect();
+    download_images_from_map(http_client, redis, photos, id.to_string()).await?;
+
+    let mut group = Vec::<Media>::new();
+    for (i, m) in media.iter().enumerate() {
+        match m {
+            RemoteMedia::Photo(_) => match retrieve_image(redis, config, id, i as i32).await {
+                Ok(photo) => group.push(photo),
+                Err(e) => error!("Error retrieving photo #{}: {:?}", i, e),
+            },
+            RemoteMedia::Video(video_url) => {
+                let item_id = format!("{}_{}", id, i);
+                if let Err(e) = download_video_from_url(
+                    http_client,
+                    redis,
+                    source_url,
+                    video_url,
+                    &item_id,
+                    None,
+                    None,
+                )
+                .await
+                {
+                    error!("Error downloading video #{}: {:?}", i, e);
+                    continue;
+                }
+                match retrieve_blob(redis, config, &item_id).await {
+                    Ok(video) => group.push(Media::Video(InputMediaVideo {
+                        media: video,
+                        thumbnail: None,
+                        caption: None,
+                        parse_mode: None,
+                        caption_entities: None,
+                        width: None,
+                        height: None,
+                        duration: None,
+                        supports_streaming: Some(true),
+                        has_spoiler: None,
+                    })),
+                    Err(e) => error!("Error retrieving video #{}: {:?}", i, e),
+                }
+            }
+        }
+    }
+
+    if group.is_empty() {
+        return Err(Box::new(MediaDownloaderError::ImagesNotDownloaded));
+    }
+    debug!("Media group of {} items ready", group.len());
+    Ok(group)
+}
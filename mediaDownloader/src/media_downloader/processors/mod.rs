@@ -1,4 +1,17 @@
+mod generic;
+mod instagram;
+mod media_group;
 mod processor;
+mod reddit;
+mod registry;
 mod tiktok;
-pub use processor::{route_to_processor, Processor, ProcessorType};
+mod twitter;
+mod yt_dlp;
+pub use generic::GenericProcessor;
+pub use instagram::InstagramProcessor;
+pub use processor::{Processor, ProcessorConfig, ProcessorContext};
+pub use reddit::RedditProcessor;
+pub use registry::{FallbackStage, ProcessorDescriptor, ProcessorRegistry, ProcessorsConfig};
 pub use tiktok::{AwemeConfig, AwemeHeaders, AwemeParams, TikTokProcessor};
+pub use twitter::TwitterProcessor;
+pub use yt_dlp::{YtDlpConfig, YtDlpProcessor};
@@ -1,31 +1,101 @@
-use std::error::Error;
+use std::{error::Error, path::PathBuf, sync::Arc};
 
-use super::TikTokProcessor;
-use crate::{MessageContent, TIKTOK_GENERAL_DOMAIN, TIKTOK_MOBILE_DOMAIN};
+use super::{tiktok::AwemeConfig, yt_dlp::YtDlpConfig};
+use crate::{
+    media_downloader::{
+        http_client::HttpClient, progress::ProgressReporter, transcoder::TranscodingConfig,
+        video_metadata::VideoMetadata,
+    },
+    services::{ChatSettings, RedisManager},
+    Config, FileSizeLimits, MessageContent,
+};
 use async_trait::async_trait;
-use tracing::instrument;
+use frankenstein::{FileUpload, InputFile};
+use url::Url;
 
-#[derive(Debug)]
-pub enum ProcessorType {
-    TikTok(TikTokProcessor),
+/// State of the request the processors are built for
+#[derive(Debug, Clone)]
+pub struct ProcessorContext {
+    pub chat_settings: ChatSettings,
+    /// The client the processors send their requests with
+    pub http_client: HttpClient,
+    /// Where to report the progress of the download
+    pub progress: ProgressReporter,
+    /// Where the downloads are recorded, so that they are not repeated
+    pub redis: RedisManager,
+    pub config: Arc<ProcessorConfig>,
+}
+
+impl ProcessorContext {
+    /// The context of a request without chat settings nor progress to report
+    pub fn new(
+        http_client: HttpClient,
+        redis: RedisManager,
+        config: Arc<ProcessorConfig>,
+    ) -> ProcessorContext {
+        ProcessorContext {
+            chat_settings: ChatSettings::default(),
+            http_client,
+            progress: ProgressReporter::default(),
+            redis,
+            config,
+        }
+    }
+}
+
+/// The parts of the configuration the processors download with
+#[derive(Debug, Clone, Default)]
+pub struct ProcessorConfig {
+    pub file_size_limits: FileSizeLimits,
+    /// Whether files are uploaded by path, see `TelegramConfig::uploads_by_path`
+    pub uploads_by_path: bool,
+    pub aweme: Option<AwemeConfig>,
+    pub yt_dlp: YtDlpConfig,
+    pub transcoding: TranscodingConfig,
+}
+
+impl ProcessorConfig {
+    pub fn from_config(config: &Config) -> ProcessorConfig {
+        ProcessorConfig {
+            file_size_limits: config.telegram.file_size_limits(),
+            uploads_by_path: config.telegram.uploads_by_path(),
+            aweme: config.aweme_api.clone(),
+            yt_dlp: config.yt_dlp.clone().unwrap_or_default(),
+            transcoding: config.transcoding.clone().unwrap_or_default(),
+        }
+    }
+
+    /// The upload of a local file, by path when the self-hosted Bot API server shares
+    /// the filesystem
+    /// # Arguments
+    /// * `path` - The absolute path of the file
+    pub fn file_upload(&self, path: PathBuf) -> FileUpload {
+        if self.uploads_by_path {
+            return FileUpload::String(format!("file://{}", path.display()));
+        }
+        FileUpload::InputFile(InputFile { path })
+    }
 }
 
 #[async_trait]
 pub trait Processor {
     async fn process(&mut self) -> Result<Option<MessageContent>, Box<dyn Error + Send>>;
-}
 
-#[instrument(level = "debug", name = "route_to_processor")]
-pub fn route_to_processor(url: &str, url_id: &str) -> Option<ProcessorType> {
-    if url.contains(TIKTOK_GENERAL_DOMAIN) {
-        debug!("Routing to TikTok processor");
-        let mut tiktok_processor = TikTokProcessor::new(url_id.to_string(), url.to_string());
-        if url.contains(TIKTOK_MOBILE_DOMAIN) {
-            tiktok_processor.set_mobile_experience(true);
-        } else {
-            tiktok_processor.set_mobile_experience(false);
-        }
-        return Some(ProcessorType::TikTok(tiktok_processor));
+    /// What is known about the processed media, to caption it with
+    fn metadata(&self) -> Option<VideoMetadata> {
+        None
     }
-    None
+}
+
+/// Whether the host of the URL is one of the given domains or one of their subdomains
+pub(super) fn url_matches_domains(url: &str, domains: &[&str]) -> bool {
+    let Some(host) = Url::parse(url)
+        .ok()
+        .and_then(|u| u.host_str().map(str::to_string))
+    else {
+        return false;
+    };
+    domains
+        .iter()
+        .any(|domain| host == *domain || host.ends_with(&format!(".{}", domain)))
 }
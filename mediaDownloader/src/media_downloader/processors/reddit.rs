@@ -0,0 +1,539 @@
+use std::{collections::HashMap, error::Error, sync::Arc};
+
+use async_trait::async_trait;
+use regex::Regex;
+use serde::Deserialize;
+use tracing::{debug, instrument};
+use url::Url;
+
+use super::media_group::{download_media_group, RemoteMedia};
+use super::processor::{url_matches_domains, Processor, ProcessorConfig, ProcessorContext};
+use super::registry::{FallbackStage, ProcessorDescriptor, DEDICATED_PROCESSOR_PRIORITY};
+use crate::media_downloader::http_client::HttpClient;
+use crate::{
+    media_downloader::{
+        downloader::{
+            download_file, forget_download, mux_video_audio, was_video_already_downloaded,
+        },
+        errors::MediaDownloaderError,
+        video_metadata::VideoMetadata,
+    },
+    retrieve_video,
+    services::{OversizePolicy, RedisManager},
+    MessageContent, REDDIT_DOMAINS, SERVICE_NAME, TARGET_DIRECTORY, VIDEO_EXTENSIONS_FORMAT,
+};
+
+const REDDIT_URL: &str = "https://www.reddit.com";
+const REDDIT_COMMENTS_URL: &str = "https://www.reddit.com/comments/";
+const REDDIT_SHORT_DOMAIN: &str = "redd.it";
+const REDDIT_IMAGES_DOMAIN: &str = "i.redd.it";
+const REDDIT_AUDIO_TRACKS: [&str; 4] = [
+    "DASH_AUDIO_128.mp4",
+    "DASH_AUDIO_64.mp4",
+    "DASH_audio.mp4",
+    "audio",
+];
+
+#[derive(Clone, Debug)]
+pub struct RedditProcessor {
+    id: String,
+    url: String,
+    http_client: HttpClient,
+    redis: RedisManager,
+    config: Arc<ProcessorConfig>,
+    oversize: OversizePolicy,
+    metadata: Option<VideoMetadata>,
+}
+
+#[derive(Debug, PartialEq)]
+enum RedditPost {
+    Video {
+        video_url: String,
+        audio_urls: Vec<String>,
+    },
+    Gallery(Vec<RemoteMedia>),
+}
+
+#[derive(Debug, Deserialize)]
+struct Listing {
+    data: ListingData,
+}
+
+#[derive(Debug, Deserialize)]
+struct ListingData {
+    children: Vec<ListingChild>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ListingChild {
+    data: Post,
+}
+
+#[derive(Debug, Deserialize)]
+struct Post {
+    title: Option<String>,
+    author: Option<String>,
+    permalink: Option<String>,
+    gallery_data: Option<GalleryData>,
+    media_metadata: Option<HashMap<String, MediaMetadata>>,
+    secure_media: Option<SecureMedia>,
+    url_overridden_by_dest: Option<String>,
+    post_hint: Option<String>,
+    crosspost_parent_list: Option<Vec<Post>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GalleryData {
+    items: Vec<GalleryItem>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GalleryItem {
+    media_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct MediaMetadata {
+    e: Option<String>,
+    s: Option<MediaSource>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MediaSource {
+    u: Option<String>,
+    mp4: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SecureMedia {
+    reddit_video: Option<RedditVideo>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RedditVideo {
+    fallback_url: String,
+    has_audio: Option<bool>,
+    width: Option<u32>,
+    height: Option<u32>,
+    /// In seconds
+    duration: Option<f64>,
+}
+
+impl RedditProcessor {
+    pub fn new(id: String, url: String, context: &ProcessorContext) -> RedditProcessor {
+        RedditProcessor {
+            id,
+            url,
+            http_client: context.http_client.clone(),
+            redis: context.redis.clone(),
+            config: context.config.clone(),
+            oversize: context.chat_settings.oversize,
+            metadata: None,
+        }
+    }
+
+    pub fn get_id(&self) -> String {
+        self.id.to_string()
+    }
+
+    pub fn descriptor() -> ProcessorDescriptor {
+        ProcessorDescriptor {
+            name: "reddit",
+            stage: FallbackStage::Dedicated,
+            priority: DEDICATED_PROCESSOR_PRIORITY,
+            claims: |url| url_matches_domains(url, &REDDIT_DOMAINS),
+            build: |url_id, url, context| {
+                Box::new(RedditProcessor::new(
+                    url_id.to_string(),
+                    url.to_string(),
+                    context,
+                ))
+            },
+        }
+    }
+
+    /// Resolves the ID of the post, following share links (`/s/`, `v.redd.it`) when needed
+    #[instrument(level = "debug", name = "resolve_reddit_post_id", skip(self))]
+    async fn resolve_post_id(&self) -> Result<String, Box<dyn Error + Send>> {
+        if let Some(id) = extract_reddit_post_id(&self.url) {
+            return Ok(id);
+        }
+
+        debug!("Following share link {:?}", self.url);
+        let content = self
+            .http_client
+            .fetch(&self.url, None, None, None, Some(reddit_user_agent()), None)
+            .await
+            .map_err(|e| {
+                error!("Error resolving Reddit link: {}", e);
+                Box::new(MediaDownloaderError::UnreachableResource) as Box<dyn Error + Send>
+            })?;
+
+        extract_reddit_post_id(content.url().as_str()).ok_or_else(|| {
+            error!("Failed to extract post ID from {:?}", content.url());
+            Box::new(MediaDownloaderError::CouldNotExtractId) as Box<dyn Error + Send>
+        })
+    }
+
+    /// Downloads the DASH tracks of a video and merges them into the final mp4
+    /// A failed download is forgotten, along with the tracks and what was merged of them
+    #[instrument(level = "debug", name = "download_reddit_video", skip_all)]
+    async fn download_video(
+        &self,
+        video_url: &str,
+        audio_urls: &[String],
+    ) -> Result<(), Box<dyn Error + Send>> {
+        if was_video_already_downloaded(&self.redis, &self.id).await {
+            debug!("Video already downloaded!");
+            return Ok(());
+        }
+
+        let downloaded = self.download_tracks(video_url, audio_urls).await;
+        let _ = tokio::fs::remove_file(self.track_path("video")).await;
+        let _ = tokio::fs::remove_file(self.track_path("audio")).await;
+        if downloaded.is_err() {
+            forget_download(&self.redis, &self.id).await;
+        }
+        downloaded
+    }
+
+    /// e.g. `<id>_video.mp4`
+    fn track_path(&self, track: &str) -> String {
+        format!(
+            "{}{}_{}.{}",
+            TARGET_DIRECTORY, self.id, track, VIDEO_EXTENSIONS_FORMAT
+        )
+    }
+
+    async fn download_tracks(
+        &self,
+        video_url: &str,
+        audio_urls: &[String],
+    ) -> Result<(), Box<dyn Error + Send>> {
+        let output_path = format!(
+            "{}{}.{}",
+            TARGET_DIRECTORY, self.id, VIDEO_EXTENSIONS_FORMAT
+        );
+        let video_path = self.track_path("video");
+        let audio_path = self.track_path("audio");
+
+        download_file(
+            &self.http_client,
+            video_url,
+            &video_path,
+            Some(reddit_user_agent()),
+        )
+        .await?;
+
+        let mut audio_downloaded = false;
+        for audio_url in audio_urls {
+            if download_file(
+                &self.http_client,
+                audio_url,
+                &audio_path,
+                Some(reddit_user_agent()),
+            )
+            .await
+            .is_ok()
+            {
+                audio_downloaded = true;
+                break;
+            }
+        }
+
+        if audio_downloaded {
+            debug!("Muxing video and audio tracks");
+            mux_video_audio(
+                &video_path,
+                &audio_path,
+                &output_path,
+                self.config.transcoding.timeout(),
+            )
+            .await
+        } else {
+            debug!("No audio track, delivering the video track as it is");
+            tokio::fs::rename(&video_path, &output_path)
+                .await
+                .map_err(|e| {
+                    Box::new(MediaDownloaderError::IoErrorDirectory(e)) as Box<dyn Error + Send>
+                })
+        }
+    }
+}
+
+#[async_trait]
+impl Processor for RedditProcessor {
+    #[instrument(level = "debug", name = "process_reddit", skip(self))]
+    async fn process(&mut self) -> Result<Option<MessageContent>, Box<dyn Error + Send>> {
+        debug!("Processing Reddit: {}", self.url);
+
+        // Direct links to images have no post to look up
+        if Url::parse(&self.url).is_ok_and(|u| u.host_str() == Some(REDDIT_IMAGES_DOMAIN)) {
+            self.id = self.id.split('.').next().unwrap_or_default().to_string();
+            let image = vec![RemoteMedia::Photo(self.url.clone())];
+            let group = download_media_group(
+                &self.http_client,
+                &self.redis,
+                &self.config,
+                &self.url,
+                &self.id,
+                image,
+            )
+            .await?;
+            return Ok(Some(MessageContent::Images(group)));
+        }
+
+        self.id = self.resolve_post_id().await?;
+
+        let content = self
+            .http_client
+            .fetch(
+                &format!(
+                    "{}{}.json",
+                    self.http_client
+                        .base_url("reddit_comments", REDDIT_COMMENTS_URL),
+                    self.id
+                ),
+                None,
+                None,
+                None,
+                Some(reddit_user_agent()),
+                Some(vec![("Accept", "application/json")]),
+            )
+            .await
+            .map_err(|e| {
+                error!("Error fetching Reddit post: {}", e);
+                Box::new(MediaDownloaderError::UnreachableResource) as Box<dyn Error + Send>
+            })?;
+
+        if !content.status().is_success() {
+            error!(
+                "Error: Request failed with status code {:?}",
+                content.status()
+            );
+            return Err(Box::new(MediaDownloaderError::UnreachableResource));
+        }
+
+        let body = content.text().await.unwrap_or_default();
+        let (post, metadata) = parse_reddit_post(&body)?;
+        self.metadata = Some(metadata);
+        match post {
+            RedditPost::Video {
+                video_url,
+                audio_urls,
+            } => {
+                self.download_video(&video_url, &audio_urls).await?;
+                Ok(Some(
+                    retrieve_video(&self.redis, &self.config, &self.id, self.oversize).await?,
+                ))
+            }
+            RedditPost::Gallery(media) => {
+                debug!("Reddit post {:?} has {} media", self.id, media.len());
+                let group = download_media_group(
+                    &self.http_client,
+                    &self.redis,
+                    &self.config,
+                    &self.url,
+                    &self.id,
+                    media,
+                )
+                .await?;
+                Ok(Some(MessageContent::Images(group)))
+            }
+        }
+    }
+
+    fn metadata(&self) -> Option<VideoMetadata> {
+        self.metadata.clone()
+    }
+}
+
+/// Reddit rejects generic user agents
+fn reddit_user_agent() -> String {
+    format!("{}/{}", SERVICE_NAME, env!("CARGO_PKG_VERSION"))
+}
+
+/// Extracts the ID of a post from its URL, short links (`/s/`) have to be followed first
+#[instrument(level = "debug", name = "extract_reddit_post_id")]
+fn extract_reddit_post_id(url: &str) -> Option<String> {
+    let re = Regex::new(r"/comments/([a-z0-9]+)").unwrap();
+    if let Some(captures) = re.captures(url) {
+        return captures.get(1).map(|m| m.as_str().to_string());
+    }
+
+    let parsed_url = Url::parse(url).ok()?;
+    if parsed_url.host_str() == Some(REDDIT_SHORT_DOMAIN) {
+        return parsed_url
+            .path_segments()?
+            .next()
+            .filter(|id| !id.is_empty())
+            .map(str::to_string);
+    }
+    None
+}
+
+/// Parses the media of a post along with its title and author,
+/// crossposts are resolved to their original post
+#[instrument(level = "debug", name = "parse_reddit_post", skip_all)]
+fn parse_reddit_post(body: &str) -> Result<(RedditPost, VideoMetadata), Box<dyn Error + Send>> {
+    let listings: Vec<Listing> = serde_json::from_str(body).map_err(|e| {
+        error!("Error parsing Reddit post: {}", e);
+        Box::new(MediaDownloaderError::ParsingError) as Box<dyn Error + Send>
+    })?;
+
+    let mut post = listings
+        .into_iter()
+        .next()
+        .and_then(|l| l.data.children.into_iter().next())
+        .map(|c| c.data)
+        .ok_or_else(|| Box::new(MediaDownloaderError::ParsingError) as Box<dyn Error + Send>)?;
+
+    if let Some(original) = post
+        .crosspost_parent_list
+        .take()
+        .and_then(|p| p.into_iter().next())
+    {
+        debug!("Resolving crosspost to its original post");
+        post = original;
+    }
+
+    let mut metadata = VideoMetadata {
+        title: post.title.clone(),
+        uploader_id: post.author.as_ref().map(|author| format!("u/{}", author)),
+        webpage_url: post
+            .permalink
+            .as_ref()
+            .map(|permalink| format!("{}{}", REDDIT_URL, permalink)),
+        ..Default::default()
+    };
+
+    if let Some(video) = post.secure_media.and_then(|m| m.reddit_video) {
+        metadata.width = video.width;
+        metadata.height = video.height;
+        metadata.duration = video.duration;
+        let video_url = unescape_url(&video.fallback_url);
+        let audio_urls = match (video.has_audio, video_url.rsplit_once('/')) {
+            (Some(false), _) | (_, None) => Vec::new(),
+            (_, Some((base_url, _))) => REDDIT_AUDIO_TRACKS
+                .iter()
+                .map(|track| format!("{}/{}", base_url, track))
+                .collect(),
+        };
+        return Ok((
+            RedditPost::Video {
+                video_url,
+                audio_urls,
+            },
+            metadata,
+        ));
+    }
+
+    if let (Some(gallery), Some(media_metadata)) = (post.gallery_data, post.media_metadata) {
+        let media: Vec<RemoteMedia> = gallery
+            .items
+            .iter()
+            .filter_map(|item| media_metadata.get(&item.media_id))
+            .filter_map(|m| {
+                let source = m.s.as_ref()?;
+                match m.e.as_deref() {
+                    Some("AnimatedImage") => source
+                        .mp4
+                        .as_deref()
+                        .map(|u| RemoteMedia::Video(unescape_url(u))),
+                    _ => source
+                        .u
+                        .as_deref()
+                        .map(|u| RemoteMedia::Photo(unescape_url(u))),
+                }
+            })
+            .collect();
+        if !media.is_empty() {
+            return Ok((RedditPost::Gallery(media), metadata));
+        }
+    }
+
+    match (post.post_hint.as_deref(), post.url_overridden_by_dest) {
+        (Some("image"), Some(image_url)) => Ok((
+            RedditPost::Gallery(vec![RemoteMedia::Photo(unescape_url(&image_url))]),
+            metadata,
+        )),
+        _ => {
+            error!("Reddit post has no supported media");
+            Err(Box::new(MediaDownloaderError::ParsingError))
+        }
+    }
+}
+
+/// URLs in the Reddit API are HTML-escaped
+fn unescape_url(url: &str) -> String {
+    url.replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod reddit_processor_test {
+    use super::*;
+
+    #[test]
+    fn test_extract_post_id() {
+        assert_eq!(
+            extract_reddit_post_id("https://www.reddit.com/r/rust/comments/1c2x3y4/some_title/"),
+            Some("1c2x3y4".to_string())
+        );
+        assert_eq!(
+            extract_reddit_post_id("https://redd.it/1c2x3y4"),
+            Some("1c2x3y4".to_string())
+        );
+        assert_eq!(
+            extract_reddit_post_id("https://www.reddit.com/r/rust/s/AbCdEf123"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_parse_video_with_audio() {
+        let body = r#"[{"data":{"children":[{"data":{"title":"A clip","author":"someone",
+            "permalink":"/r/rust/comments/abc123/a_clip/","secure_media":{"reddit_video":{
+            "fallback_url":"https://v.redd.it/abc123/DASH_720.mp4?source=fallback","has_audio":true,
+            "width":1280,"height":720,"duration":42}}}}]}}]"#;
+
+        let (
+            RedditPost::Video {
+                video_url,
+                audio_urls,
+            },
+            metadata,
+        ) = parse_reddit_post(body).unwrap()
+        else {
+            panic!("Expected a video");
+        };
+        assert_eq!(
+            video_url,
+            "https://v.redd.it/abc123/DASH_720.mp4?source=fallback"
+        );
+        assert_eq!(audio_urls[0], "https://v.redd.it/abc123/DASH_AUDIO_128.mp4");
+        assert_eq!(metadata.author().as_deref(), Some("u/someone"));
+        assert_eq!(metadata.resolution().as_deref(), Some("1280x720"));
+        assert_eq!(
+            metadata.webpage_url.as_deref(),
+            Some("https://www.reddit.com/r/rust/comments/abc123/a_clip/")
+        );
+    }
+
+    #[test]
+    fn test_parse_gallery() {
+        let body = r#"[{"data":{"children":[{"data":{
+            "gallery_data":{"items":[{"media_id":"b"},{"media_id":"a"}]},
+            "media_metadata":{
+                "a":{"e":"Image","s":{"u":"https://preview.redd.it/a.jpg?width=1&amp;s=x"}},
+                "b":{"e":"AnimatedImage","s":{"mp4":"https://preview.redd.it/b.gif?format=mp4&amp;s=y"}}
+            }}}]}}]"#;
+
+        assert_eq!(
+            parse_reddit_post(body).unwrap().0,
+            RedditPost::Gallery(vec![
+                RemoteMedia::Video("https://preview.redd.it/b.gif?format=mp4&s=y".to_string()),
+                RemoteMedia::Photo("https://preview.redd.it/a.jpg?width=1&s=x".to_string()),
+            ])
+        );
+    }
+}
@@ -0,0 +1,191 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+use tracing::instrument;
+
+use super::processor::{Processor, ProcessorContext};
+use super::{
+    GenericProcessor, InstagramProcessor, RedditProcessor, TikTokProcessor, TwitterProcessor,
+    YtDlpProcessor,
+};
+
+pub const DEDICATED_PROCESSOR_PRIORITY: i32 = 10;
+const DEFAULT_FALLBACK_CHAIN: [FallbackStage; 3] = [
+    FallbackStage::Dedicated,
+    FallbackStage::YtDlp,
+    FallbackStage::Generic,
+];
+
+/// Stages of the fallback chain, processors of a stage are tried before the following ones
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FallbackStage {
+    Dedicated,
+    YtDlp,
+    Generic,
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct ProcessorsConfig {
+    pub disabled: Option<Vec<String>>,
+    pub fallback_chain: Option<Vec<FallbackStage>>,
+    pub priorities: Option<HashMap<String, i32>>,
+}
+
+/// Declares a processor to the registry: the URLs it claims and how to build it
+#[derive(Debug, Clone)]
+pub struct ProcessorDescriptor {
+    pub name: &'static str,
+    pub stage: FallbackStage,
+    /// Processors of the same stage claiming a URL are tried by descending priority
+    pub priority: i32,
+    pub claims: fn(url: &str) -> bool,
+    pub build: fn(url_id: &str, url: &str, context: &ProcessorContext) -> Box<dyn Processor + Send>,
+}
+
+/// Registry of the enabled processors, routing URLs along the configured fallback chain
+#[derive(Debug)]
+pub struct ProcessorRegistry {
+    descriptors: Vec<ProcessorDescriptor>,
+    fallback_chain: Vec<FallbackStage>,
+    disabled: Vec<String>,
+    priorities: HashMap<String, i32>,
+}
+
+impl ProcessorRegistry {
+    /// Creates a registry holding the built-in processors not disabled by the configuration
+    pub fn new(config: &ProcessorsConfig) -> Self {
+        let mut registry = Self::empty(config);
+
+        registry.register(TikTokProcessor::descriptor());
+        registry.register(InstagramProcessor::descriptor());
+        registry.register(TwitterProcessor::descriptor());
+        registry.register(RedditProcessor::descriptor());
+        registry.register(YtDlpProcessor::descriptor());
+        registry.register(GenericProcessor::descriptor());
+        registry
+    }
+
+    /// Creates a registry without any processor
+    pub fn empty(config: &ProcessorsConfig) -> Self {
+        Self {
+            descriptors: Vec::new(),
+            fallback_chain: config
+                .fallback_chain
+                .clone()
+                .unwrap_or(DEFAULT_FALLBACK_CHAIN.to_vec()),
+            disabled: config.disabled.clone().unwrap_or_default(),
+            priorities: config.priorities.clone().unwrap_or_default(),
+        }
+    }
+
+    /// Adds a processor, unless disabled by the configuration
+    pub fn register(&mut self, mut descriptor: ProcessorDescriptor) {
+        if self.disabled.iter().any(|d| d == descriptor.name) {
+            debug!("Processor `{}` is disabled", descriptor.name);
+            return;
+        }
+        if let Some(priority) = self.priorities.get(descriptor.name) {
+            descriptor.priority = *priority;
+        }
+
+        self.descriptors.push(descriptor);
+        self.descriptors
+            .sort_by_key(|d| std::cmp::Reverse(d.priority));
+    }
+
+    /// Names of the processors claiming the URL, in the order they are tried
+    pub fn candidates(&self, url: &str) -> Vec<&'static str> {
+        self.matching(url).map(|d| d.name).collect()
+    }
+
+    /// Builds the processors claiming the URL, in the order they are tried
+    /// # Arguments
+    /// * `url` - The requested URL
+    /// * `url_id` - The ID extracted from the URL
+    /// * `context` - The state of the request
+    #[instrument(level = "debug", name = "route_to_processors", skip(self, context))]
+    pub fn route(
+        &self,
+        url: &str,
+        url_id: &str,
+        context: &ProcessorContext,
+    ) -> Vec<(&'static str, Box<dyn Processor + Send>)> {
+        self.matching(url)
+            .map(|d| (d.name, (d.build)(url_id, url, context)))
+            .collect()
+    }
+
+    fn matching<'a>(&'a self, url: &'a str) -> impl Iterator<Item = &'a ProcessorDescriptor> {
+        self.fallback_chain.iter().flat_map(move |stage| {
+            self.descriptors
+                .iter()
+                .filter(move |d| d.stage == *stage && (d.claims)(url))
+        })
+    }
+}
+
+impl Default for ProcessorRegistry {
+    fn default() -> Self {
+        ProcessorRegistry::new(&ProcessorsConfig::default())
+    }
+}
+
+#[cfg(test)]
+mod registry_test {
+    use super::*;
+
+    const TIKTOK_URL: &str = "https://vm.tiktok.com/ZGJabc123/";
+    const OTHER_URL: &str = "https://www.youtube.com/watch?v=abc123";
+
+    #[test]
+    fn test_default_fallback_chain() {
+        let registry = ProcessorRegistry::default();
+
+        assert_eq!(
+            registry.candidates(TIKTOK_URL),
+            vec!["tiktok", "yt_dlp", "generic"]
+        );
+        assert_eq!(registry.candidates(OTHER_URL), vec!["yt_dlp", "generic"]);
+    }
+
+    #[test]
+    fn test_configured_fallback_chain() {
+        let config: ProcessorsConfig = toml::from_str(
+            r#"
+    disabled = ["tiktok"]
+    fallback_chain = ["dedicated", "generic"]
+    "#,
+        )
+        .unwrap();
+        let registry = ProcessorRegistry::new(&config);
+
+        assert_eq!(registry.candidates(TIKTOK_URL), vec!["generic"]);
+    }
+
+    #[test]
+    fn test_priority_orders_processors_of_a_stage() {
+        let config: ProcessorsConfig = toml::from_str(
+            r#"
+    [priorities]
+    generic = 100
+    "#,
+        )
+        .unwrap();
+        let mut registry = ProcessorRegistry::new(&config);
+        let mut descriptor = GenericProcessor::descriptor();
+        descriptor.name = "custom";
+        descriptor.stage = FallbackStage::Dedicated;
+        registry.register(descriptor);
+
+        let mut descriptor = TikTokProcessor::descriptor();
+        descriptor.name = "tiktok_mirror";
+        descriptor.priority = 1;
+        registry.register(descriptor);
+
+        assert_eq!(
+            registry.candidates(TIKTOK_URL),
+            vec!["tiktok", "tiktok_mirror", "custom", "yt_dlp", "generic"]
+        );
+    }
+}
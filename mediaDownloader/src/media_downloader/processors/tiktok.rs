@@ -1,21 +1,38 @@
 use core::panic;
-use std::{collections::HashMap, error::Error, fmt::Debug, io::Write};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt::Debug,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 use rand::distributions::{Alphanumeric, DistString};
 use serde::Deserialize;
 use tracing::{debug, instrument};
 
-use super::processor::Processor;
+use super::processor::{url_matches_domains, Processor, ProcessorConfig, ProcessorContext};
+use super::registry::{FallbackStage, ProcessorDescriptor, DEDICATED_PROCESSOR_PRIORITY};
 use crate::{
     media_downloader::{
-        downloader::{fetch_resource, was_video_already_downloaded},
+        downloader::{
+            download_file, download_video_from_url, forget_download, render_slideshow,
+            was_video_already_downloaded,
+        },
         errors::MediaDownloaderError,
+        http_client::HttpClient,
+        progress::ProgressReporter,
+        video_metadata::VideoMetadata,
     },
-    retrieve_blob, MessageContent, AWEME_CONFIG, BACKOFF_SECONDS, RETRIES_ATTEMPTS,
-    TARGET_DIRECTORY, VIDEO_EXTENSIONS_FORMAT,
+    retrieve_blob, retrieve_video,
+    services::{OversizePolicy, RedisManager, SlideshowAudio},
+    ImageInfo, MessageContent, AUDIO_EXTENSIONS_FORMAT, BACKOFF_SECONDS, IMAGE_EXTENSIONS_FORMAT,
+    RETRIES_ATTEMPTS, TARGET_DIRECTORY, TARGET_DIRECTORY_IMAGES, TIKTOK_GENERAL_DOMAIN,
+    TIKTOK_MOBILE_DOMAIN, VIDEO_EXTENSIONS_FORMAT,
 };
 use async_trait::async_trait;
 use cookie::Cookie;
+use frankenstein::{FileUpload, Media};
 use regex::Regex;
 use reqwest::header::{self, HeaderValue};
 use scraper::Selector;
@@ -26,6 +43,9 @@ const TIKTOK_SCRIPT_ID: &str = "SIGI_STATE";
 const TIKTOK_SCRIPT_ID_SECONDARY: &str = "__UNIVERSAL_DATA_FOR_REHYDRATION__";
 const TIKTOK_SCRIPT_ID_NOT_FOUND: &str = "NOT_FOUND";
 const TIKTOK_LOGIN_PATH: &str = "/login";
+const SLIDESHOW_MUSIC_SUFFIX: &str = "_music";
+const SLIDESHOW_RENDERED_SUFFIX: &str = "_slideshow";
+const SLIDESHOW_SECONDS_PER_IMAGE: u32 = 3;
 
 #[derive(Clone, Debug)]
 pub struct TikTokProcessor {
@@ -36,6 +56,49 @@ pub struct TikTokProcessor {
     slideshows: Vec<String>,
     download_url: Option<String>,
     slideshows_map: HashMap<i32, String>,
+    music_url: Option<String>,
+    slideshow_audio: SlideshowAudio,
+    oversize: OversizePolicy,
+    metadata: Option<VideoMetadata>,
+    http_client: HttpClient,
+    redis: RedisManager,
+    config: Arc<ProcessorConfig>,
+    progress: ProgressReporter,
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct Slideshow {
+    images: Vec<String>,
+    music_url: Option<String>,
+}
+
+/// A rendition of a video, each one is served by several mirrors
+#[derive(Debug, Default, Clone, PartialEq)]
+struct VideoVariant {
+    urls: Vec<String>,
+    width: u32,
+    height: u32,
+    bitrate: u64,
+    size: Option<u64>,
+    h264: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct BitrateInfo {
+    bitrate: Option<u64>,
+    codec_type: Option<String>,
+    play_addr: PlayAddr,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct PlayAddr {
+    // Served either as a number or as a string
+    data_size: Option<Value>,
+    width: Option<u32>,
+    height: Option<u32>,
+    url_list: Vec<String>,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -128,26 +191,24 @@ pub struct AwemeParams {
     pub is_pad: String,
 }
 
-impl Default for TikTokProcessor {
-    fn default() -> TikTokProcessor {
+impl TikTokProcessor {
+    pub fn new(id: String, url: String, context: &ProcessorContext) -> TikTokProcessor {
         TikTokProcessor {
-            id: "".to_string(),
-            url: "".to_string(),
+            id,
+            url,
             mobile_experience: true,
             resource_type: ResourceType::Video,
             slideshows: Vec::new(),
             download_url: None,
             slideshows_map: HashMap::new(),
-        }
-    }
-}
-
-impl TikTokProcessor {
-    pub fn new(id: String, url: String) -> TikTokProcessor {
-        TikTokProcessor {
-            id,
-            url,
-            ..Default::default()
+            music_url: None,
+            slideshow_audio: context.chat_settings.slideshow_audio,
+            oversize: context.chat_settings.oversize,
+            metadata: None,
+            http_client: context.http_client.clone(),
+            redis: context.redis.clone(),
+            config: context.config.clone(),
+            progress: context.progress.clone(),
         }
     }
 
@@ -155,6 +216,21 @@ impl TikTokProcessor {
         self.id.to_string()
     }
 
+    pub fn descriptor() -> ProcessorDescriptor {
+        ProcessorDescriptor {
+            name: "tiktok",
+            stage: FallbackStage::Dedicated,
+            priority: DEDICATED_PROCESSOR_PRIORITY,
+            claims: |url| url_matches_domains(url, &[TIKTOK_GENERAL_DOMAIN]),
+            build: |url_id, url, context| {
+                let mut tiktok_processor =
+                    TikTokProcessor::new(url_id.to_string(), url.to_string(), context);
+                tiktok_processor.set_mobile_experience(url.contains(TIKTOK_MOBILE_DOMAIN));
+                Box::new(tiktok_processor)
+            },
+        }
+    }
+
     pub fn set_mobile_experience(&mut self, mobile_experience: bool) {
         self.mobile_experience = mobile_experience;
     }
@@ -178,37 +254,238 @@ impl TikTokProcessor {
         }
     }
 
+    /// Finds the post in the script data, either in the rehydration data or in `SIGI_STATE`
+    /// # Arguments
+    /// * `json` - The parsed script data
+    /// # Returns
+    /// * `&Value` - The post, `Value::Null` when it is missing
+    fn find_item<'a>(&self, json: &'a Value) -> &'a Value {
+        let rehydration_item =
+            &json["__DEFAULT_SCOPE__"]["webapp.video-detail"]["itemInfo"]["itemStruct"];
+        if rehydration_item.is_object() {
+            return rehydration_item;
+        }
+        debug!("Looking up the post in `{}`", TIKTOK_SCRIPT_ID);
+        match &json["ItemModule"][&self.id] {
+            Value::Null => json["ItemModule"]
+                .as_object()
+                .and_then(|items| items.values().next())
+                .unwrap_or(&Value::Null),
+            item => item,
+        }
+    }
+
+    /// Ranks the video variants advertised by the script data, best first
+    /// # Arguments
+    /// * `json` - The parsed script data
+    /// * `max_size` - The size limit, in bytes
+    /// # Errors
+    /// * `MediaDownloaderError::ParsingError` - No video URL found
     #[instrument(level = "debug", name = "parse_video", skip_all)]
-    async fn parse_video(&self, json: &Value) -> Result<String, Box<dyn Error>> {
-        let video_urls: Vec<String> = json["__DEFAULT_SCOPE__"]["webapp.video-detail"]["itemInfo"]
-            ["itemStruct"]["video"]["bitrateInfo"][0]["PlayAddr"]["UrlList"]
-            .as_array()
-            .unwrap()
-            .iter()
-            .map(|url| url.as_str().unwrap().replace("amp;", "").to_string())
-            .collect();
+    fn parse_video(
+        &self,
+        json: &Value,
+        max_size: u64,
+    ) -> Result<Vec<VideoVariant>, Box<dyn Error + Send>> {
+        let video =
+            &json["__DEFAULT_SCOPE__"]["webapp.video-detail"]["itemInfo"]["itemStruct"]["video"];
+        let variants = rank_video_variants(parse_video_variants(video), max_size);
+
+        if variants.is_empty() {
+            error!("No video URL found for {:?}", self.id);
+            return Err(Box::new(MediaDownloaderError::ParsingError));
+        }
+        debug!("Found {} video variants", variants.len());
+        Ok(variants)
+    }
+
+    /// Downloads the first variant that can be fetched from any of its mirrors and fits
+    /// the size limit, as fitted according to the chat policy, trying the following ones otherwise
+    /// # Arguments
+    /// * `variants` - The ranked video variants
+    /// * `cookies` - (`Option`) The cookies to inject
+    /// # Errors
+    /// * `MediaDownloaderError::FileSizeExceeded` - Every variant downloaded exceeds the size limit
+    /// * `MediaDownloaderError::DownloadError` - No variant could be downloaded
+    #[instrument(level = "debug", name = "download_video_variants", skip_all)]
+    async fn download_video_variants(
+        &self,
+        variants: &[VideoVariant],
+        cookies: Option<Vec<String>>,
+    ) -> Result<MessageContent, Box<dyn Error + Send>> {
+        let mut last_error: Box<dyn Error + Send> = Box::new(MediaDownloaderError::DownloadError);
+
+        for variant in variants {
+            debug!("Trying variant {:?}", variant);
+            for video_url in &variant.urls {
+                match download_video_from_url(
+                    &self.http_client,
+                    &self.redis,
+                    &self.url,
+                    video_url,
+                    &self.id,
+                    cookies.clone(),
+                    Some(&self.progress),
+                )
+                .await
+                {
+                    Ok(_) => {}
+                    Err(e) => {
+                        warn!("Error downloading from mirror {:?}: {:?}", video_url, e);
+                        continue;
+                    }
+                }
 
-        let mut rng = rand::thread_rng();
-        let random_index = rand::Rng::gen_range(&mut rng, 0..=1);
+                match retrieve_video(&self.redis, &self.config, &self.id, self.oversize).await {
+                    Ok(video) => return Ok(video),
+                    Err(e) => {
+                        warn!("Variant not usable: {:?}", e);
+                        forget_download(&self.redis, &self.id).await;
+                        last_error = e;
+                        // Mirrors serve the same file, a smaller variant might fit instead
+                        break;
+                    }
+                }
+            }
+        }
+        Err(last_error)
+    }
 
-        let video_url = match Url::parse(&video_urls[random_index]) {
-            Ok(url) => url,
-            Err(_) => return Err(Box::new(MediaDownloaderError::ParsingError)),
+    /// Delivers the downloaded slideshow along with its music, as preferred by the chat
+    /// The bare images are delivered whenever the music cannot be added
+    /// # Arguments
+    /// * `images` - The downloaded images
+    /// * `number_of_images` - The number of images downloaded
+    #[instrument(level = "debug", name = "slideshow_content", skip(self, images))]
+    async fn slideshow_content(&self, images: Vec<Media>, number_of_images: i32) -> MessageContent {
+        let music_url = match (self.slideshow_audio, &self.music_url) {
+            (SlideshowAudio::Off, _) => return MessageContent::Images(images),
+            (_, None) => {
+                warn!("Slideshow {:?} has no music", self.id);
+                return MessageContent::Images(images);
+            }
+            (_, Some(music_url)) => music_url,
         };
-        Ok(video_url.to_string())
+
+        let music_path = format!(
+            "{}{}{}.{}",
+            TARGET_DIRECTORY, self.id, SLIDESHOW_MUSIC_SUFFIX, AUDIO_EXTENSIONS_FORMAT
+        );
+        if let Err(e) = download_file(&self.http_client, music_url, &music_path, None).await {
+            error!("Error downloading slideshow music: {:?}", e);
+            return MessageContent::Images(images);
+        }
+
+        match self.slideshow_audio {
+            SlideshowAudio::Video => {
+                match self.render_slideshow(number_of_images, &music_path).await {
+                    Ok(video) => video,
+                    Err(e) => {
+                        error!("Error rendering slideshow: {:?}", e);
+                        MessageContent::Images(images)
+                    }
+                }
+            }
+            _ => {
+                let music = self.config.file_upload(PathBuf::from(music_path));
+                MessageContent::Slideshow(images, music)
+            }
+        }
+    }
+
+    /// Renders the downloaded images and the music into a video, fitted to the size limit
+    /// according to the chat policy
+    /// # Arguments
+    /// * `number_of_images` - The number of images downloaded
+    /// * `music_path` - The path of the downloaded music
+    async fn render_slideshow(
+        &self,
+        number_of_images: i32,
+        music_path: &str,
+    ) -> Result<MessageContent, Box<dyn Error + Send>> {
+        let rendered_id = format!("{}{}", self.id, SLIDESHOW_RENDERED_SUFFIX);
+        if was_video_already_downloaded(&self.redis, &rendered_id).await {
+            debug!("Slideshow already rendered!");
+            return retrieve_video(&self.redis, &self.config, &rendered_id, self.oversize).await;
+        }
+
+        let image_paths: Vec<String> = (0..number_of_images)
+            .map(|i| {
+                format!(
+                    "{}{}{}_{}.{}",
+                    TARGET_DIRECTORY, TARGET_DIRECTORY_IMAGES, self.id, i, IMAGE_EXTENSIONS_FORMAT
+                )
+            })
+            .filter(|path| Path::new(path).exists())
+            .collect();
+        let output_path = format!(
+            "{}{}.{}",
+            TARGET_DIRECTORY, rendered_id, VIDEO_EXTENSIONS_FORMAT
+        );
+
+        if let Err(e) = render_slideshow(
+            &image_paths,
+            Some(music_path),
+            SLIDESHOW_SECONDS_PER_IMAGE,
+            &output_path,
+            self.config.transcoding.timeout(),
+        )
+        .await
+        {
+            let _ = self.redis.del(&rendered_id).await;
+            return Err(e);
+        }
+        retrieve_video(&self.redis, &self.config, &rendered_id, self.oversize).await
     }
 
+    /// Extracts the images and the background music of a slideshow from the script data,
+    /// either `__UNIVERSAL_DATA_FOR_REHYDRATION__` or the legacy `SIGI_STATE`
+    /// # Arguments
+    /// * `json` - The parsed script data
+    /// # Errors
+    /// * `MediaDownloaderError::ParsingError` - No images found for the slideshow
     #[instrument(level = "debug", name = "parse_slideshow", skip_all)]
-    async fn parse_slideshow(&self, json: &Value) -> Result<Vec<String>, Box<dyn Error>> {
-        if json.to_string().contains(".jpeg") {
-            debug!("Saving Slideshow JSON to file...");
-            let file = std::fs::File::create("slideshow_to_be_parsed.json".to_string()).unwrap();
-            let mut writer = std::io::BufWriter::new(file);
-            writer.write_all(json.to_string().as_bytes()).unwrap();
+    fn parse_slideshow(&self, json: &Value) -> Result<Slideshow, Box<dyn Error + Send>> {
+        let item = self.find_item(json);
+
+        let image_info: ImageInfo = match serde_json::from_value(item["imagePost"].clone()) {
+            Ok(image_info) => image_info,
+            Err(e) => {
+                error!("Error parsing slideshow images: {}", e);
+                return Err(Box::new(MediaDownloaderError::ParsingError));
+            }
+        };
+
+        // Telegram does not accept every format TikTok serves (e.g. `heic`)
+        let images: Vec<String> = image_info
+            .images
+            .iter()
+            .filter_map(|image| {
+                let url_list = &image.image_url.url_list;
+                url_list
+                    .iter()
+                    .find(|url| url.contains(".jpeg") || url.contains(".jpg"))
+                    .or_else(|| url_list.first())
+                    .map(|url| url.replace("amp;", ""))
+            })
+            .collect();
+
+        if images.is_empty() {
+            error!("No images found in slideshow {:?}", self.id);
+            return Err(Box::new(MediaDownloaderError::ParsingError));
         }
 
-        let images = Vec::<String>::new();
-        Ok(images)
+        let music_url = item["music"]["playUrl"]
+            .as_str()
+            .filter(|url| !url.is_empty())
+            .map(|url| url.replace("amp;", ""));
+        debug!(
+            "Found {} images ~ music: {}",
+            images.len(),
+            music_url.is_some()
+        );
+
+        Ok(Slideshow { images, music_url })
     }
 }
 
@@ -229,16 +506,14 @@ impl Processor for TikTokProcessor {
             ),
         ];
 
-        let content = fetch_resource(
-            &self.url,
-            None,
-            None,
-            None,
-            Some("Mozilla/5.0".to_string()),
-            Some(headers),
-        )
-        .await
-        .unwrap();
+        let content = self
+            .http_client
+            .fetch(&self.url, None, None, None, None, Some(headers))
+            .await
+            .map_err(|e| {
+                error!("Error fetching TikTok page: {}", e);
+                Box::new(MediaDownloaderError::UnreachableResource) as Box<dyn Error + Send>
+            })?;
 
         if !content.status().is_success() {
             error!(
@@ -255,7 +530,9 @@ impl Processor for TikTokProcessor {
         let content_path = content.url().path();
 
         if self.mobile_experience {
-            let full_url_clean = content_url.rsplit_once("?").unwrap().0;
+            let full_url_clean = content_url
+                .split_once('?')
+                .map_or(content_url.as_str(), |(url, _)| url);
             self.set_url(full_url_clean.to_string());
         } else {
             self.set_url(content_url);
@@ -263,7 +540,7 @@ impl Processor for TikTokProcessor {
 
         self.check_tiktok_resource();
 
-        match extract_tiktok_id_from_path(&content_path.to_string()) {
+        match extract_tiktok_id_from_path(content_path) {
             Some(id) => {
                 debug!("Setting new ID [path] as: {:?}", id);
                 self.set_id(id.to_string());
@@ -273,35 +550,67 @@ impl Processor for TikTokProcessor {
             }
         }
 
-        let content_text = content.text().await.unwrap();
+        let content_text = content.text().await.map_err(|e| {
+            error!("Error reading TikTok page: {}", e);
+            Box::new(MediaDownloaderError::UnreachableResource) as Box<dyn Error + Send>
+        })?;
         let script_structure = retrieving_script(content_text);
 
         let json_structure: Result<Value, _> = serde_json::from_str(&script_structure);
+        if let Ok(json) = &json_structure {
+            self.metadata = parse_item_metadata(self.find_item(json));
+        }
 
         match (self.resource_type, json_structure) {
             (ResourceType::Video, Ok(parsed_json)) => {
-                let video_url = self.parse_video(&parsed_json).await.unwrap();
-                match download_video(&self.url, &video_url, &self.get_id(), cookies).await {
-                    Ok(_) => {
+                let variants =
+                    self.parse_video(&parsed_json, self.config.file_size_limits.video)?;
+                match self.download_video_variants(&variants, cookies).await {
+                    Ok(video) => {
                         debug!("Video obtained successfully!");
-                        match retrieve_blob(&self.id).await {
-                            Ok(video) => {
-                                return Ok(Some(MessageContent::File(video)));
-                            }
-                            Err(e) => {
-                                error!("Error retrieving video: {:?}", e);
-                                return Err(Box::new(MediaDownloaderError::BlobRetrievingError));
-                            }
-                        }
+                        return Ok(Some(video));
                     }
                     Err(e) => {
                         error!("Error downloading video: {:?}", e);
-                        return Err(Box::new(MediaDownloaderError::DownloadError));
+                        return Err(e);
                     }
                 }
             }
             (ResourceType::Slideshow, Ok(parsed_json)) => {
-                let _slideshow_url = self.parse_slideshow(&parsed_json).await.unwrap();
+                match self.parse_slideshow(&parsed_json) {
+                    Ok(slideshow) => {
+                        self.slideshows_map = slideshow
+                            .images
+                            .into_iter()
+                            .enumerate()
+                            .map(|(i, url)| (i as i32, url))
+                            .collect();
+                        self.music_url = slideshow.music_url;
+
+                        let number_of_downloaded_images = crate::download_images_from_map(
+                            &self.http_client,
+                            &self.redis,
+                            self.slideshows_map.clone(),
+                            self.id.clone(),
+                        )
+                        .await?;
+                        let images = crate::retrieve_images(
+                            &self.redis,
+                            &self.config,
+                            &self.id,
+                            number_of_downloaded_images,
+                        )
+                        .await?;
+                        return Ok(Some(
+                            self.slideshow_content(images, number_of_downloaded_images)
+                                .await,
+                        ));
+                    }
+                    Err(e) => {
+                        warn!("Error parsing slideshow: {:?}", e);
+                        debug!("Calling external API!");
+                    }
+                }
             }
             (_, Err(err)) => {
                 error!("Error parsing JSON: {}", err);
@@ -309,21 +618,23 @@ impl Processor for TikTokProcessor {
             }
         }
 
-        if AWEME_CONFIG.is_none() {
+        let Some(aweme) = &self.config.aweme else {
             debug!("Cannot call Aweme API without configuration!");
             return Err(Box::new(MediaDownloaderError::DownloadError));
-        }
+        };
 
-        let (status, body) = aweme_api_call(&self.id).await.unwrap();
+        let (status, body) = aweme_api_call(&self.http_client, aweme, &self.id)
+            .await
+            .map_err(|e| {
+                error!("Error calling Aweme API: {}", e);
+                Box::new(MediaDownloaderError::UnreachableResource) as Box<dyn Error + Send>
+            })?;
         match status {
             reqwest::StatusCode::OK => {
                 debug!("Aweme API call successful!");
-                match body {
-                    Value::Null => {
-                        error!("Error: Body is null!");
-                        return Err(Box::new(MediaDownloaderError::ParsingError));
-                    }
-                    _ => {}
+                if body == Value::Null {
+                    error!("Error: Body is null!");
+                    return Err(Box::new(MediaDownloaderError::ParsingError));
                 }
             }
             _ => {
@@ -332,16 +643,42 @@ impl Processor for TikTokProcessor {
             }
         }
 
-        match parse_aweme_api(&self.resource_type, body).unwrap() {
-            AwemeParsingResult::Images(images) => {
-                let number_of_dowloaded_images =
-                    crate::download_images_from_map(images, self.id.clone())
-                        .await
-                        .unwrap();
+        if self.metadata.is_none() {
+            self.metadata = parse_item_metadata(&body["aweme_list"][0]);
+        }
+        if self.music_url.is_none() {
+            self.music_url = body["aweme_list"][0]["music"]["play_url"]["url_list"][0]
+                .as_str()
+                .map(str::to_string);
+        }
 
-                match crate::retrieve_images(&self.id.clone(), number_of_dowloaded_images).await {
+        let parsed = parse_aweme_api(&self.resource_type, body).map_err(|e| {
+            error!("Error parsing Aweme API response: {}", e);
+            Box::new(MediaDownloaderError::ParsingError) as Box<dyn Error + Send>
+        })?;
+        match parsed {
+            AwemeParsingResult::Images(images) => {
+                let number_of_dowloaded_images = crate::download_images_from_map(
+                    &self.http_client,
+                    &self.redis,
+                    images,
+                    self.id.clone(),
+                )
+                .await?;
+
+                match crate::retrieve_images(
+                    &self.redis,
+                    &self.config,
+                    &self.id,
+                    number_of_dowloaded_images,
+                )
+                .await
+                {
                     Ok(images) => {
-                        return Ok(Some(MessageContent::Images(images)));
+                        return Ok(Some(
+                            self.slideshow_content(images, number_of_dowloaded_images)
+                                .await,
+                        ));
                     }
                     Err(e) => {
                         error!("Error retrieving images: {:?}", e);
@@ -350,16 +687,28 @@ impl Processor for TikTokProcessor {
                 }
             }
             AwemeParsingResult::Video(video_url) => {
-                match download_video(&self.url, &video_url, &self.get_id(), cookies).await {
+                match download_video_from_url(
+                    &self.http_client,
+                    &self.redis,
+                    &self.url,
+                    &video_url,
+                    &self.get_id(),
+                    cookies,
+                    Some(&self.progress),
+                )
+                .await
+                {
                     Ok(_) => {
                         debug!("Video obtained successfully!");
-                        match retrieve_blob(&self.id).await {
+                        match retrieve_video(&self.redis, &self.config, &self.id, self.oversize)
+                            .await
+                        {
                             Ok(video) => {
-                                return Ok(Some(MessageContent::File(video)));
+                                return Ok(Some(video));
                             }
                             Err(e) => {
                                 error!("Error retrieving video: {:?}", e);
-                                return Err(Box::new(MediaDownloaderError::BlobRetrievingError));
+                                return Err(e);
                             }
                         }
                     }
@@ -372,6 +721,111 @@ impl Processor for TikTokProcessor {
         }
         Ok(None)
     }
+
+    fn metadata(&self) -> Option<VideoMetadata> {
+        self.metadata.clone()
+    }
+}
+
+/// Extracts the description and the author of a post, the page and the Aweme API
+/// name their fields differently, `SIGI_STATE` only keeps the handle of the author
+/// # Arguments
+/// * `item` - The post, from the page or from the Aweme API
+/// # Returns
+/// * `Option<VideoMetadata>` - `None` when nothing is known about the post
+fn parse_item_metadata(item: &Value) -> Option<VideoMetadata> {
+    let author = &item["author"];
+    let metadata = VideoMetadata {
+        description: item["desc"]
+            .as_str()
+            .filter(|desc| !desc.is_empty())
+            .map(str::to_string),
+        uploader: author["nickname"].as_str().map(str::to_string),
+        uploader_id: author["uniqueId"]
+            .as_str()
+            .or(author["unique_id"].as_str())
+            .or(author.as_str())
+            .map(|handle| format!("@{}", handle)),
+        ..Default::default()
+    };
+    (metadata != VideoMetadata::default()).then_some(metadata)
+}
+
+/// Parses the variants of a video, from `bitrateInfo` or the plain play address
+/// # Arguments
+/// * `video` - The `video` object of the item
+fn parse_video_variants(video: &Value) -> Vec<VideoVariant> {
+    let bitrate_info: Vec<BitrateInfo> = serde_json::from_value(video["bitrateInfo"].clone())
+        .unwrap_or_else(|e| {
+            warn!("Error parsing `bitrateInfo`: {}", e);
+            Vec::new()
+        });
+
+    let mut variants: Vec<VideoVariant> = bitrate_info
+        .into_iter()
+        .map(|info| VideoVariant {
+            urls: info
+                .play_addr
+                .url_list
+                .iter()
+                .map(|url| url.replace("amp;", ""))
+                .filter(|url| Url::parse(url).is_ok())
+                .collect(),
+            width: info.play_addr.width.unwrap_or_default(),
+            height: info.play_addr.height.unwrap_or_default(),
+            bitrate: info.bitrate.unwrap_or_default(),
+            size: info
+                .play_addr
+                .data_size
+                .as_ref()
+                .and_then(|size| match size {
+                    Value::Number(n) => n.as_u64(),
+                    Value::String(s) => s.parse().ok(),
+                    _ => None,
+                }),
+            h264: info
+                .codec_type
+                .as_deref()
+                .is_none_or(|codec| codec.starts_with("h264")),
+        })
+        .filter(|variant| !variant.urls.is_empty())
+        .collect();
+
+    if variants.is_empty() {
+        debug!("No `bitrateInfo`, falling back to the play address");
+        let urls: Vec<String> = ["playAddr", "downloadAddr"]
+            .iter()
+            .filter_map(|key| video[key].as_str())
+            .map(|url| url.replace("amp;", ""))
+            .filter(|url| Url::parse(url).is_ok())
+            .collect();
+        if !urls.is_empty() {
+            variants.push(VideoVariant {
+                urls,
+                width: video["width"].as_u64().unwrap_or_default() as u32,
+                height: video["height"].as_u64().unwrap_or_default() as u32,
+                ..Default::default()
+            });
+        }
+    }
+    variants
+}
+
+/// Ranks video variants for Telegram: H.264 first, as HEVC does not play everywhere,
+/// then by resolution and bitrate; variants known to exceed `max_size` are dropped
+/// # Arguments
+/// * `variants` - The variants to rank
+/// * `max_size` - The maximum size of a variant, in bytes
+fn rank_video_variants(mut variants: Vec<VideoVariant>, max_size: u64) -> Vec<VideoVariant> {
+    variants.retain(|variant| variant.size.is_none_or(|size| size <= max_size));
+    variants.sort_by_key(|variant| {
+        std::cmp::Reverse((
+            variant.h264,
+            variant.width * variant.height,
+            variant.bitrate,
+        ))
+    });
+    variants
 }
 
 #[instrument(level = "debug", name = "retrieving_script", skip_all)]
@@ -383,31 +837,29 @@ pub fn retrieving_script(content: String) -> String {
     let fragment = scraper::Html::parse_document(&content);
 
     let selector =
-        Selector::parse(format!(r#"script[id="{}"]"#, TIKTOK_SCRIPT_ID.to_string()).as_ref())
-            .unwrap();
+        Selector::parse(format!(r#"script[id="{}"]"#, TIKTOK_SCRIPT_ID).as_ref()).unwrap();
 
     let mut script_structure;
 
     script_structure = fragment
         .select(&selector)
         .next()
-        .and_then(|element| Some(element.inner_html()))
+        .map(|element| element.inner_html())
         .unwrap_or_else(|| {
             warn!("Warning: No matching element found!");
             TIKTOK_SCRIPT_ID_NOT_FOUND.to_string()
         });
 
-    if script_structure == TIKTOK_SCRIPT_ID_NOT_FOUND.to_string() {
+    if script_structure == TIKTOK_SCRIPT_ID_NOT_FOUND {
         debug!("Trying with secondary script ID...");
-        let selector = Selector::parse(
-            format!(r#"script[id="{}"]"#, TIKTOK_SCRIPT_ID_SECONDARY.to_string()).as_ref(),
-        )
-        .unwrap();
+        let selector =
+            Selector::parse(format!(r#"script[id="{}"]"#, TIKTOK_SCRIPT_ID_SECONDARY).as_ref())
+                .unwrap();
 
         script_structure = fragment
             .select(&selector)
             .next()
-            .and_then(|element| Some(element.inner_html()))
+            .map(|element| element.inner_html())
             .unwrap_or_else(|| {
                 error!("Warning: No matching element found even with **secondary**!");
                 TIKTOK_SCRIPT_ID_NOT_FOUND.to_string()
@@ -432,18 +884,16 @@ fn extract_tiktok_id_from_path(path: &str) -> Option<&str> {
 }
 
 #[instrument(level = "debug", name = "prepare_cookies_for_injection", skip_all)]
-fn prepare_cookies_for_injection<'a>(
-    cookies_retrieved: &'a header::GetAll<'_, HeaderValue>,
-) -> Option<Vec<(String, Option<url::Url>)>> {
-    let cookies: Vec<(String, Option<url::Url>)> = cookies_retrieved
+fn prepare_cookies_for_injection(
+    cookies_retrieved: &header::GetAll<'_, HeaderValue>,
+) -> Option<Vec<String>> {
+    let cookies: Vec<String> = cookies_retrieved
         .iter()
         .filter_map(|c| c.to_str().ok())
         .flat_map(|c| {
             if let Ok(c_parsed) = Cookie::parse(c) {
                 debug!("Preparing cookie `{}`", c_parsed.name());
-                let cookie_str = format!("{}={};", c_parsed.name(), c_parsed.value());
-                let url = None;
-                Some((cookie_str, url))
+                Some(format!("{}={};", c_parsed.name(), c_parsed.value()))
             } else {
                 None
             }
@@ -457,40 +907,34 @@ fn prepare_cookies_for_injection<'a>(
     Some(cookies)
 }
 
-#[instrument(level = "debug", name = "aweme_api_call")]
-async fn aweme_api_call(id: &str) -> Result<(reqwest::StatusCode, Value), Box<dyn Error>> {
+#[instrument(level = "debug", name = "aweme_api_call", skip(http_client, aweme))]
+async fn aweme_api_call(
+    http_client: &HttpClient,
+    aweme: &AwemeConfig,
+    id: &str,
+) -> Result<(reqwest::StatusCode, Value), Box<dyn Error>> {
     debug!("Calling aweme API for ID: {:?}", id);
-    let url = &AWEME_CONFIG.as_ref().unwrap().url.clone();
-    let parsed_url = url.parse::<url::Url>().unwrap();
+    let url = &http_client.base_url("aweme", &aweme.url);
 
     let headers_vec = vec![
-        (
-            "Accept-Language",
-            AWEME_CONFIG
-                .as_ref()
-                .unwrap()
-                .headers
-                .accept_language
-                .as_str(),
-        ),
-        (
-            "Accept",
-            AWEME_CONFIG.as_ref().unwrap().headers.accept.as_str(),
-        ),
+        ("Accept-Language", aweme.headers.accept_language.as_str()),
+        ("Accept", aweme.headers.accept.as_str()),
     ];
-    let ua = user_agent_aweme_api();
+    let ua = user_agent_aweme_api(aweme);
     let odin_cookie = format!(
         "{}={};",
         "odin_tt",
         Alphanumeric.sample_string(&mut rand::thread_rng(), 160)
     );
-    let cookie_str_url_vec = vec![(odin_cookie, Some(parsed_url))];
+    let cookies = vec![odin_cookie];
 
     let (status, body) = tryhard::retry_fn(move || {
         __aweme_api_call_lower_level(
+            http_client,
+            &aweme.params,
             id,
             url,
-            cookie_str_url_vec.clone(),
+            cookies.clone(),
             ua.clone(),
             headers_vec.clone(),
         )
@@ -504,24 +948,26 @@ async fn aweme_api_call(id: &str) -> Result<(reqwest::StatusCode, Value), Box<dy
 
 #[instrument(level = "debug", name = "__aweme_api_call_lower_level", skip_all)]
 async fn __aweme_api_call_lower_level(
+    http_client: &HttpClient,
+    params: &AwemeParams,
     id: &str,
     url: &str,
-    cookie_str_url_vec: Vec<(String, Option<Url>)>,
+    cookies: Vec<String>,
     ua: String,
     headers_vec: Vec<(&str, &str)>,
 ) -> Result<(reqwest::StatusCode, Value), Box<dyn Error>> {
-    let query_params = query_params_aweme_api(id);
-
-    let res = fetch_resource(
-        url,
-        Some(query_params),
-        None,
-        Some(cookie_str_url_vec),
-        Some(ua),
-        Some(headers_vec),
-    )
-    .await
-    .unwrap();
+    let query_params = query_params_aweme_api(params, id);
+
+    let res = http_client
+        .fetch(
+            url,
+            Some(query_params),
+            None,
+            Some(cookies),
+            Some(ua),
+            Some(headers_vec),
+        )
+        .await?;
 
     let status = res.status();
     let body = match res.json::<serde_json::Value>().await {
@@ -535,17 +981,16 @@ async fn __aweme_api_call_lower_level(
     Ok((status, body))
 }
 
-fn user_agent_aweme_api() -> String {
-    let app_name = AWEME_CONFIG.as_ref().unwrap().app_name.clone();
-    let ua = AWEME_CONFIG.as_ref().unwrap().ua.clone();
-    let version_code = AWEME_CONFIG.as_ref().unwrap().params.version_code.clone();
+fn user_agent_aweme_api(aweme: &AwemeConfig) -> String {
+    let app_name = aweme.app_name.clone();
+    let ua = aweme.ua.clone();
+    let version_code = aweme.params.version_code.clone();
 
-    let package;
-    if app_name.eq("musical_ly") {
-        package = "com.zhiliaoapp.musically".to_string();
+    let package = if app_name.eq("musical_ly") {
+        "com.zhiliaoapp.musically".to_string()
     } else {
-        package = format!("com.ss.android.ugc.{}", app_name);
-    }
+        format!("com.ss.android.ugc.{}", app_name)
+    };
     format!("{}/{} {}", package, version_code, ua)
 }
 
@@ -561,9 +1006,9 @@ fn expand_app_version(app_version: String) -> String {
     formatted_version
 }
 
-#[instrument(level = "debug", name = "query_params_aweme_api")]
-fn query_params_aweme_api(id: &str) -> Vec<(&str, String)> {
-    let params = AWEME_CONFIG.as_ref().unwrap().params.clone();
+#[instrument(level = "debug", name = "query_params_aweme_api", skip(params))]
+fn query_params_aweme_api(params: &AwemeParams, id: &str) -> Vec<(&'static str, String)> {
+    let params = params.clone();
     let mut rng = rand::thread_rng();
 
     let iid_vec = params.iid.clone();
@@ -653,37 +1098,39 @@ fn parse_aweme_api(
     match resource_type {
         ResourceType::Video => {
             let video_url_str = parse_aweme_video(data)?;
-            return Ok(AwemeParsingResult::Video(video_url_str));
+            Ok(AwemeParsingResult::Video(video_url_str))
         }
         ResourceType::Slideshow => {
             let images = parse_aweme_slideshow(data)?;
-            return Ok(AwemeParsingResult::Images(images));
+            Ok(AwemeParsingResult::Images(images))
         }
     }
 }
 
 #[instrument(level = "debug", name = "parse_aweme_video", skip_all)]
 fn parse_aweme_video(data: serde_json::Value) -> Result<String, Box<dyn Error>> {
-    let video_url_str = data["aweme_list"][0]["video"]["bit_rate"][0]["play_addr"]["url_list"]
+    let video_url = data["aweme_list"][0]["video"]["bit_rate"][0]["play_addr"]["url_list"]
         .as_array()
-        .ok_or_else(|| "No array found".to_string())
         .and_then(|arr| {
-            let url = arr
-                .iter()
-                .find_map(|val| val.as_str().filter(|&s| s.contains("byteicdn.com")));
+            arr.iter()
+                .find_map(|val| val.as_str().filter(|&s| s.contains("byteicdn.com")))
+        });
 
-            match url {
-                Some(url_str) => Ok(url_str.to_string()),
-                None => Err("Video URL not found".to_string()),
-            }
-        })
-        .unwrap_or_default();
-    Ok(video_url_str)
+    match video_url {
+        Some(video_url) => Ok(video_url.to_string()),
+        None => {
+            error!("Video URL not found in the Aweme API response");
+            Err(Box::new(MediaDownloaderError::ParsingError))
+        }
+    }
 }
 
 #[instrument(level = "debug", name = "parse_aweme_slideshow", skip_all)]
 fn parse_aweme_slideshow(data: serde_json::Value) -> Result<HashMap<i32, String>, Box<dyn Error>> {
-    let list_object: Data = serde_json::from_value(data).unwrap();
+    let list_object: Data = serde_json::from_value(data).map_err(|e| {
+        error!("Unexpected Aweme API response: {}", e);
+        Box::new(MediaDownloaderError::ParsingError)
+    })?;
     let mut images = HashMap::<i32, String>::new();
 
     if let Some(aweme) = list_object.aweme_list.first() {
@@ -708,82 +1155,32 @@ fn parse_aweme_slideshow(data: serde_json::Value) -> Result<HashMap<i32, String>
     Ok(images)
 }
 
-#[instrument(level = "debug", name = "download_video", skip_all)]
-async fn download_video(
-    source_url: &String,
-    download_url: &String,
-    id: &String,
-    cookies: Option<Vec<(String, Option<Url>)>>,
-) -> Result<(), Box<dyn Error + Send>> {
-    match was_video_already_downloaded(&id).await {
-        true => {
-            debug!("Video already downloaded!");
-            return Ok(());
-        }
-        false => {}
-    }
-
-    let headers = vec![
-        ("Accept-Language", "en-US,en;q=0.5"),
-        (
-            "Accept",
-            "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8",
-        ),
-        ("Accept-Encoding", "identity"),
-        ("Referer", source_url.as_str()),
-    ];
-
-    let ua = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/92.0.4515.115 Safari/537.36";
-
-    let content = fetch_resource(
-        &download_url,
-        None,
-        None,
-        cookies,
-        Some(ua.to_string()),
-        Some(headers),
-    )
-    .await
-    .unwrap();
-
-    if !content.status().is_success() {
-        error!(
-            "Error: Request failed with status code {:?}",
-            content.status()
-        );
-        return Err(Box::new(MediaDownloaderError::UnreachableResource));
-    }
-
-    let _ = tokio::fs::create_dir_all(TARGET_DIRECTORY)
-        .await
-        .map_err(MediaDownloaderError::IoErrorDirectory);
-
-    let mut file = match tokio::fs::File::create(format!(
-        "{}{}.{}",
-        TARGET_DIRECTORY, id, VIDEO_EXTENSIONS_FORMAT
-    ))
-    .await
-    {
-        Ok(file) => file,
-        Err(err) => {
-            error!("Error creating file: {}", err);
-            return Err(Box::new(MediaDownloaderError::IoErrorDirectory(err)));
-        }
+#[cfg(test)]
+mod tiktok_processor_test {
+    use super::*;
+    use crate::media_downloader::{
+        caption::CaptionsConfig,
+        http_client::HttpConfig,
+        mock_server::{fixture, MockRedis, MockResponse, MockServer},
+        processors::ProcessorContext,
     };
+    use crate::MAX_FILE_SIZE;
+
+    const VIDEO_ID: &str = "7300000000000000001";
+    const SLIDESHOW_ID: &str = "7300000000000000002";
+    const AWEME_VIDEO_ID: &str = "7300000000000000003";
 
-    let mut stream = content.bytes_stream();
-    while let Some(chunk) = futures::StreamExt::next(&mut stream).await {
-        let chunk = chunk.unwrap();
-        tokio::io::AsyncWriteExt::write_all(&mut file, &chunk)
+    /// Fetches a page from the stand-in, returning its path after redirects and its body
+    async fn fetch_page(server: &MockServer, path: &str) -> (String, String) {
+        let content = HttpClient::default()
+            .fetch(&server.url(path), None, None, None, None, None)
             .await
             .unwrap();
-    }
-    Ok(())
-}
+        assert!(content.status().is_success());
 
-#[cfg(test)]
-mod tiktok_processor_test {
-    use super::*;
+        let content_path = content.url().path().to_string();
+        (content_path, content.text().await.unwrap())
+    }
 
     #[test]
     fn test_extract_id_from_path_video() {
@@ -802,4 +1199,336 @@ mod tiktok_processor_test {
 
         assert_eq!(id.unwrap(), expected_id);
     }
+
+    #[tokio::test]
+    async fn test_parse_slideshow_rehydration() {
+        let context = MockRedis::start().await.context().await;
+        let json: Value = serde_json::from_str(
+            r#"{"__DEFAULT_SCOPE__":{"webapp.video-detail":{"itemInfo":{"itemStruct":{
+                "imagePost":{"images":[
+                    {"imageURL":{"urlList":["https://p16.tiktokcdn.com/1.heic","https://p16.tiktokcdn.com/1.jpeg?a=1&amp;b=2"]}},
+                    {"imageURL":{"urlList":["https://p16.tiktokcdn.com/2.webp"]}}
+                ]},
+                "music":{"playUrl":"https://sf16.tiktokcdn.com/music.mp3"}
+            }}}}}"#,
+        )
+        .unwrap();
+        let processor = TikTokProcessor::new("123".to_string(), "".to_string(), &context);
+
+        assert_eq!(
+            processor.parse_slideshow(&json).unwrap(),
+            Slideshow {
+                images: vec![
+                    "https://p16.tiktokcdn.com/1.jpeg?a=1&b=2".to_string(),
+                    "https://p16.tiktokcdn.com/2.webp".to_string(),
+                ],
+                music_url: Some("https://sf16.tiktokcdn.com/music.mp3".to_string()),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_parse_slideshow_sigi_state() {
+        let context = MockRedis::start().await.context().await;
+        let json: Value = serde_json::from_str(
+            r#"{"ItemModule":{"123":{
+                "imagePost":{"images":[{"imageURL":{"urlList":["https://p16.tiktokcdn.com/1.jpeg"]}}]},
+                "music":{"playUrl":""}
+            }}}"#,
+        )
+        .unwrap();
+        let processor = TikTokProcessor::new("123".to_string(), "".to_string(), &context);
+        let slideshow = processor.parse_slideshow(&json).unwrap();
+
+        assert_eq!(slideshow.images, vec!["https://p16.tiktokcdn.com/1.jpeg"]);
+        assert_eq!(slideshow.music_url, None);
+    }
+
+    #[test]
+    fn test_rank_video_variants() {
+        let video: Value = serde_json::from_str(
+            r#"{"bitrateInfo":[
+                {"Bitrate":2000000,"CodecType":"h265_hvc1","PlayAddr":{"DataSize":"9000000","Width":1080,"Height":1920,"UrlList":["https://v16.tiktokcdn.com/hevc_1080.mp4"]}},
+                {"Bitrate":1500000,"CodecType":"h264","PlayAddr":{"DataSize":90000000,"Width":1080,"Height":1920,"UrlList":["https://v16.tiktokcdn.com/avc_1080.mp4"]}},
+                {"Bitrate":800000,"CodecType":"h264","PlayAddr":{"DataSize":5000000,"Width":720,"Height":1280,"UrlList":["https://v16.tiktokcdn.com/avc_720.mp4?a=1&amp;b=2","https://v19.tiktokcdn.com/avc_720.mp4"]}},
+                {"Bitrate":500000,"CodecType":"h264","PlayAddr":{"Width":540,"Height":960,"UrlList":["not a url"]}}
+            ]}"#,
+        )
+        .unwrap();
+
+        let variants = rank_video_variants(parse_video_variants(&video), 50 * 1024 * 1024);
+        let urls: Vec<&str> = variants.iter().map(|v| v.urls[0].as_str()).collect();
+
+        assert_eq!(
+            urls,
+            vec![
+                "https://v16.tiktokcdn.com/avc_720.mp4?a=1&b=2",
+                "https://v16.tiktokcdn.com/hevc_1080.mp4",
+            ]
+        );
+        assert_eq!(variants[0].urls.len(), 2);
+    }
+
+    #[test]
+    fn test_parse_video_variants_play_address() {
+        let video: Value =
+            serde_json::from_str(r#"{"playAddr":"https://v16.tiktokcdn.com/video.mp4"}"#).unwrap();
+
+        assert_eq!(
+            parse_video_variants(&video)[0].urls,
+            vec!["https://v16.tiktokcdn.com/video.mp4"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_video_page_fixture() {
+        let context = MockRedis::start().await.context().await;
+        let video_path = format!("/@fixture_user/video/{}", VIDEO_ID);
+        let server = MockServer::start(vec![
+            ("/ZMfixture/", MockResponse::redirect(&video_path)),
+            (
+                video_path.as_str(),
+                MockResponse::html(fixture("tiktok/video_page.html")),
+            ),
+        ])
+        .await;
+
+        let (path, body) = fetch_page(&server, "/ZMfixture/").await;
+        assert_eq!(extract_tiktok_id_from_path(&path), Some(VIDEO_ID));
+
+        let json: Value = serde_json::from_str(&retrieving_script(body)).unwrap();
+        let processor = TikTokProcessor::new(VIDEO_ID.to_string(), server.url(&path), &context);
+        let variants = processor.parse_video(&json, MAX_FILE_SIZE).unwrap();
+        let urls: Vec<&str> = variants.iter().map(|v| v.urls[0].as_str()).collect();
+
+        assert_eq!(
+            urls,
+            vec![
+                "https://v16-webapp-prime.tiktok.com/video/tos/avc_720.mp4?a=1988&br=1802",
+                "https://v16-webapp-prime.tiktok.com/video/tos/avc_540.mp4?a=1988&br=717",
+                "https://v16-webapp-prime.tiktok.com/video/tos/hevc_1080.mp4?a=1988&br=2399",
+            ]
+        );
+        assert_eq!(variants[0].urls.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_slideshow_page_fixture() {
+        let context = MockRedis::start().await.context().await;
+        let slideshow_path = format!("/@fixture_user/photo/{}", SLIDESHOW_ID);
+        let server = MockServer::start(vec![(
+            slideshow_path.as_str(),
+            MockResponse::html(fixture("tiktok/slideshow_page.html")),
+        )])
+        .await;
+
+        let (path, body) = fetch_page(&server, &slideshow_path).await;
+        let id = extract_tiktok_id_from_path(&path).unwrap();
+
+        let json: Value = serde_json::from_str(&retrieving_script(body)).unwrap();
+        let processor = TikTokProcessor::new(id.to_string(), server.url(&path), &context);
+        let slideshow = processor.parse_slideshow(&json).unwrap();
+
+        assert_eq!(slideshow.images.len(), 3);
+        assert!(slideshow.images[0].contains("photo_1~tplv-photomode-image.jpeg"));
+        assert!(slideshow.images[2].contains("photo_3~tplv-photomode-image.webp"));
+        assert_eq!(
+            slideshow.music_url.as_deref(),
+            Some("https://sf16-ies-music.tiktokcdn.com/obj/slideshow_music.mp3")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_login_page_fixture() {
+        let server = MockServer::start(vec![(
+            TIKTOK_LOGIN_PATH,
+            MockResponse::html(fixture("tiktok/login_page.html")),
+        )])
+        .await;
+
+        let (_, body) = fetch_page(&server, TIKTOK_LOGIN_PATH).await;
+        assert_eq!(retrieving_script(body), TIKTOK_SCRIPT_ID_NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_aweme_fixtures() {
+        let server = MockServer::start(vec![
+            (
+                "/aweme/v1/feed/",
+                MockResponse::json(fixture("tiktok/aweme_video.json")),
+            ),
+            (
+                "/aweme/v1/multi/aweme/detail/",
+                MockResponse::json(fixture("tiktok/aweme_slideshow.json")),
+            ),
+        ])
+        .await;
+
+        let content = HttpClient::default()
+            .fetch(
+                &server.url("/aweme/v1/feed/"),
+                Some(vec![("aweme_id", VIDEO_ID.to_string())]),
+                None,
+                None,
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+        let video: Value = content.json().await.unwrap();
+        assert_eq!(
+            server.requests(),
+            vec![format!("/aweme/v1/feed/?aweme_id={}", VIDEO_ID)]
+        );
+        assert_eq!(
+            parse_aweme_video(video).unwrap(),
+            "https://v77.byteicdn.com/video/tos/avc_720.mp4"
+        );
+
+        let (_, body) = fetch_page(&server, "/aweme/v1/multi/aweme/detail/").await;
+        let slideshow: Value = serde_json::from_str(&body).unwrap();
+        match parse_aweme_api(&ResourceType::Slideshow, slideshow).unwrap() {
+            AwemeParsingResult::Images(images) => {
+                assert_eq!(images.len(), 2);
+                assert!(images[&0].ends_with("photo_1~tplv-photomode-image.jpeg"));
+                assert!(images[&1].ends_with("photo_2~tplv-photomode-image.jpeg"));
+            }
+            AwemeParsingResult::Video(url) => unreachable!("Unexpected video {}", url),
+        }
+    }
+
+    #[test]
+    fn test_unexpected_aweme_responses() {
+        let unexpected: Value =
+            serde_json::from_str(r#"{"aweme_list":[{"video":{"bit_rate":"none"}}]}"#).unwrap();
+
+        assert!(parse_aweme_video(unexpected.clone()).is_err());
+        assert!(parse_aweme_slideshow(unexpected).is_err());
+        assert!(parse_aweme_slideshow(Value::Null).is_err());
+    }
+
+    /// Runs the processor on the given page of the stand-in, the downloads being recorded
+    /// in the Redis stand-in
+    async fn process_page(
+        server: &MockServer,
+        path: &str,
+        redis: &MockRedis,
+        http_client: HttpClient,
+        config: ProcessorConfig,
+    ) -> Result<(Option<MessageContent>, Option<VideoMetadata>), Box<dyn Error + Send>> {
+        let context = ProcessorContext::new(http_client, redis.manager().await, Arc::new(config));
+        let url = server.url(path);
+        let id = extract_tiktok_id_from_path(path).unwrap();
+        let mut processor = (TikTokProcessor::descriptor().build)(id, &url, &context);
+        let content = processor.process().await?;
+        Ok((content, processor.metadata()))
+    }
+
+    /// Asserts that the video was delivered from its file, holding the served content
+    fn assert_video_delivered(content: Option<MessageContent>, id: &str, body: &str) {
+        let path = PathBuf::from(format!(
+            "{}{}.{}",
+            TARGET_DIRECTORY, id, VIDEO_EXTENSIONS_FORMAT
+        ));
+        let downloaded = std::fs::read_to_string(&path);
+        let _ = std::fs::remove_file(&path);
+
+        match content {
+            Some(MessageContent::File(FileUpload::InputFile(file))) => {
+                assert_eq!(file.path, path)
+            }
+            other => unreachable!("Unexpected content {:?}", other),
+        }
+        assert_eq!(downloaded.unwrap(), body);
+    }
+
+    #[tokio::test]
+    async fn test_process_video_page() {
+        let cdn = MockServer::start(vec![(
+            "/video/tos/avc_720.mp4",
+            MockResponse::video("fixture video".to_string()),
+        )])
+        .await;
+        let video_path = format!("/@fixture_user/video/{}", VIDEO_ID);
+        let page = fixture("tiktok/video_page.html")
+            .replace("https://v16-webapp-prime.tiktok.com", &cdn.url(""));
+        let server = MockServer::start(vec![(video_path.as_str(), MockResponse::html(page))]).await;
+        let redis = MockRedis::start().await;
+
+        let (content, metadata) = process_page(
+            &server,
+            &video_path,
+            &redis,
+            HttpClient::default(),
+            ProcessorConfig::default(),
+        )
+        .await
+        .unwrap();
+
+        assert_video_delivered(content, VIDEO_ID, "fixture video");
+        assert!(redis.get(VIDEO_ID).is_some());
+        let caption = CaptionsConfig::default()
+            .render(&metadata.unwrap(), "https://vm.tiktok.com/ZMfixture/")
+            .unwrap();
+        assert_eq!(
+            caption.text,
+            "Fixture video #fixture\n@fixture_user\nhttps://vm.tiktok.com/ZMfixture/"
+        );
+        assert_eq!(
+            server.request_header(0, "Accept-Language").as_deref(),
+            Some("en-US,en;q=0.5")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_process_aweme_fallback() {
+        let cdn = MockServer::start(vec![(
+            "/v77.byteicdn.com/video/tos/avc_720.mp4",
+            MockResponse::video("fixture aweme video".to_string()),
+        )])
+        .await;
+        let video_path = format!("/@fixture_user/video/{}", AWEME_VIDEO_ID);
+        let aweme_video = fixture("tiktok/aweme_video.json")
+            .replace("https://v77.byteicdn.com", &cdn.url("/v77.byteicdn.com"));
+        let server = MockServer::start(vec![
+            (
+                video_path.as_str(),
+                MockResponse::html(fixture("tiktok/login_page.html")),
+            ),
+            ("/aweme/v1/feed/", MockResponse::json(aweme_video)),
+        ])
+        .await;
+        let redis = MockRedis::start().await;
+
+        let http_client = HttpClient::new(&HttpConfig {
+            base_urls: Some(HashMap::from([(
+                "aweme".to_string(),
+                server.url("/aweme/v1/feed/"),
+            )])),
+            ..Default::default()
+        })
+        .unwrap();
+        let config = ProcessorConfig {
+            aweme: Some(toml::from_str(&fixture("tiktok/aweme_config.toml")).unwrap()),
+            ..Default::default()
+        };
+
+        let (content, metadata) = process_page(&server, &video_path, &redis, http_client, config)
+            .await
+            .unwrap();
+
+        assert_video_delivered(content, AWEME_VIDEO_ID, "fixture aweme video");
+        let metadata = metadata.unwrap();
+        assert_eq!(metadata.author().as_deref(), Some("@fixture_user"));
+        assert_eq!(
+            metadata.description.as_deref(),
+            Some("Fixture video #fixture")
+        );
+        assert!(redis.get(AWEME_VIDEO_ID).is_some());
+        let aweme_request = format!("/aweme/v1/feed/?aweme_id={}&", AWEME_VIDEO_ID);
+        assert!(server
+            .requests()
+            .iter()
+            .any(|target| target.starts_with(&aweme_request)));
+    }
 }
@@ -0,0 +1,292 @@
+use std::{error::Error, sync::Arc};
+
+use async_trait::async_trait;
+use regex::Regex;
+use serde::Deserialize;
+use tracing::{debug, instrument};
+
+use super::media_group::{download_media_group, RemoteMedia};
+use super::processor::{url_matches_domains, Processor, ProcessorConfig, ProcessorContext};
+use super::registry::{FallbackStage, ProcessorDescriptor, DEDICATED_PROCESSOR_PRIORITY};
+use crate::media_downloader::http_client::HttpClient;
+use crate::{
+    media_downloader::{
+        downloader::download_video_from_url, errors::MediaDownloaderError,
+        video_metadata::VideoMetadata,
+    },
+    retrieve_video,
+    services::{OversizePolicy, RedisManager},
+    MessageContent, TWITTER_DOMAINS,
+};
+
+const FXTWITTER_API_URL: &str = "https://api.fxtwitter.com/status/";
+
+#[derive(Clone, Debug)]
+pub struct TwitterProcessor {
+    id: String,
+    url: String,
+    http_client: HttpClient,
+    redis: RedisManager,
+    config: Arc<ProcessorConfig>,
+    oversize: OversizePolicy,
+    metadata: Option<VideoMetadata>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FxTwitterResponse {
+    tweet: Option<Tweet>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Tweet {
+    url: Option<String>,
+    text: Option<String>,
+    author: Option<TweetAuthor>,
+    media: Option<TweetMedia>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TweetAuthor {
+    name: Option<String>,
+    screen_name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TweetMedia {
+    all: Vec<TweetMediaItem>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TweetMediaItem {
+    #[serde(rename = "type")]
+    kind: String,
+    url: String,
+    width: Option<u32>,
+    height: Option<u32>,
+    /// In seconds
+    duration: Option<f64>,
+}
+
+impl TwitterProcessor {
+    pub fn new(id: String, url: String, context: &ProcessorContext) -> TwitterProcessor {
+        TwitterProcessor {
+            id,
+            url,
+            http_client: context.http_client.clone(),
+            redis: context.redis.clone(),
+            config: context.config.clone(),
+            oversize: context.chat_settings.oversize,
+            metadata: None,
+        }
+    }
+
+    pub fn get_id(&self) -> String {
+        self.id.to_string()
+    }
+
+    pub fn descriptor() -> ProcessorDescriptor {
+        ProcessorDescriptor {
+            name: "twitter",
+            stage: FallbackStage::Dedicated,
+            priority: DEDICATED_PROCESSOR_PRIORITY,
+            claims: is_twitter_url,
+            build: |url_id, url, context| {
+                Box::new(TwitterProcessor::new(
+                    url_id.to_string(),
+                    url.to_string(),
+                    context,
+                ))
+            },
+        }
+    }
+}
+
+#[async_trait]
+impl Processor for TwitterProcessor {
+    #[instrument(level = "debug", name = "process_twitter", skip(self))]
+    async fn process(&mut self) -> Result<Option<MessageContent>, Box<dyn Error + Send>> {
+        debug!("Processing Twitter: {}", self.url);
+
+        match extract_tweet_id(&self.url) {
+            Some(id) => self.id = id.to_string(),
+            None => {
+                error!("Failed to extract tweet ID from {:?}", self.url);
+                return Err(Box::new(MediaDownloaderError::CouldNotExtractId));
+            }
+        }
+
+        let content = self
+            .http_client
+            .fetch(
+                &format!(
+                    "{}{}",
+                    self.http_client.base_url("fxtwitter", FXTWITTER_API_URL),
+                    self.id
+                ),
+                None,
+                None,
+                None,
+                None,
+                Some(vec![("Accept", "application/json")]),
+            )
+            .await
+            .map_err(|e| {
+                error!("Error fetching tweet: {}", e);
+                Box::new(MediaDownloaderError::UnreachableResource) as Box<dyn Error + Send>
+            })?;
+
+        if !content.status().is_success() {
+            error!(
+                "Error: Request failed with status code {:?}",
+                content.status()
+            );
+            return Err(Box::new(MediaDownloaderError::UnreachableResource));
+        }
+
+        let body = content.text().await.unwrap_or_default();
+        let (media, metadata) = parse_tweet_media(&body)?;
+        self.metadata = Some(metadata);
+
+        // Single videos and GIFs (delivered by Twitter as mp4) are sent as they are
+        if let [RemoteMedia::Video(video_url)] = media.as_slice() {
+            download_video_from_url(
+                &self.http_client,
+                &self.redis,
+                &self.url,
+                video_url,
+                &self.id,
+                None,
+                None,
+            )
+            .await?;
+            return Ok(Some(
+                retrieve_video(&self.redis, &self.config, &self.id, self.oversize).await?,
+            ));
+        }
+
+        debug!("Tweet {:?} has {} media", self.id, media.len());
+        let group = download_media_group(
+            &self.http_client,
+            &self.redis,
+            &self.config,
+            &self.url,
+            &self.id,
+            media,
+        )
+        .await?;
+        Ok(Some(MessageContent::Images(group)))
+    }
+
+    fn metadata(&self) -> Option<VideoMetadata> {
+        self.metadata.clone()
+    }
+}
+
+/// Whether the URL belongs to Twitter/X or to one of its embed-fixing mirrors
+pub fn is_twitter_url(url: &str) -> bool {
+    url_matches_domains(url, &TWITTER_DOMAINS)
+}
+
+/// Extracts the status ID of a tweet from its URL
+#[instrument(level = "debug", name = "extract_tweet_id")]
+fn extract_tweet_id(url: &str) -> Option<&str> {
+    let re = Regex::new(r"/status(?:es)?/(\d+)").unwrap();
+    re.captures(url)
+        .and_then(|captures| captures.get(1))
+        .map(|m| m.as_str())
+}
+
+/// Parses the media attached to a tweet, in order, along with its author and text
+#[instrument(level = "debug", name = "parse_tweet_media", skip_all)]
+fn parse_tweet_media(
+    body: &str,
+) -> Result<(Vec<RemoteMedia>, VideoMetadata), Box<dyn Error + Send>> {
+    let response: FxTwitterResponse = serde_json::from_str(body).map_err(|e| {
+        error!("Error parsing tweet: {}", e);
+        Box::new(MediaDownloaderError::ParsingError) as Box<dyn Error + Send>
+    })?;
+
+    let Some(tweet) = response.tweet else {
+        error!("Tweet not found");
+        return Err(Box::new(MediaDownloaderError::ParsingError));
+    };
+    let items = tweet.media.map(|m| m.all).unwrap_or_default();
+
+    let mut metadata = VideoMetadata {
+        description: tweet.text,
+        uploader: tweet.author.as_ref().and_then(|a| a.name.clone()),
+        uploader_id: tweet
+            .author
+            .and_then(|a| a.screen_name)
+            .map(|screen_name| format!("@{}", screen_name)),
+        webpage_url: tweet.url,
+        ..Default::default()
+    };
+    if let [item] = items.as_slice() {
+        metadata.width = item.width;
+        metadata.height = item.height;
+        metadata.duration = item.duration.filter(|d| *d > 0.0);
+    }
+
+    let media: Vec<RemoteMedia> = items
+        .into_iter()
+        .filter_map(|item| match item.kind.as_str() {
+            "photo" => Some(RemoteMedia::Photo(item.url)),
+            "video" | "gif" => Some(RemoteMedia::Video(item.url)),
+            unknown => {
+                warn!("Skipping unknown media type `{}`", unknown);
+                None
+            }
+        })
+        .collect();
+
+    if media.is_empty() {
+        error!("Tweet has no media attached");
+        return Err(Box::new(MediaDownloaderError::ParsingError));
+    }
+    Ok((media, metadata))
+}
+
+#[cfg(test)]
+mod twitter_processor_test {
+    use super::*;
+
+    #[test]
+    fn test_twitter_urls() {
+        let urls = [
+            "https://x.com/someone/status/1780000000000000000",
+            "https://twitter.com/someone/status/1780000000000000000?s=20",
+            "https://mobile.twitter.com/someone/status/1780000000000000000",
+            "https://fxtwitter.com/someone/status/1780000000000000000",
+            "https://vxtwitter.com/someone/status/1780000000000000000/photo/1",
+        ];
+
+        for url in urls {
+            assert!(is_twitter_url(url));
+            assert_eq!(extract_tweet_id(url), Some("1780000000000000000"));
+        }
+        assert!(!is_twitter_url("https://dropbox.com/s/1780000000000000000"));
+    }
+
+    #[test]
+    fn test_parse_mixed_media() {
+        let body = r#"{"code":200,"tweet":{"text":"Three of them","author":{"name":"Someone","screen_name":"someone"},"media":{"all":[
+            {"type":"photo","url":"https://pbs.twimg.com/media/1.jpg"},
+            {"type":"gif","url":"https://video.twimg.com/tweet_video/2.mp4"},
+            {"type":"video","url":"https://video.twimg.com/ext_tw_video/3.mp4"}
+        ]}}}"#;
+
+        let (media, metadata) = parse_tweet_media(body).unwrap();
+        assert_eq!(
+            media,
+            vec![
+                RemoteMedia::Photo("https://pbs.twimg.com/media/1.jpg".to_string()),
+                RemoteMedia::Video("https://video.twimg.com/tweet_video/2.mp4".to_string()),
+                RemoteMedia::Video("https://video.twimg.com/ext_tw_video/3.mp4".to_string()),
+            ]
+        );
+        assert_eq!(metadata.author().as_deref(), Some("@someone"));
+        assert_eq!(metadata.description.as_deref(), Some("Three of them"));
+        assert_eq!(metadata.resolution(), None);
+    }
+}
@@ -0,0 +1,205 @@
+use std::{error::Error, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use serde::Deserialize;
+use tracing::{debug, instrument};
+
+use super::processor::{Processor, ProcessorConfig, ProcessorContext};
+use super::registry::{FallbackStage, ProcessorDescriptor};
+use crate::media_downloader::{http_client::HttpClient, progress::ProgressReporter};
+use crate::{
+    media_downloader::{
+        downloader::{download_video, fetch_video_metadata},
+        errors::MediaDownloaderError,
+        formatter::UrlFormatter,
+        video_metadata::{FormatPick, VideoMetadata},
+    },
+    retrieve_video,
+    services::{OversizePolicy, RedisManager},
+    MessageContent, VIDEO_EXTENSIONS_FORMAT,
+};
+
+const DEFAULT_YT_DLP_TIMEOUT_SECONDS: u64 = 600;
+/// The resolution downloaded when no format fits the size limit, before being fitted
+const OVERSIZE_MAX_HEIGHT: u32 = 720;
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct YtDlpConfig {
+    /// How long a download is given before `yt-dlp` is killed
+    pub timeout_seconds: Option<u64>,
+}
+
+impl YtDlpConfig {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(
+            self.timeout_seconds
+                .unwrap_or(DEFAULT_YT_DLP_TIMEOUT_SECONDS),
+        )
+    }
+}
+
+/// Downloads any resource supported by `yt-dlp`
+#[derive(Clone, Debug)]
+pub struct YtDlpProcessor {
+    id: String,
+    url: String,
+    http_client: HttpClient,
+    redis: RedisManager,
+    config: Arc<ProcessorConfig>,
+    oversize: OversizePolicy,
+    progress: ProgressReporter,
+    metadata: Option<VideoMetadata>,
+}
+
+impl YtDlpProcessor {
+    pub fn new(id: String, url: String, context: &ProcessorContext) -> YtDlpProcessor {
+        YtDlpProcessor {
+            id,
+            url,
+            http_client: context.http_client.clone(),
+            redis: context.redis.clone(),
+            config: context.config.clone(),
+            oversize: context.chat_settings.oversize,
+            progress: context.progress.clone(),
+            metadata: None,
+        }
+    }
+
+    pub fn descriptor() -> ProcessorDescriptor {
+        ProcessorDescriptor {
+            name: "yt_dlp",
+            stage: FallbackStage::YtDlp,
+            priority: 0,
+            claims: |_| true,
+            build: |url_id, url, context| {
+                Box::new(YtDlpProcessor::new(
+                    url_id.to_string(),
+                    url.to_string(),
+                    context,
+                ))
+            },
+        }
+    }
+}
+
+impl YtDlpProcessor {
+    /// Extracts the metadata of the video and picks the format fitting the size limit,
+    /// the metadata is kept to caption the video and stored once it is downloaded
+    /// Videos whose metadata is already stored were downloaded already
+    /// # Arguments
+    /// * `url` - The `UrlFormatter` of the video
+    /// * `proxy` - (`Option`) The proxy `yt-dlp` goes through
+    /// # Returns
+    /// * `Option<String>` - The format selector, `None` to let `yt-dlp` pick
+    /// # Errors
+    /// * `MediaDownloaderError::FileSizeExceeded` - No format fits the size limit and the chat
+    ///   rejects oversized videos
+    #[instrument(level = "debug", name = "pick_yt_dlp_format", skip(self, url))]
+    async fn pick_format(
+        &mut self,
+        url: &UrlFormatter,
+        proxy: Option<String>,
+    ) -> Result<Option<String>, Box<dyn Error + Send>> {
+        if let Ok(Some(metadata)) = self.redis.get_video_metadata(&self.id).await {
+            debug!("Metadata of `{}` already stored", self.id);
+            self.metadata = Some(metadata);
+            return Ok(None);
+        }
+
+        let metadata = match fetch_video_metadata(url, proxy, self.config.yt_dlp.timeout()).await {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                warn!("Downloading without metadata ~ {:?}", e);
+                return Ok(None);
+            }
+        };
+
+        let format = match metadata.pick_format(self.config.file_size_limits.video) {
+            FormatPick::Fits(format) => Some(format),
+            FormatPick::TooLarge if self.oversize == OversizePolicy::Reject => {
+                error!("No format of `{}` fits the size limit", self.url);
+                return Err(Box::new(MediaDownloaderError::FileSizeExceeded));
+            }
+            // Fitted once downloaded, no point in shrinking a higher resolution
+            FormatPick::TooLarge => Some(format!(
+                "bestvideo[ext={ext}][height<={h}]+bestaudio[ext=m4a]/best[ext={ext}][height<={h}]/{ext}",
+                ext = VIDEO_EXTENSIONS_FORMAT,
+                h = OVERSIZE_MAX_HEIGHT
+            )),
+            FormatPick::Unknown => None,
+        };
+        debug!("Picked format {:?}", format);
+
+        self.metadata = Some(metadata);
+        Ok(format)
+    }
+
+    /// Downloads the video through the proxies of the site in turn, the ones `yt-dlp`
+    /// cannot go through are marked as dead
+    /// # Arguments
+    /// * `url` - The `UrlFormatter` of the video
+    /// # Errors
+    /// * `MediaDownloaderError::ProxyError` - `yt-dlp` could not go through any proxy
+    /// * Any other error of the format pick or of the download
+    async fn download(&mut self, url: &UrlFormatter) -> Result<(), Box<dyn Error + Send>> {
+        let mut last_error: Box<dyn Error + Send> = Box::new(MediaDownloaderError::DownloadError);
+
+        for proxy in self.http_client.proxies_for(&self.url) {
+            let format = self.pick_format(url, proxy.clone()).await?;
+            let Err(e) = download_video(
+                &self.redis,
+                url,
+                self.id.clone(),
+                proxy.clone(),
+                format,
+                self.config.yt_dlp.timeout(),
+                &self.progress,
+            )
+            .await
+            else {
+                return Ok(());
+            };
+
+            error!("Error downloading video `{}`: {:?}", self.url, e);
+            match (e.downcast_ref(), proxy) {
+                (Some(MediaDownloaderError::ProxyError), Some(proxy)) => {
+                    self.http_client.mark_proxy_dead(&proxy);
+                    last_error = e;
+                }
+                _ => return Err(e),
+            }
+        }
+        Err(last_error)
+    }
+
+    /// Stores the metadata along with the downloaded video, so that a later request
+    /// for the same video skips the extraction
+    async fn store_metadata(&self) {
+        let Some(metadata) = &self.metadata else {
+            return;
+        };
+        if let Err(e) = self.redis.set_video_metadata(&self.id, metadata).await {
+            error!("Failed to store video metadata: {:?}", e);
+        }
+    }
+}
+
+#[async_trait]
+impl Processor for YtDlpProcessor {
+    #[instrument(level = "debug", name = "process_yt_dlp", skip(self))]
+    async fn process(&mut self) -> Result<Option<MessageContent>, Box<dyn Error + Send>> {
+        let url_formatted = UrlFormatter::new(&self.url);
+
+        self.download(&url_formatted).await?;
+        self.store_metadata().await;
+
+        debug!("Successfully obtained video: `{}`", self.url);
+        Ok(Some(
+            retrieve_video(&self.redis, &self.config, &self.id, self.oversize).await?,
+        ))
+    }
+
+    fn metadata(&self) -> Option<VideoMetadata> {
+        self.metadata.clone()
+    }
+}
@@ -0,0 +1,202 @@
+use std::{sync::Arc, time::Duration};
+
+use frankenstein::{
+    AsyncApi, AsyncTelegramApi, DeleteMessageParams, EditMessageTextParams, SendMessageParams,
+};
+use tokio::{
+    sync::{oneshot, watch},
+    time::Instant,
+};
+use tracing::{debug, instrument};
+
+use crate::{DOWNLOADING, PROGRESS_UPDATE_INTERVAL};
+
+/// Reports the progress of a download, as a percentage
+/// Reporting is a no-op when no chat tracks the progress
+#[derive(Debug, Clone, Default)]
+pub struct ProgressReporter {
+    sender: Option<Arc<watch::Sender<Option<u8>>>>,
+}
+
+impl ProgressReporter {
+    pub fn report(&self, percent: u8) {
+        if let Some(sender) = &self.sender {
+            sender.send_if_modified(|current| {
+                let changed = *current != Some(percent);
+                *current = Some(percent);
+                changed
+            });
+        }
+    }
+
+    /// Reports the bytes downloaded so far, the total is unknown for chunked responses
+    pub fn report_bytes(&self, downloaded: u64, total: Option<u64>) {
+        if let Some(total) = total.filter(|t| *t > 0) {
+            self.report((downloaded.min(total) * 100 / total) as u8);
+        }
+    }
+}
+
+/// Shows the progress of a download in the chat it was requested from
+/// The status message is only sent once the download takes longer than the update
+/// interval, and is deleted when the tracker is dropped
+pub struct ProgressTracker {
+    reporter: ProgressReporter,
+    done: Option<oneshot::Sender<()>>,
+}
+
+impl ProgressTracker {
+    /// Starts tracking the progress reported through the tracker's reporter
+    /// # Arguments
+    /// * `chat_id` - The chat the download was requested from
+    /// * `message_id` - The message requesting the download
+    /// * `api` - The api to send the status message with
+    pub fn start(chat_id: i64, message_id: i32, api: AsyncApi) -> ProgressTracker {
+        let (sender, receiver) = watch::channel(None);
+        let (done, done_receiver) = oneshot::channel();
+
+        tokio::spawn(show_progress(
+            chat_id,
+            message_id,
+            api,
+            receiver,
+            done_receiver,
+        ));
+
+        ProgressTracker {
+            reporter: ProgressReporter {
+                sender: Some(Arc::new(sender)),
+            },
+            done: Some(done),
+        }
+    }
+
+    pub fn reporter(&self) -> ProgressReporter {
+        self.reporter.clone()
+    }
+}
+
+impl Drop for ProgressTracker {
+    fn drop(&mut self) {
+        if let Some(done) = self.done.take() {
+            let _ = done.send(());
+        }
+    }
+}
+
+fn progress_text(percent: u8) -> String {
+    format!("{} Downloading… {}%", DOWNLOADING, percent)
+}
+
+/// Sends and edits the status message as the progress changes, at most once per interval
+#[instrument(level = "debug", name = "show_progress", skip(api, receiver, done))]
+async fn show_progress(
+    chat_id: i64,
+    message_id: i32,
+    api: AsyncApi,
+    mut receiver: watch::Receiver<Option<u8>>,
+    mut done: oneshot::Receiver<()>,
+) {
+    let mut next_update = Instant::now() + PROGRESS_UPDATE_INTERVAL;
+    let mut status_message_id: Option<i32> = None;
+    let mut shown: Option<u8> = None;
+
+    loop {
+        tokio::select! {
+            _ = &mut done => break,
+            _ = tokio::time::sleep_until(next_update) => {}
+        }
+        next_update = Instant::now() + PROGRESS_UPDATE_INTERVAL;
+
+        let percent = *receiver.borrow_and_update();
+        let Some(percent) = percent.filter(|p| shown != Some(*p)) else {
+            continue;
+        };
+        shown = Some(percent);
+
+        match status_message_id {
+            None => {
+                let params = SendMessageParams::builder()
+                    .chat_id(chat_id)
+                    .reply_to_message_id(message_id)
+                    .text(progress_text(percent))
+                    .build();
+                match api.send_message(&params).await {
+                    Ok(response) => status_message_id = Some(response.result.message_id),
+                    Err(e) => error!("Failed to send progress: {:?}", e),
+                }
+            }
+            Some(status_message_id) => {
+                let params = EditMessageTextParams::builder()
+                    .chat_id(chat_id)
+                    .message_id(status_message_id)
+                    .text(progress_text(percent))
+                    .build();
+                if let Err(e) = api.edit_message_text(&params).await {
+                    debug!("Failed to update progress: {:?}", e);
+                }
+            }
+        }
+    }
+
+    if let Some(status_message_id) = status_message_id {
+        let params = DeleteMessageParams::builder()
+            .chat_id(chat_id)
+            .message_id(status_message_id)
+            .build();
+        if let Err(e) = api.delete_message(&params).await {
+            error!("Failed to delete progress: {:?}", e);
+        }
+    }
+}
+
+/// Parses the percentage of a `yt-dlp` progress line (printed with `--newline`)
+/// e.g. `[download]  42.3% of   12.34MiB at    1.23MiB/s ETA 00:05`
+pub fn parse_yt_dlp_progress(line: &str) -> Option<u8> {
+    let percent = line
+        .strip_prefix("[download]")?
+        .trim_start()
+        .split_once('%')?
+        .0;
+    percent
+        .parse::<f32>()
+        .ok()
+        .map(|p| p.clamp(0.0, 100.0) as u8)
+}
+
+#[cfg(test)]
+mod progress_test {
+    use super::*;
+
+    #[test]
+    fn test_parse_yt_dlp_progress() {
+        assert_eq!(
+            parse_yt_dlp_progress("[download]  42.3% of   12.34MiB at    1.23MiB/s ETA 00:05"),
+            Some(42)
+        );
+        assert_eq!(
+            parse_yt_dlp_progress("[download] 100% of   12.34MiB in 00:00:10"),
+            Some(100)
+        );
+        assert_eq!(
+            parse_yt_dlp_progress("[download] Destination: /tmp/media_downloaded/x.mp4"),
+            None
+        );
+        assert_eq!(parse_yt_dlp_progress("[info] 42% done"), None);
+    }
+
+    #[tokio::test]
+    async fn test_reporter_keeps_the_latest_progress() {
+        let (sender, receiver) = watch::channel(None);
+        let reporter = ProgressReporter {
+            sender: Some(Arc::new(sender)),
+        };
+
+        reporter.report_bytes(512, Some(2048));
+        assert_eq!(*receiver.borrow(), Some(25));
+        reporter.report_bytes(4096, None);
+        assert_eq!(*receiver.borrow(), Some(25));
+
+        ProgressReporter::default().report(50);
+    }
+}
@@ -0,0 +1,89 @@
+use serde::Deserialize;
+use std::time::Duration;
+
+const DEFAULT_MAX_ATTEMPTS: u32 = 3;
+const DEFAULT_BACKOFF_SECONDS: u64 = 30;
+const DEFAULT_MAX_BACKOFF_SECONDS: u64 = 3600;
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct RetryConfig {
+    pub max_attempts: Option<u32>,
+    pub backoff_seconds: Option<u64>,
+    pub max_backoff_seconds: Option<u64>,
+}
+
+/// Policy applied to failed download jobs before they end up in the dead-letter queue
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    pub fn new(config: &RetryConfig) -> Self {
+        Self {
+            max_attempts: config.max_attempts.unwrap_or(DEFAULT_MAX_ATTEMPTS),
+            backoff: Duration::from_secs(config.backoff_seconds.unwrap_or(DEFAULT_BACKOFF_SECONDS)),
+            max_backoff: Duration::from_secs(
+                config
+                    .max_backoff_seconds
+                    .unwrap_or(DEFAULT_MAX_BACKOFF_SECONDS),
+            ),
+        }
+    }
+
+    /// Whether a job that already failed `attempts` times can be retried
+    pub fn should_retry(&self, attempts: u32) -> bool {
+        attempts < self.max_attempts
+    }
+
+    /// Exponential backoff before the next attempt, capped to `max_backoff`
+    /// # Arguments
+    /// * `attempts` - The number of failed attempts so far (starting from 1)
+    pub fn backoff(&self, attempts: u32) -> Duration {
+        let exponent = attempts.saturating_sub(1).min(16);
+        self.backoff
+            .saturating_mul(2u32.pow(exponent))
+            .min(self.max_backoff)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy::new(&RetryConfig::default())
+    }
+}
+
+#[cfg(test)]
+mod retry_policy_test {
+    use super::*;
+
+    #[test]
+    fn test_default_policy() {
+        let policy = RetryPolicy::default();
+
+        assert!(policy.should_retry(1));
+        assert!(policy.should_retry(2));
+        assert!(!policy.should_retry(3));
+    }
+
+    #[test]
+    fn test_backoff_is_exponential_and_capped() {
+        let config: RetryConfig = toml::from_str(
+            r#"
+    max_attempts = 10
+    backoff_seconds = 10
+    max_backoff_seconds = 60
+    "#,
+        )
+        .unwrap();
+        let policy = RetryPolicy::new(&config);
+
+        assert_eq!(policy.backoff(1), Duration::from_secs(10));
+        assert_eq!(policy.backoff(2), Duration::from_secs(20));
+        assert_eq!(policy.backoff(3), Duration::from_secs(40));
+        assert_eq!(policy.backoff(4), Duration::from_secs(60));
+        assert_eq!(policy.backoff(100), Duration::from_secs(60));
+    }
+}
@@ -25,15 +25,13 @@ mod site_validator_test {
     use super::*;
 
     fn setup() -> SupportedSites {
-        let supported_sites = toml::from_str(
+        toml::from_str(
             r#"
     sites = ['site_1', 'site_2']
 
     "#,
         )
-        .unwrap();
-
-        supported_sites
+        .unwrap()
     }
 
     #[test]
@@ -47,7 +45,7 @@ mod site_validator_test {
         let mocked_sites = setup();
         let supported_site = "site_1";
 
-        assert_eq!(mocked_sites.is_supported(supported_site), true);
+        assert!(mocked_sites.is_supported(supported_site));
     }
 
     #[test]
@@ -55,6 +53,6 @@ mod site_validator_test {
         let mocked_sites = setup();
         let unsupported_site = "site_that_should_not_be_supported";
 
-        assert_eq!(mocked_sites.is_supported(unsupported_site), false);
+        assert!(!mocked_sites.is_supported(unsupported_site));
     }
 }
@@ -0,0 +1,359 @@
+use std::{error::Error, time::Duration};
+
+use serde::Deserialize;
+use tracing::instrument;
+
+use super::{downloader::run_with_timeout, errors::MediaDownloaderError};
+use crate::{services::RedisManager, TARGET_DIRECTORY, VIDEO_EXTENSIONS_FORMAT};
+
+const DEFAULT_TRANSCODING_TIMEOUT_SECONDS: u64 = 900;
+const AUDIO_BITRATE_KBPS: u64 = 128;
+/// Below this, the video is not worth watching anymore
+const MIN_VIDEO_BITRATE_KBPS: u64 = 200;
+/// The minimum video bitrate each resolution is kept at, highest first
+const DOWNSCALE_STEPS: [(u64, u32); 4] = [(2500, 1080), (1200, 720), (600, 480), (0, 360)];
+/// Room left for the container overhead and the encoder overshooting the bitrate
+const SIZE_MARGIN: f64 = 0.92;
+/// Parts are cut on keyframes, so they are aimed well below the limit
+const SPLIT_MARGIN: f64 = 0.85;
+const FIT_ID_SUFFIX: &str = "_fit";
+const PART_ID_SUFFIX: &str = "_part";
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct TranscodingConfig {
+    /// How long re-encoding or splitting a video is given before `ffmpeg` is killed
+    pub timeout_seconds: Option<u64>,
+}
+
+impl TranscodingConfig {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(
+            self.timeout_seconds
+                .unwrap_or(DEFAULT_TRANSCODING_TIMEOUT_SECONDS),
+        )
+    }
+}
+
+/// The settings a video is re-encoded with to fit the size limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Encoding {
+    pub video_bitrate_kbps: u64,
+    pub max_height: u32,
+}
+
+/// Computes the bitrate a video has to be re-encoded at to fit the size limit,
+/// lowering the resolution along with the bitrate
+/// # Arguments
+/// * `duration_seconds` - The duration of the video
+/// * `max_size` - The size limit, in bytes
+/// # Returns
+/// * `Option<Encoding>` - `None` when the video is too long to fit at a watchable bitrate
+pub fn target_encoding(duration_seconds: f64, max_size: u64) -> Option<Encoding> {
+    if duration_seconds <= 0.0 {
+        return None;
+    }
+    let total_kbps = (max_size as f64 * 8.0 * SIZE_MARGIN / duration_seconds / 1000.0) as u64;
+    let video_bitrate_kbps = total_kbps.checked_sub(AUDIO_BITRATE_KBPS)?;
+    if video_bitrate_kbps < MIN_VIDEO_BITRATE_KBPS {
+        return None;
+    }
+
+    let max_height = DOWNSCALE_STEPS
+        .iter()
+        .find(|(min_kbps, _)| video_bitrate_kbps >= *min_kbps)
+        .map(|(_, height)| *height)?;
+    Some(Encoding {
+        video_bitrate_kbps,
+        max_height,
+    })
+}
+
+/// The number of parts a video has to be split into to fit the size limit
+pub fn split_count(file_size: u64, max_size: u64) -> u64 {
+    let part_size = (max_size as f64 * SPLIT_MARGIN) as u64;
+    file_size.div_ceil(part_size.max(1)).max(1)
+}
+
+fn video_path(id: &str) -> String {
+    format!("{}{}.{}", TARGET_DIRECTORY, id, VIDEO_EXTENSIONS_FORMAT)
+}
+
+/// Re-encodes a downloaded video in two passes at the bitrate fitting the size limit
+/// The re-encoded video is kept next to the original one, recorded like a download
+/// so that it is reused until it expires
+/// # Arguments
+/// * `redis` - Where the re-encoded video is recorded
+/// * `url_id` - The ID of the downloaded video
+/// * `max_size` - The size limit, in bytes
+/// * `timeout` - How long each `ffmpeg` pass is given before being killed
+/// # Returns
+/// * `String` - The ID of the re-encoded video
+/// # Errors
+/// * `MediaDownloaderError::FileSizeExceeded` - The video is too long to fit the size limit
+/// * `MediaDownloaderError::Timeout` - `ffmpeg` did not finish in time
+/// * `MediaDownloaderError::TranscodingError` - `ffmpeg` could not be run or failed
+#[instrument(level = "debug", name = "reencode_to_fit", skip(redis))]
+pub async fn reencode_to_fit(
+    redis: &RedisManager,
+    url_id: &str,
+    max_size: u64,
+    timeout: Duration,
+) -> Result<String, Box<dyn Error + Send>> {
+    let fit_id = format!("{}{}", url_id, FIT_ID_SUFFIX);
+    let output_path = video_path(&fit_id);
+    if redis.get(&fit_id).await.is_ok()
+        && tokio::fs::try_exists(&output_path).await.unwrap_or(false)
+    {
+        debug!("Video already re-encoded!");
+        return Ok(fit_id);
+    }
+
+    let input_path = video_path(url_id);
+    let duration = probe_duration(&input_path, timeout).await?;
+    let Some(encoding) = target_encoding(duration, max_size) else {
+        error!("`{}` lasts too long to fit {} bytes", url_id, max_size);
+        return Err(Box::new(MediaDownloaderError::FileSizeExceeded));
+    };
+    debug!("Re-encoding `{}` with {:?}", url_id, encoding);
+
+    let passlog_path = format!("{}{}", TARGET_DIRECTORY, fit_id);
+    let scale = format!("scale=-2:min(ih\\,{})", encoding.max_height);
+    let bitrate = format!("{}k", encoding.video_bitrate_kbps);
+    let audio_bitrate = format!("{}k", AUDIO_BITRATE_KBPS);
+    let passes: [&[&str]; 2] = [
+        &["-pass", "1", "-an", "-f", "null", "/dev/null"],
+        &[
+            "-pass",
+            "2",
+            "-c:a",
+            "aac",
+            "-b:a",
+            &audio_bitrate,
+            "-movflags",
+            "+faststart",
+            &output_path,
+        ],
+    ];
+
+    for pass in passes {
+        let mut command = tokio::process::Command::new("ffmpeg");
+        command
+            .args(["-y", "-loglevel", "error", "-i", &input_path])
+            .args(["-vf", &scale, "-c:v", "libx264", "-pix_fmt", "yuv420p"])
+            .args(["-b:v", &bitrate, "-passlogfile", &passlog_path])
+            .args(pass);
+        if let Err(e) = run_ffmpeg(&mut command, timeout).await {
+            let _ = tokio::fs::remove_file(&output_path).await;
+            return Err(e);
+        }
+    }
+
+    for suffix in ["-0.log", "-0.log.mbtree"] {
+        let _ = tokio::fs::remove_file(format!("{}{}", passlog_path, suffix)).await;
+    }
+    let _ = redis.set(&fit_id, &output_path).await;
+    Ok(fit_id)
+}
+
+/// Splits a downloaded video into numbered parts fitting the size limit, without re-encoding
+/// The parts of a previous split are replaced, the new ones are recorded like downloads
+/// # Arguments
+/// * `redis` - Where the parts are recorded
+/// * `url_id` - The ID of the downloaded video
+/// * `file_size` - The size of the video, in bytes
+/// * `max_size` - The size limit, in bytes
+/// * `timeout` - How long `ffmpeg` is given before being killed
+/// # Returns
+/// * `Vec<String>` - The IDs of the parts, in order
+/// # Errors
+/// * `MediaDownloaderError::FileSizeExceeded` - A part exceeds the size limit
+/// * `MediaDownloaderError::Timeout` - `ffmpeg` did not finish in time
+/// * `MediaDownloaderError::TranscodingError` - `ffmpeg` could not be run or failed
+#[instrument(level = "debug", name = "split_to_fit", skip(redis))]
+pub async fn split_to_fit(
+    redis: &RedisManager,
+    url_id: &str,
+    file_size: u64,
+    max_size: u64,
+    timeout: Duration,
+) -> Result<Vec<String>, Box<dyn Error + Send>> {
+    let input_path = video_path(url_id);
+    let duration = probe_duration(&input_path, timeout).await?;
+    let parts = split_count(file_size, max_size);
+    let segment_seconds = duration / parts as f64;
+    debug!(
+        "Splitting `{}` into {} parts of {:.1}s",
+        url_id, parts, segment_seconds
+    );
+
+    let part_prefix = format!("{}{}", url_id, PART_ID_SUFFIX);
+    remove_parts(redis, &written_parts(&part_prefix).await).await;
+
+    let mut command = tokio::process::Command::new("ffmpeg");
+    command
+        .args(["-y", "-loglevel", "error", "-i", &input_path])
+        .args(["-map", "0", "-c", "copy", "-f", "segment"])
+        .args(["-segment_time", &format!("{:.3}", segment_seconds)])
+        .args(["-segment_format", VIDEO_EXTENSIONS_FORMAT])
+        .args(["-reset_timestamps", "1"])
+        .arg(format!(
+            "{}{}%d.{}",
+            TARGET_DIRECTORY, part_prefix, VIDEO_EXTENSIONS_FORMAT
+        ));
+    if let Err(e) = run_ffmpeg(&mut command, timeout).await {
+        remove_parts(redis, &written_parts(&part_prefix).await).await;
+        return Err(e);
+    }
+
+    // Keyframes might add a trailing part
+    let part_ids = written_parts(&part_prefix).await;
+    if part_ids.is_empty() {
+        error!("No part of `{}` was written", url_id);
+        return Err(Box::new(MediaDownloaderError::TranscodingError));
+    }
+
+    for part_id in &part_ids {
+        let part_size = tokio::fs::metadata(video_path(part_id))
+            .await
+            .map(|metadata| metadata.len())
+            .unwrap_or(u64::MAX);
+        if part_size > max_size {
+            error!(
+                "Part `{}` [{}] is greater than {}!",
+                part_id, part_size, max_size
+            );
+            remove_parts(redis, &part_ids).await;
+            return Err(Box::new(MediaDownloaderError::FileSizeExceeded));
+        }
+    }
+
+    for part_id in &part_ids {
+        let _ = redis.set(part_id, &video_path(part_id)).await;
+    }
+    Ok(part_ids)
+}
+
+/// The IDs of the parts written under the prefix, numbered from `0` without gaps
+async fn written_parts(part_prefix: &str) -> Vec<String> {
+    let mut part_ids = Vec::new();
+    loop {
+        let part_id = format!("{}{}", part_prefix, part_ids.len());
+        if !tokio::fs::try_exists(video_path(&part_id))
+            .await
+            .unwrap_or(false)
+        {
+            return part_ids;
+        }
+        part_ids.push(part_id);
+    }
+}
+
+/// Removes the parts along with their records
+async fn remove_parts(redis: &RedisManager, part_ids: &[String]) {
+    for part_id in part_ids {
+        let _ = redis.del(part_id).await;
+        let _ = tokio::fs::remove_file(video_path(part_id)).await;
+    }
+}
+
+/// Reads the duration of a video, in seconds
+async fn probe_duration(path: &str, timeout: Duration) -> Result<f64, Box<dyn Error + Send>> {
+    let mut command = tokio::process::Command::new("ffprobe");
+    command
+        .args(["-v", "error", "-show_entries", "format=duration"])
+        .args(["-of", "default=noprint_wrappers=1:nokey=1", path]);
+    let output = run_ffmpeg(&mut command, timeout).await?;
+
+    String::from_utf8_lossy(&output.stdout)
+        .trim()
+        .parse::<f64>()
+        .map_err(|e| {
+            error!("Error parsing the duration of `{}`: {}", path, e);
+            Box::new(MediaDownloaderError::TranscodingError) as Box<dyn Error + Send>
+        })
+}
+
+/// Runs `ffmpeg` (or `ffprobe`) to completion, failing on a non-zero exit
+async fn run_ffmpeg(
+    command: &mut tokio::process::Command,
+    timeout: Duration,
+) -> Result<std::process::Output, Box<dyn Error + Send>> {
+    let output = run_with_timeout(command, timeout, |_| {})
+        .await
+        .map_err(|e| match e.downcast_ref() {
+            Some(MediaDownloaderError::Timeout) => e,
+            _ => Box::new(MediaDownloaderError::TranscodingError) as Box<dyn Error + Send>,
+        })?;
+
+    if !output.status.success() {
+        error!(
+            "ffmpeg exited with status {} ~ {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        return Err(Box::new(MediaDownloaderError::TranscodingError));
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod transcoder_test {
+    use super::*;
+    use crate::media_downloader::mock_server::MockRedis;
+
+    #[test]
+    fn test_target_encoding_and_split_count() {
+        let max_size = 50 * 1024 * 1024;
+
+        assert_eq!(
+            target_encoding(60.0, max_size),
+            Some(Encoding {
+                video_bitrate_kbps: 6303,
+                max_height: 1080,
+            })
+        );
+        assert_eq!(
+            target_encoding(5.0 * 60.0, max_size).map(|e| e.max_height),
+            Some(480)
+        );
+        assert_eq!(
+            target_encoding(10.0 * 60.0, max_size).map(|e| e.max_height),
+            Some(360)
+        );
+        assert_eq!(target_encoding(25.0 * 60.0, max_size), None);
+        assert_eq!(target_encoding(0.0, max_size), None);
+
+        assert_eq!(split_count(30 * 1024 * 1024, max_size), 1);
+        assert_eq!(split_count(120 * 1024 * 1024, max_size), 3);
+    }
+
+    #[tokio::test]
+    async fn test_stale_parts_are_removed() {
+        let redis = MockRedis::start().await;
+        let manager = redis.manager().await;
+        let part_prefix = format!("7300000000000000010{}", PART_ID_SUFFIX);
+        tokio::fs::create_dir_all(TARGET_DIRECTORY).await.unwrap();
+        for n in [0, 1, 3] {
+            let part_id = format!("{}{}", part_prefix, n);
+            tokio::fs::write(video_path(&part_id), "part")
+                .await
+                .unwrap();
+            manager.set(&part_id, &video_path(&part_id)).await.unwrap();
+        }
+
+        let part_ids = written_parts(&part_prefix).await;
+        assert_eq!(
+            part_ids,
+            vec![format!("{}0", part_prefix), format!("{}1", part_prefix)]
+        );
+
+        remove_parts(&manager, &part_ids).await;
+        let stray_id = format!("{}3", part_prefix);
+        let stray_left = tokio::fs::try_exists(video_path(&stray_id)).await.unwrap();
+        let _ = tokio::fs::remove_file(video_path(&stray_id)).await;
+
+        assert!(written_parts(&part_prefix).await.is_empty());
+        assert!(part_ids.iter().all(|part_id| redis.get(part_id).is_none()));
+        assert!(stray_left);
+    }
+}
@@ -0,0 +1,193 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    error::Error,
+    hash::{Hash, Hasher},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use rand::seq::SliceRandom;
+use reqwest::header::HeaderValue;
+use serde::Deserialize;
+use url::Url;
+
+const DESKTOP_USER_AGENTS: [&str; 5] = [
+    "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/124.0.0.0 Safari/537.36",
+    "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/124.0.0.0 Safari/537.36",
+    "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.4 Safari/605.1.15",
+    "Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:125.0) Gecko/20100101 Firefox/125.0",
+    "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/124.0.0.0 Safari/537.36",
+];
+const MOBILE_USER_AGENTS: [&str; 4] = [
+    "Mozilla/5.0 (iPhone; CPU iPhone OS 17_4 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.4 Mobile/15E148 Safari/604.1",
+    "Mozilla/5.0 (Linux; Android 14; Pixel 8) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/124.0.0.0 Mobile Safari/537.36",
+    "Mozilla/5.0 (Linux; Android 14; SM-S921B) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/124.0.0.0 Mobile Safari/537.36",
+    "Mozilla/5.0 (iPhone; CPU iPhone OS 17_4 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) CriOS/124.0.6367.88 Mobile/15E148 Safari/604.1",
+];
+
+/// The kind of device the requests are presented as
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum UserAgentProfile {
+    #[default]
+    Desktop,
+    Mobile,
+}
+
+/// How the user agent of a request is picked from its profile
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RotationPolicy {
+    /// A random one for every request
+    #[default]
+    Random,
+    /// Each one in turn
+    RoundRobin,
+    /// Always the same one for a given host
+    Sticky,
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct UserAgentConfig {
+    pub rotation: Option<RotationPolicy>,
+    pub default_profile: Option<UserAgentProfile>,
+    /// Replace the built-in user agents of the profile
+    pub desktop: Option<Vec<String>>,
+    pub mobile: Option<Vec<String>>,
+    /// The profile of each site, by domain (subdomains included)
+    pub sites: Option<HashMap<String, UserAgentProfile>>,
+}
+
+/// Built-in pool of user agents, used by requests not sending a specific one
+#[derive(Debug, Clone)]
+pub struct UserAgentPool {
+    desktop: Vec<String>,
+    mobile: Vec<String>,
+    default_profile: UserAgentProfile,
+    sites: HashMap<String, UserAgentProfile>,
+    rotation: RotationPolicy,
+    next: Arc<AtomicUsize>,
+}
+
+impl UserAgentPool {
+    /// Builds the pool from the configuration
+    /// # Errors
+    /// * One of the configured user agents is not a valid header value
+    pub fn new(config: &UserAgentConfig) -> Result<Self, Box<dyn Error>> {
+        let configured = config.desktop.iter().chain(config.mobile.iter()).flatten();
+        for user_agent in configured {
+            HeaderValue::from_str(user_agent)
+                .map_err(|e| format!("Invalid user agent `{}`: {}", user_agent, e))?;
+        }
+
+        let pool_or_builtin = |pool: &Option<Vec<String>>, builtin: &[&str]| match pool {
+            Some(pool) if !pool.is_empty() => pool.clone(),
+            _ => builtin.iter().map(|ua| ua.to_string()).collect(),
+        };
+
+        Ok(UserAgentPool {
+            desktop: pool_or_builtin(&config.desktop, &DESKTOP_USER_AGENTS),
+            mobile: pool_or_builtin(&config.mobile, &MOBILE_USER_AGENTS),
+            default_profile: config.default_profile.unwrap_or_default(),
+            sites: config.sites.clone().unwrap_or_default(),
+            rotation: config.rotation.unwrap_or_default(),
+            next: Arc::new(AtomicUsize::new(0)),
+        })
+    }
+
+    /// The profile configured for the host of the URL, the default one otherwise
+    pub fn profile(&self, url: &str) -> UserAgentProfile {
+        let host = host_of(url);
+        self.sites
+            .iter()
+            .find(|(domain, _)| host == **domain || host.ends_with(&format!(".{}", domain)))
+            .map(|(_, profile)| *profile)
+            .unwrap_or(self.default_profile)
+    }
+
+    /// Picks the user agent to request the URL with, according to the rotation policy
+    /// # Arguments
+    /// * `url` - The URL about to be requested
+    pub fn pick(&self, url: &str) -> String {
+        let pool = match self.profile(url) {
+            UserAgentProfile::Desktop => &self.desktop,
+            UserAgentProfile::Mobile => &self.mobile,
+        };
+
+        let user_agent = match self.rotation {
+            RotationPolicy::Random => pool.choose(&mut rand::thread_rng()),
+            RotationPolicy::RoundRobin => {
+                pool.get(self.next.fetch_add(1, Ordering::Relaxed) % pool.len())
+            }
+            RotationPolicy::Sticky => {
+                let mut hasher = DefaultHasher::new();
+                host_of(url).hash(&mut hasher);
+                pool.get(hasher.finish() as usize % pool.len())
+            }
+        };
+        user_agent.cloned().unwrap_or_default()
+    }
+}
+
+impl Default for UserAgentPool {
+    fn default() -> Self {
+        UserAgentPool::new(&UserAgentConfig::default()).unwrap()
+    }
+}
+
+fn host_of(url: &str) -> String {
+    Url::parse(url)
+        .ok()
+        .and_then(|u| u.host_str().map(str::to_string))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod user_agent_test {
+    use super::*;
+
+    #[test]
+    fn test_pick_follows_profile_and_rotation() {
+        let config: UserAgentConfig = toml::from_str(
+            r#"
+    rotation = "round_robin"
+    desktop = ["desktop_1", "desktop_2"]
+    [sites]
+    "tiktok.com" = "mobile"
+    "#,
+        )
+        .unwrap();
+        let pool = UserAgentPool::new(&config).unwrap();
+
+        assert_eq!(pool.pick("https://www.reddit.com/r/rust"), "desktop_1");
+        assert_eq!(pool.pick("https://www.reddit.com/r/rust"), "desktop_2");
+        assert_eq!(pool.pick("https://www.reddit.com/r/rust"), "desktop_1");
+        assert!(
+            MOBILE_USER_AGENTS.contains(&pool.pick("https://vm.tiktok.com/ZGJabc123/").as_str())
+        );
+
+        let sticky = UserAgentPool::new(&UserAgentConfig {
+            rotation: Some(RotationPolicy::Sticky),
+            ..Default::default()
+        })
+        .unwrap();
+        let user_agent = sticky.pick("https://www.instagram.com/p/abc/");
+        assert_eq!(
+            sticky.pick("https://www.instagram.com/reel/def/"),
+            user_agent
+        );
+        assert!(user_agent.starts_with("Mozilla/5.0"));
+    }
+
+    #[test]
+    fn test_invalid_user_agent_is_rejected() {
+        let config = UserAgentConfig {
+            mobile: Some(vec!["Mozilla/5.0\nInjected: header".to_string()]),
+            ..Default::default()
+        };
+
+        assert!(UserAgentPool::new(&config).is_err());
+    }
+}
@@ -0,0 +1,170 @@
+use serde::{Deserialize, Serialize};
+
+use crate::VIDEO_EXTENSIONS_FORMAT;
+
+const AUDIO_FORMAT_EXTENSION: &str = "m4a";
+const NO_CODEC: &str = "none";
+
+/// Information about a video, as dumped by `yt-dlp -J`
+/// The processors fill in what their site exposes, to caption the media with
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct VideoMetadata {
+    pub id: String,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub uploader: Option<String>,
+    /// The handle of the uploader, e.g. `@channel`
+    pub uploader_id: Option<String>,
+    /// In seconds
+    pub duration: Option<f64>,
+    pub webpage_url: Option<String>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub filesize: Option<u64>,
+    pub filesize_approx: Option<u64>,
+    /// Only needed to pick the format to download, so it is not stored
+    #[serde(default, skip_serializing)]
+    pub formats: Vec<VideoFormat>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct VideoFormat {
+    pub format_id: String,
+    pub ext: Option<String>,
+    pub vcodec: Option<String>,
+    pub acodec: Option<String>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// Total bitrate, in KBit/s
+    pub tbr: Option<f64>,
+    pub filesize: Option<u64>,
+    pub filesize_approx: Option<u64>,
+}
+
+/// The outcome of picking the format to download
+#[derive(Debug, Clone, PartialEq)]
+pub enum FormatPick {
+    /// The `yt-dlp` format selector of the best rendition fitting the size limit
+    Fits(String),
+    /// Every rendition of known size exceeds the size limit
+    TooLarge,
+    /// No rendition advertises its size
+    Unknown,
+}
+
+impl VideoFormat {
+    fn has_video(&self) -> bool {
+        self.vcodec.as_deref().is_some_and(|c| c != NO_CODEC)
+    }
+
+    fn has_audio(&self) -> bool {
+        self.acodec.as_deref().is_some_and(|c| c != NO_CODEC)
+    }
+
+    fn has_ext(&self, ext: &str) -> bool {
+        self.ext.as_deref() == Some(ext)
+    }
+
+    fn size(&self) -> Option<u64> {
+        self.filesize.or(self.filesize_approx)
+    }
+
+    fn rank(&self) -> (u32, u64) {
+        (
+            self.width.unwrap_or_default() * self.height.unwrap_or_default(),
+            self.tbr.unwrap_or_default() as u64,
+        )
+    }
+}
+
+impl VideoMetadata {
+    /// The handle of the uploader when it is one, their name otherwise
+    pub fn author(&self) -> Option<String> {
+        self.uploader_id
+            .clone()
+            .filter(|id| id.starts_with('@') || id.starts_with("u/"))
+            .or_else(|| self.uploader.clone())
+    }
+
+    /// e.g. `1920x1080`
+    pub fn resolution(&self) -> Option<String> {
+        match (self.width, self.height) {
+            (Some(width), Some(height)) => Some(format!("{}x{}", width, height)),
+            _ => None,
+        }
+    }
+
+    /// Picks the best `mp4` rendition fitting the size limit, either a single format
+    /// carrying both video and audio or a video merged with an `m4a` audio
+    /// # Arguments
+    /// * `max_size` - The size limit, in bytes
+    pub fn pick_format(&self, max_size: u64) -> FormatPick {
+        let best_audio = self
+            .formats
+            .iter()
+            .filter(|f| f.has_audio() && !f.has_video() && f.has_ext(AUDIO_FORMAT_EXTENSION))
+            .filter(|f| f.size().is_some_and(|s| s <= max_size))
+            .max_by_key(|f| f.tbr.unwrap_or_default() as u64);
+
+        let mut renditions: Vec<(String, (u32, u64), u64)> = Vec::new();
+        for format in self
+            .formats
+            .iter()
+            .filter(|f| f.has_video() && f.has_ext(VIDEO_EXTENSIONS_FORMAT))
+        {
+            let Some(size) = format.size() else {
+                continue;
+            };
+            if format.has_audio() {
+                renditions.push((format.format_id.clone(), format.rank(), size));
+            } else if let Some(audio) = best_audio {
+                renditions.push((
+                    format!("{}+{}", format.format_id, audio.format_id),
+                    format.rank(),
+                    size + audio.size().unwrap_or_default(),
+                ));
+            }
+        }
+
+        if renditions.is_empty() {
+            return FormatPick::Unknown;
+        }
+        renditions
+            .into_iter()
+            .filter(|(_, _, size)| *size <= max_size)
+            .max_by_key(|(_, rank, _)| *rank)
+            .map(|(selector, _, _)| FormatPick::Fits(selector))
+            .unwrap_or(FormatPick::TooLarge)
+    }
+}
+
+#[cfg(test)]
+mod video_metadata_test {
+    use super::*;
+    use crate::media_downloader::mock_server::fixture;
+
+    #[test]
+    fn test_pick_format_fitting_the_size_limit() {
+        let metadata: VideoMetadata =
+            serde_json::from_str(&fixture("yt_dlp/video_info.json")).unwrap();
+
+        assert_eq!(metadata.uploader.as_deref(), Some("Fixture Channel"));
+        assert_eq!(metadata.formats.len(), 6);
+        assert_eq!(
+            metadata.pick_format(100 * 1024 * 1024),
+            FormatPick::Fits("137+140".to_string())
+        );
+        assert_eq!(
+            metadata.pick_format(30 * 1024 * 1024),
+            FormatPick::Fits("22".to_string())
+        );
+        assert_eq!(metadata.pick_format(1024), FormatPick::TooLarge);
+        assert_eq!(
+            VideoMetadata::default().pick_format(1024),
+            FormatPick::Unknown
+        );
+
+        let stored = serde_json::to_string(&metadata).unwrap();
+        assert!(!stored.contains("formats"));
+    }
+}
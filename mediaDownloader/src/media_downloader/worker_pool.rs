@@ -0,0 +1,214 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::{debug, instrument};
+
+const DEFAULT_MAX_CONCURRENT_JOBS: usize = 4;
+const DEFAULT_MAX_QUEUED_JOBS: usize = 32;
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct WorkersConfig {
+    pub max_concurrent_jobs: Option<usize>,
+    pub max_queued_jobs: Option<usize>,
+    pub per_site: Option<HashMap<String, usize>>,
+}
+
+/// Bounds the number of jobs processed concurrently, globally and per site
+/// Jobs exceeding the limits wait for a slot in FIFO order, up to `max_queued_jobs`
+#[derive(Debug)]
+pub struct WorkerPool {
+    global: Arc<Semaphore>,
+    per_site: HashMap<String, Arc<Semaphore>>,
+    max_concurrent_jobs: usize,
+    max_queued_jobs: usize,
+    in_flight: Arc<AtomicUsize>,
+    waiting: Arc<AtomicUsize>,
+}
+
+/// Slot held by a job while being processed, released on drop
+#[derive(Debug)]
+pub struct WorkerPermit {
+    _site: Option<OwnedSemaphorePermit>,
+    _global: OwnedSemaphorePermit,
+}
+
+/// Tracks a dispatched job until dropped, to apply backpressure
+#[derive(Debug)]
+pub struct InFlightJob {
+    in_flight: Arc<AtomicUsize>,
+}
+
+impl Drop for InFlightJob {
+    fn drop(&mut self) {
+        self.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+struct WaitingJob {
+    waiting: Arc<AtomicUsize>,
+}
+
+impl Drop for WaitingJob {
+    fn drop(&mut self) {
+        self.waiting.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl WorkerPool {
+    pub fn new(config: &WorkersConfig) -> Self {
+        let max_concurrent_jobs = config
+            .max_concurrent_jobs
+            .unwrap_or(DEFAULT_MAX_CONCURRENT_JOBS)
+            .max(1);
+
+        let per_site = config
+            .per_site
+            .clone()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(site, limit)| (site, Arc::new(Semaphore::new(limit.max(1)))))
+            .collect();
+
+        Self {
+            global: Arc::new(Semaphore::new(max_concurrent_jobs)),
+            per_site,
+            max_concurrent_jobs,
+            max_queued_jobs: config.max_queued_jobs.unwrap_or(DEFAULT_MAX_QUEUED_JOBS),
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            waiting: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Number of additional jobs that can be dispatched without exceeding the queue bound
+    pub fn capacity(&self) -> usize {
+        (self.max_concurrent_jobs + self.max_queued_jobs)
+            .saturating_sub(self.in_flight.load(Ordering::SeqCst))
+    }
+
+    /// Marks a job as dispatched, until the returned guard is dropped
+    pub fn track(&self) -> InFlightJob {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        InFlightJob {
+            in_flight: self.in_flight.clone(),
+        }
+    }
+
+    /// Waits for a slot to process a job for the given site
+    /// # Arguments
+    /// * `site` - The site of the requested resource
+    /// * `on_queued` - Called with the position in the queue when no slot is immediately available
+    /// # Returns
+    /// * `WorkerPermit` - The slot, released on drop
+    #[instrument(level = "debug", name = "acquire_worker", skip(self, on_queued))]
+    pub async fn acquire<F>(&self, site: &str, on_queued: F) -> WorkerPermit
+    where
+        F: FnOnce(usize),
+    {
+        let site_semaphore = self.per_site.get(site).cloned();
+
+        let site_permit = site_semaphore
+            .as_ref()
+            .map(|s| s.clone().try_acquire_owned());
+        let global_permit = self.global.clone().try_acquire_owned();
+
+        match (site_permit, global_permit) {
+            (None, Ok(global)) => {
+                return WorkerPermit {
+                    _site: None,
+                    _global: global,
+                }
+            }
+            (Some(Ok(site)), Ok(global)) => {
+                return WorkerPermit {
+                    _site: Some(site),
+                    _global: global,
+                }
+            }
+            _ => {}
+        }
+
+        let position = self.waiting.fetch_add(1, Ordering::SeqCst) + 1;
+        let _waiting = WaitingJob {
+            waiting: self.waiting.clone(),
+        };
+        debug!("Job for `{}` queued at position {}", site, position);
+        on_queued(position);
+
+        // The site slot is acquired first so that no global slot is held while waiting for it
+        let site_permit = match site_semaphore {
+            Some(s) => Some(s.acquire_owned().await.unwrap()),
+            None => None,
+        };
+        let global_permit = self.global.clone().acquire_owned().await.unwrap();
+
+        WorkerPermit {
+            _site: site_permit,
+            _global: global_permit,
+        }
+    }
+}
+
+impl Default for WorkerPool {
+    fn default() -> Self {
+        WorkerPool::new(&WorkersConfig::default())
+    }
+}
+
+#[cfg(test)]
+mod worker_pool_test {
+    use super::*;
+
+    fn setup() -> WorkerPool {
+        let config: WorkersConfig = toml::from_str(
+            r#"
+    max_concurrent_jobs = 2
+    max_queued_jobs = 1
+
+    [per_site]
+    "tiktok.com" = 1
+    "#,
+        )
+        .unwrap();
+        WorkerPool::new(&config)
+    }
+
+    #[tokio::test]
+    async fn test_acquire_within_limits_is_not_queued() {
+        let pool = setup();
+
+        let _first = pool.acquire("youtube.com", |_| panic!("Not queued")).await;
+        let _second = pool.acquire("tiktok.com", |_| panic!("Not queued")).await;
+    }
+
+    #[tokio::test]
+    async fn test_acquire_over_site_limit_is_queued() {
+        let pool = Arc::new(setup());
+        let first = pool.acquire("tiktok.com", |_| panic!("Not queued")).await;
+
+        let pool_clone = pool.clone();
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let queued = tokio::spawn(async move {
+            pool_clone
+                .acquire("tiktok.com", move |position| tx.send(position).unwrap())
+                .await
+        });
+
+        assert_eq!(rx.await.unwrap(), 1);
+        drop(first);
+        let _second = queued.await.unwrap();
+    }
+
+    #[test]
+    fn test_capacity_is_bounded() {
+        let pool = setup();
+        assert_eq!(pool.capacity(), 3);
+
+        let jobs: Vec<_> = (0..3).map(|_| pool.track()).collect();
+        assert_eq!(pool.capacity(), 0);
+
+        drop(jobs);
+        assert_eq!(pool.capacity(), 3);
+    }
+}
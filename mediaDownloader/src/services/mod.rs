@@ -1,5 +1,11 @@
 mod redis;
+mod shutdown;
 mod tracing;
 
-pub use self::redis::{Builder, MetadataArchive, RedisBuilder, RedisConfig, RedisManager};
-pub use self::tracing::{init_telemetry, TelemetryConfig};
+pub use self::redis::{
+    AudioFormat, Builder, CachedGroupItem, CachedMedia, ChannelConfig, ChatSettings, DeadLetter,
+    MetadataArchive, OversizePolicy, QueuedJob, RedisBuilder, RedisConfig, RedisManager,
+    SlideshowAudio,
+};
+pub use self::shutdown::{drain_tasks, shutdown_signal};
+pub use self::tracing::{init_telemetry, shutdown_telemetry, TelemetryConfig};
@@ -16,6 +16,15 @@ pub struct RedisConfig {
     pub channel: String,
     pub host: Option<String>,
     pub port: Option<u16>,
+    pub channels: Option<Vec<ChannelConfig>>,
+    pub consumer_group: Option<String>,
+    pub consumer_name: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ChannelConfig {
+    pub name: String,
+    pub token: Option<String>,
 }
 
 #[derive(Clone, Default)]
@@ -26,8 +35,10 @@ pub struct RedisBuilder {
     port: Option<u16>,
 }
 
+/// Cloning is cheap, the clones share the same pool
+#[derive(Clone)]
 pub struct RedisManager {
-    manager: Pool,
+    pub(super) manager: Pool,
 }
 
 #[derive(Debug)]
@@ -107,8 +118,20 @@ impl Builder for RedisBuilder {
     }
 }
 
+impl Debug for RedisManager {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RedisManager")
+            .field("status", &self.manager.status())
+            .finish()
+    }
+}
+
 impl RedisManager {
     pub async fn new(builder: RedisBuilder) -> Result<RedisManager, RedisError> {
+        Ok(RedisManager::with_pool(builder))
+    }
+
+    fn with_pool(builder: RedisBuilder) -> RedisManager {
         let redis_db = 0;
 
         let redis_conn_info = RedisConnectionInfo {
@@ -131,7 +154,7 @@ impl RedisManager {
 
         debug!("Pool status: {:?}", pool.status());
 
-        Ok(Self { manager: pool })
+        Self { manager: pool }
     }
 
     pub async fn build(builder: RedisBuilder) -> Result<RedisManager, RedisError> {
@@ -154,19 +177,19 @@ impl RedisManager {
         let opts = SetOptions::default().with_expiration(SetExpiry::EX(DEFAULT_REDIS_TTL));
 
         let mut conn = self.manager.get().await.unwrap();
-        conn.set_options(key, value, opts).await?;
+        conn.set_options::<_, _, ()>(key, value, opts).await?;
         Ok(())
     }
 
     pub async fn del(&self, key: &str) -> Result<(), RedisError> {
         let mut conn = self.manager.get().await.unwrap();
-        conn.del(key).await?;
+        conn.del::<_, ()>(key).await?;
         Ok(())
     }
 
     pub async fn send_to_channel(&self, channel: &str, message: &str) -> Result<(), RedisError> {
         let mut conn = self.manager.get().await.unwrap();
-        conn.publish(channel, message).await?;
+        conn.publish::<_, _, ()>(channel, message).await?;
         Ok(())
     }
 
@@ -204,7 +227,13 @@ impl RedisManager {
                             }
 
                             let ttl: i32 = conn.ttl(key).await.unwrap();
-                            let val: String = conn.get(key).await.unwrap();
+                            let val: String = match conn.get(key).await {
+                                Ok(val) => val,
+                                Err(e) => {
+                                    debug!("Skipping non-string key {:?} ~ {}", key, e);
+                                    continue;
+                                }
+                            };
 
                             if ttl != -1 {
                                 debug!("Key: {:?} ~ Val: {:?}", key, val);
@@ -0,0 +1,245 @@
+use std::{fmt, str::FromStr};
+
+use redis::{AsyncCommands, RedisError};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, instrument, warn};
+
+use super::backend::RedisManager;
+
+const CHAT_SETTINGS_KEY_SUFFIX: &str = ":settings";
+
+/// How the background music of a TikTok slideshow is delivered
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SlideshowAudio {
+    /// Only the images are sent
+    #[default]
+    Off,
+    /// The images are followed by the music as an audio message
+    Separate,
+    /// The images and the music are rendered into a video
+    Video,
+}
+
+impl SlideshowAudio {
+    pub const VARIANTS: [&'static str; 3] = ["off", "separate", "video"];
+}
+
+impl fmt::Display for SlideshowAudio {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SlideshowAudio::Off => write!(f, "off"),
+            SlideshowAudio::Separate => write!(f, "separate"),
+            SlideshowAudio::Video => write!(f, "video"),
+        }
+    }
+}
+
+impl FromStr for SlideshowAudio {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "off" => Ok(SlideshowAudio::Off),
+            "separate" => Ok(SlideshowAudio::Separate),
+            "video" => Ok(SlideshowAudio::Video),
+            unknown => Err(format!(
+                "Unknown option `{}`, expected one of {:?}",
+                unknown,
+                SlideshowAudio::VARIANTS
+            )),
+        }
+    }
+}
+
+/// How videos exceeding the Telegram size limit are delivered
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OversizePolicy {
+    /// The video is not delivered
+    Reject,
+    /// The video is re-encoded at a lower bitrate, and resolution if needed
+    #[default]
+    Reencode,
+    /// The video is split into numbered parts
+    Split,
+}
+
+impl OversizePolicy {
+    pub const VARIANTS: [&'static str; 3] = ["reject", "reencode", "split"];
+}
+
+impl fmt::Display for OversizePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OversizePolicy::Reject => write!(f, "reject"),
+            OversizePolicy::Reencode => write!(f, "reencode"),
+            OversizePolicy::Split => write!(f, "split"),
+        }
+    }
+}
+
+impl FromStr for OversizePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "reject" => Ok(OversizePolicy::Reject),
+            "reencode" => Ok(OversizePolicy::Reencode),
+            "split" => Ok(OversizePolicy::Split),
+            unknown => Err(format!(
+                "Unknown option `{}`, expected one of {:?}",
+                unknown,
+                OversizePolicy::VARIANTS
+            )),
+        }
+    }
+}
+
+/// The format the audio track of a video is extracted as
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AudioFormat {
+    #[default]
+    M4a,
+    Mp3,
+}
+
+impl AudioFormat {
+    pub const VARIANTS: [&'static str; 2] = ["m4a", "mp3"];
+}
+
+impl fmt::Display for AudioFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AudioFormat::M4a => write!(f, "m4a"),
+            AudioFormat::Mp3 => write!(f, "mp3"),
+        }
+    }
+}
+
+impl FromStr for AudioFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "m4a" => Ok(AudioFormat::M4a),
+            "mp3" => Ok(AudioFormat::Mp3),
+            unknown => Err(format!(
+                "Unknown option `{}`, expected one of {:?}",
+                unknown,
+                AudioFormat::VARIANTS
+            )),
+        }
+    }
+}
+
+/// Preferences of a chat, set through the bot commands
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ChatSettings {
+    #[serde(default)]
+    pub slideshow_audio: SlideshowAudio,
+    #[serde(default)]
+    pub oversize: OversizePolicy,
+    /// Links are delivered as their audio track in this format, as videos otherwise
+    #[serde(default)]
+    pub audio_default: Option<AudioFormat>,
+}
+
+impl ChatSettings {
+    /// Distinguishes the media delivered to chats with non-default preferences,
+    /// as the same resource results in different media
+    pub fn delivery_variant(&self) -> Option<String> {
+        let mut variants = Vec::new();
+        if self.slideshow_audio != SlideshowAudio::default() {
+            variants.push(format!("slideshow_audio={}", self.slideshow_audio));
+        }
+        if self.oversize != OversizePolicy::default() {
+            variants.push(format!("oversize={}", self.oversize));
+        }
+        (!variants.is_empty()).then(|| variants.join("&"))
+    }
+}
+
+fn chat_settings_key(channel: &str, chat_id: i64) -> String {
+    format!("{}:chat:{}{}", channel, chat_id, CHAT_SETTINGS_KEY_SUFFIX)
+}
+
+impl RedisManager {
+    /// Looks up the preferences of a chat, defaults are returned if none were set
+    /// # Arguments
+    /// * `channel` - The channel of the bot the chat talks to
+    /// * `chat_id` - The chat
+    #[instrument(level = "debug", name = "get_chat_settings", skip(self))]
+    pub async fn get_chat_settings(
+        &self,
+        channel: &str,
+        chat_id: i64,
+    ) -> Result<ChatSettings, RedisError> {
+        let mut conn = self.manager.get().await.unwrap();
+        let value: Option<String> = conn.get(chat_settings_key(channel, chat_id)).await?;
+
+        Ok(value
+            .and_then(|v| match serde_json::from_str(&v) {
+                Ok(settings) => Some(settings),
+                Err(e) => {
+                    warn!("Discarding malformed chat settings `{}` ~ {}", v, e);
+                    None
+                }
+            })
+            .unwrap_or_default())
+    }
+
+    /// Stores the preferences of a chat
+    /// # Arguments
+    /// * `channel` - The channel of the bot the chat talks to
+    /// * `chat_id` - The chat
+    /// * `settings` - The preferences to store
+    #[instrument(level = "debug", name = "set_chat_settings", skip(self))]
+    pub async fn set_chat_settings(
+        &self,
+        channel: &str,
+        chat_id: i64,
+        settings: &ChatSettings,
+    ) -> Result<(), RedisError> {
+        let value = serde_json::to_string(settings).unwrap();
+
+        let mut conn = self.manager.get().await.unwrap();
+        conn.set::<_, _, ()>(chat_settings_key(channel, chat_id), value)
+            .await?;
+        debug!("Stored settings of chat {}", chat_id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod chat_settings_test {
+    use super::*;
+
+    #[test]
+    fn test_chat_settings_defaults() {
+        let settings: ChatSettings = serde_json::from_str("{}").unwrap();
+
+        assert_eq!(settings, ChatSettings::default());
+        assert_eq!(settings.delivery_variant(), None);
+        assert_eq!(
+            ChatSettings {
+                slideshow_audio: "Video".parse().unwrap(),
+                ..Default::default()
+            }
+            .delivery_variant(),
+            Some("slideshow_audio=video".to_string())
+        );
+        assert_eq!(
+            ChatSettings {
+                slideshow_audio: SlideshowAudio::Separate,
+                oversize: "split".parse().unwrap(),
+                ..Default::default()
+            }
+            .delivery_variant(),
+            Some("slideshow_audio=separate&oversize=split".to_string())
+        );
+        assert!("shrink".parse::<OversizePolicy>().is_err());
+        assert_eq!("MP3".parse(), Ok(AudioFormat::Mp3));
+    }
+}
@@ -0,0 +1,215 @@
+use frankenstein::{FileUpload, InputMediaPhoto, InputMediaVideo, Media};
+use redis::{AsyncCommands, RedisError, SetExpiry, SetOptions};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, instrument, warn};
+
+use super::backend::RedisManager;
+use crate::{media_downloader::caption::Caption, MessageContent, FILE_ID_CACHE_TTL};
+
+const FILE_ID_KEY_SUFFIX: &str = ":file_id";
+const CAPTION_KEY_SUFFIX: &str = ":caption";
+
+/// Media already delivered through Telegram, referenced by its `file_id`s
+/// The `file_id`s are only valid for the bot that uploaded the media
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CachedMedia {
+    Video(String),
+    MediaGroup(Vec<CachedGroupItem>),
+    /// A media group followed by its audio
+    Slideshow(Vec<CachedGroupItem>, String),
+    Audio(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CachedGroupItem {
+    Photo(String),
+    Video(String),
+}
+
+impl From<CachedMedia> for MessageContent {
+    fn from(media: CachedMedia) -> Self {
+        match media {
+            CachedMedia::Video(file_id) => MessageContent::File(FileUpload::String(file_id)),
+            CachedMedia::MediaGroup(items) => MessageContent::Images(group_media(items)),
+            CachedMedia::Slideshow(items, audio) => {
+                MessageContent::Slideshow(group_media(items), FileUpload::String(audio))
+            }
+            CachedMedia::Audio(file_id) => {
+                MessageContent::Audio(FileUpload::String(file_id).into())
+            }
+        }
+    }
+}
+
+fn group_media(items: Vec<CachedGroupItem>) -> Vec<Media> {
+    items
+        .into_iter()
+        .map(|item| match item {
+            CachedGroupItem::Photo(file_id) => Media::Photo(InputMediaPhoto {
+                media: FileUpload::String(file_id),
+                caption: None,
+                parse_mode: None,
+                caption_entities: None,
+                has_spoiler: None,
+            }),
+            CachedGroupItem::Video(file_id) => Media::Video(InputMediaVideo {
+                media: FileUpload::String(file_id),
+                thumbnail: None,
+                caption: None,
+                parse_mode: None,
+                caption_entities: None,
+                width: None,
+                height: None,
+                duration: None,
+                supports_streaming: None,
+                has_spoiler: None,
+            }),
+        })
+        .collect()
+}
+
+fn file_id_key(channel: &str, media_key: &str) -> String {
+    format!("{}:{}{}", channel, media_key, FILE_ID_KEY_SUFFIX)
+}
+
+fn caption_key(channel: &str, media_key: &str) -> String {
+    format!("{}:{}{}", channel, media_key, CAPTION_KEY_SUFFIX)
+}
+
+impl RedisManager {
+    /// Looks up the media already delivered for the given resource
+    /// # Arguments
+    /// * `channel` - The channel the resource was requested on, as `file_id`s are bound to its bot
+    /// * `media_key` - The key of the requested resource
+    /// # Returns
+    /// * `Option<CachedMedia>` - The delivered media, if any
+    #[instrument(level = "debug", name = "get_cached_media", skip(self))]
+    pub async fn get_cached_media(
+        &self,
+        channel: &str,
+        media_key: &str,
+    ) -> Result<Option<CachedMedia>, RedisError> {
+        let mut conn = self.manager.get().await.unwrap();
+        let value: Option<String> = conn.get(file_id_key(channel, media_key)).await?;
+
+        Ok(value.and_then(|v| match serde_json::from_str(&v) {
+            Ok(media) => Some(media),
+            Err(e) => {
+                warn!("Discarding malformed cached media `{}` ~ {}", v, e);
+                None
+            }
+        }))
+    }
+
+    /// Stores the media delivered for the given resource, outliving the local files
+    /// # Arguments
+    /// * `channel` - The channel the resource was requested on
+    /// * `media_key` - The key of the requested resource
+    /// * `media` - The delivered media
+    #[instrument(level = "debug", name = "cache_media", skip(self))]
+    pub async fn cache_media(
+        &self,
+        channel: &str,
+        media_key: &str,
+        media: &CachedMedia,
+    ) -> Result<(), RedisError> {
+        let value = serde_json::to_string(media).unwrap();
+        let opts = SetOptions::default().with_expiration(SetExpiry::EX(FILE_ID_CACHE_TTL));
+
+        let mut conn = self.manager.get().await.unwrap();
+        conn.set_options::<_, _, ()>(file_id_key(channel, media_key), value, opts)
+            .await?;
+        debug!("Cached media for `{}`", media_key);
+        Ok(())
+    }
+
+    /// Looks up the caption the media of the given resource was delivered with
+    /// # Arguments
+    /// * `channel` - The channel the resource was requested on
+    /// * `media_key` - The key of the requested resource
+    #[instrument(level = "debug", name = "get_cached_caption", skip(self))]
+    pub async fn get_cached_caption(
+        &self,
+        channel: &str,
+        media_key: &str,
+    ) -> Result<Option<Caption>, RedisError> {
+        let mut conn = self.manager.get().await.unwrap();
+        let value: Option<String> = conn.get(caption_key(channel, media_key)).await?;
+
+        Ok(value.and_then(|v| match serde_json::from_str(&v) {
+            Ok(caption) => Some(caption),
+            Err(e) => {
+                warn!("Discarding malformed cached caption `{}` ~ {}", v, e);
+                None
+            }
+        }))
+    }
+
+    /// Stores the caption the media of the given resource was delivered with,
+    /// for as long as its `file_id`s
+    /// # Arguments
+    /// * `channel` - The channel the resource was requested on
+    /// * `media_key` - The key of the requested resource
+    /// * `caption` - The caption of the delivered media
+    #[instrument(level = "debug", name = "cache_caption", skip(self, caption))]
+    pub async fn cache_caption(
+        &self,
+        channel: &str,
+        media_key: &str,
+        caption: &Caption,
+    ) -> Result<(), RedisError> {
+        let value = serde_json::to_string(caption).unwrap();
+        let opts = SetOptions::default().with_expiration(SetExpiry::EX(FILE_ID_CACHE_TTL));
+
+        let mut conn = self.manager.get().await.unwrap();
+        conn.set_options::<_, _, ()>(caption_key(channel, media_key), value, opts)
+            .await?;
+        Ok(())
+    }
+
+    /// Forgets the media delivered for the given resource, e.g. when Telegram rejects its `file_id`s
+    /// # Arguments
+    /// * `channel` - The channel the resource was requested on
+    /// * `media_key` - The key of the requested resource
+    pub async fn invalidate_cached_media(
+        &self,
+        channel: &str,
+        media_key: &str,
+    ) -> Result<(), RedisError> {
+        let mut conn = self.manager.get().await.unwrap();
+        conn.del::<_, ()>(&[
+            file_id_key(channel, media_key),
+            caption_key(channel, media_key),
+        ])
+        .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod media_cache_test {
+    use super::*;
+
+    #[test]
+    fn test_cached_media_roundtrip() {
+        let media = CachedMedia::MediaGroup(vec![
+            CachedGroupItem::Photo("photo_id".to_string()),
+            CachedGroupItem::Video("video_id".to_string()),
+        ]);
+
+        let serialized = serde_json::to_string(&media).unwrap();
+        assert_eq!(
+            serialized,
+            r#"{"media_group":[{"photo":"photo_id"},{"video":"video_id"}]}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<CachedMedia>(&serialized).unwrap(),
+            media
+        );
+
+        let audio = serde_json::to_string(&CachedMedia::Audio("audio_id".to_string())).unwrap();
+        assert_eq!(audio, r#"{"audio":"audio_id"}"#);
+    }
+}
@@ -1,2 +1,11 @@
 mod backend;
-pub use backend::{Builder, MetadataArchive, RedisBuilder, RedisConfig, RedisManager};
+mod chat_settings;
+mod media_cache;
+mod queue;
+mod video_metadata;
+pub use backend::{
+    Builder, ChannelConfig, MetadataArchive, RedisBuilder, RedisConfig, RedisManager,
+};
+pub use chat_settings::{AudioFormat, ChatSettings, OversizePolicy, SlideshowAudio};
+pub use media_cache::{CachedGroupItem, CachedMedia};
+pub use queue::{DeadLetter, QueuedJob};
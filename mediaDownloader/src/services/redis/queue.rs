@@ -0,0 +1,471 @@
+use std::collections::HashSet;
+
+use redis::streams::{
+    StreamClaimOptions, StreamClaimReply, StreamId, StreamMaxlen, StreamPendingCountReply,
+    StreamRangeReply, StreamReadOptions, StreamReadReply,
+};
+use redis::{AsyncCommands, RedisError, Script};
+use tracing::{debug, instrument};
+
+use super::backend::RedisManager;
+
+const JOB_PAYLOAD_FIELD: &str = "payload";
+const DEAD_LETTER_ERROR_FIELD: &str = "error";
+const DEAD_LETTER_ATTEMPTS_FIELD: &str = "attempts";
+const DEAD_LETTER_FIRST_FAILED_AT_FIELD: &str = "first_failed_at";
+const DEAD_LETTER_LAST_FAILED_AT_FIELD: &str = "last_failed_at";
+const RETRIES_KEY_SUFFIX: &str = ":retries";
+const DEAD_LETTERS_KEY_SUFFIX: &str = ":dead_letters";
+const BUSY_GROUP_ERROR_CODE: &str = "BUSYGROUP";
+/// Reads the jobs never delivered to any consumer
+const NEW_JOBS_ID: &str = ">";
+const STREAM_MAX_LENGTH: usize = 10_000;
+/// Scheduled jobs are stored as `<uuid>|<payload>`, so that identical retries are kept apart
+const SCHEDULED_JOB_SEPARATOR: char = '|';
+/// Moves the due jobs to the stream, a job is removed from the schedule only once enqueued
+/// Jobs scheduled without an id are enqueued as they are
+const ENQUEUE_DUE_JOBS_SCRIPT: &str = r#"
+local due = redis.call('ZRANGEBYSCORE', KEYS[1], 0, ARGV[1], 'LIMIT', 0, ARGV[2])
+for _, member in ipairs(due) do
+    local payload = string.match(member, '^%x+%-%x+%-%x+%-%x+%-%x+|(.*)$') or member
+    redis.call('XADD', KEYS[2], 'MAXLEN', '~', ARGV[3], '*', ARGV[4], payload)
+    redis.call('ZREM', KEYS[1], member)
+end
+return #due
+"#;
+
+/// A job read from a Redis stream, still to be acknowledged
+#[derive(Debug, Clone)]
+pub struct QueuedJob {
+    pub stream: String,
+    pub id: String,
+    pub payload: String,
+    pub deliveries: usize,
+}
+
+/// A job that exhausted its attempts, kept for inspection and replay
+#[derive(Debug, Clone)]
+pub struct DeadLetter {
+    pub id: String,
+    pub payload: String,
+    pub error: String,
+    pub attempts: u32,
+    pub first_failed_at: u64,
+    pub last_failed_at: u64,
+}
+
+impl DeadLetter {
+    fn from_stream_id(stream_id: &StreamId) -> DeadLetter {
+        DeadLetter {
+            id: stream_id.id.clone(),
+            payload: stream_id.get(JOB_PAYLOAD_FIELD).unwrap_or_default(),
+            error: stream_id.get(DEAD_LETTER_ERROR_FIELD).unwrap_or_default(),
+            attempts: stream_id
+                .get(DEAD_LETTER_ATTEMPTS_FIELD)
+                .unwrap_or_default(),
+            first_failed_at: stream_id
+                .get(DEAD_LETTER_FIRST_FAILED_AT_FIELD)
+                .unwrap_or_default(),
+            last_failed_at: stream_id
+                .get(DEAD_LETTER_LAST_FAILED_AT_FIELD)
+                .unwrap_or_default(),
+        }
+    }
+}
+
+fn retries_key(stream: &str) -> String {
+    format!("{}{}", stream, RETRIES_KEY_SUFFIX)
+}
+
+fn dead_letters_key(stream: &str) -> String {
+    format!("{}{}", stream, DEAD_LETTERS_KEY_SUFFIX)
+}
+
+impl QueuedJob {
+    fn from_stream_id(stream: &str, stream_id: &StreamId, deliveries: usize) -> Option<QueuedJob> {
+        match stream_id.get::<String>(JOB_PAYLOAD_FIELD) {
+            Some(payload) => Some(QueuedJob {
+                stream: stream.to_string(),
+                id: stream_id.id.clone(),
+                payload,
+                deliveries,
+            }),
+            None => {
+                warn!(
+                    "Job `{}` has no `{}` field!",
+                    stream_id.id, JOB_PAYLOAD_FIELD
+                );
+                None
+            }
+        }
+    }
+}
+
+impl RedisManager {
+    /// Creates the consumer group (and the stream, if missing) for the given stream
+    /// An already existing group is not considered an error
+    /// # Arguments
+    /// * `stream` - The stream key
+    /// * `group` - The consumer group name
+    #[instrument(level = "debug", name = "create_consumer_group", skip(self))]
+    pub async fn create_consumer_group(&self, stream: &str, group: &str) -> Result<(), RedisError> {
+        let mut conn = self.manager.get().await.unwrap();
+        match conn
+            .xgroup_create_mkstream::<_, _, _, ()>(stream, group, "0")
+            .await
+        {
+            Ok(_) => {
+                debug!("Created consumer group `{}` on `{}`", group, stream);
+                Ok(())
+            }
+            Err(e) if e.code() == Some(BUSY_GROUP_ERROR_CODE) => {
+                debug!("Consumer group `{}` already exists on `{}`", group, stream);
+                Ok(())
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Appends a job to the given stream, capping its length
+    /// # Arguments
+    /// * `stream` - The stream key
+    /// * `payload` - The serialized job
+    /// # Returns
+    /// * `Result<String, RedisError>` - The id of the enqueued job
+    pub async fn enqueue_job(&self, stream: &str, payload: &str) -> Result<String, RedisError> {
+        let mut conn = self.manager.get().await.unwrap();
+        let id: String = conn
+            .xadd_maxlen(
+                stream,
+                StreamMaxlen::Approx(STREAM_MAX_LENGTH),
+                "*",
+                &[(JOB_PAYLOAD_FIELD, payload)],
+            )
+            .await?;
+        Ok(id)
+    }
+
+    /// Reads jobs for the given consumer from one or more streams
+    /// # Arguments
+    /// * `streams` - The stream keys
+    /// * `group` - The consumer group name
+    /// * `consumer` - The consumer name
+    /// * `from_id` - `>` for new jobs, `0` for the jobs already delivered to this consumer
+    /// * `count` - The maximum number of jobs to read per stream
+    /// * `block_ms` - How long to block waiting for new jobs (ignored when `None`)
+    /// # Returns
+    /// * `Result<Vec<QueuedJob>, RedisError>` - The jobs read, along with their delivery
+    ///   count (looked up for the jobs already delivered)
+    pub async fn read_jobs(
+        &self,
+        streams: &[&str],
+        group: &str,
+        consumer: &str,
+        from_id: &str,
+        count: usize,
+        block_ms: Option<usize>,
+    ) -> Result<Vec<QueuedJob>, RedisError> {
+        let mut opts = StreamReadOptions::default()
+            .group(group, consumer)
+            .count(count);
+        if let Some(block_ms) = block_ms {
+            opts = opts.block(block_ms);
+        }
+
+        let ids = vec![from_id; streams.len()];
+
+        let mut conn = self.manager.get().await.unwrap();
+        let reply: Option<StreamReadReply> = conn.xread_options(streams, &ids, &opts).await?;
+
+        let mut jobs = Vec::new();
+        for key in reply.map(|r| r.keys).unwrap_or_default() {
+            let (Some(first), Some(last)) = (key.ids.first(), key.ids.last()) else {
+                continue;
+            };
+            // Reading the history delivers the jobs again, their count already includes it
+            let pending = match from_id {
+                NEW_JOBS_ID => Vec::new(),
+                _ => {
+                    let reply: StreamPendingCountReply = conn
+                        .xpending_consumer_count(
+                            &key.key,
+                            group,
+                            &first.id,
+                            &last.id,
+                            key.ids.len(),
+                            consumer,
+                        )
+                        .await?;
+                    reply.ids
+                }
+            };
+
+            jobs.extend(key.ids.iter().filter_map(|stream_id| {
+                let deliveries = pending
+                    .iter()
+                    .find(|p| p.id == stream_id.id)
+                    .map_or(1, |p| p.times_delivered);
+                QueuedJob::from_stream_id(&key.key, stream_id, deliveries)
+            }));
+        }
+        Ok(jobs)
+    }
+
+    /// Acknowledges a job, removing it from the pending entries list
+    /// # Arguments
+    /// * `stream` - The stream key
+    /// * `group` - The consumer group name
+    /// * `id` - The id of the job
+    pub async fn ack_job(&self, stream: &str, group: &str, id: &str) -> Result<(), RedisError> {
+        let mut conn = self.manager.get().await.unwrap();
+        conn.xack::<_, _, _, ()>(stream, group, &[id]).await?;
+        Ok(())
+    }
+
+    /// Claims the jobs left pending by any consumer for longer than `min_idle_ms`
+    /// (e.g. after a worker crash) so that they can be processed again
+    /// # Arguments
+    /// * `stream` - The stream key
+    /// * `group` - The consumer group name
+    /// * `consumer` - The consumer claiming the jobs
+    /// * `min_idle_ms` - The minimum idle time of a job before it can be claimed
+    /// * `count` - The maximum number of pending jobs to inspect
+    /// * `in_flight` - The jobs the consumer is still processing, never claimed again
+    /// # Returns
+    /// * `Result<Vec<QueuedJob>, RedisError>` - The claimed jobs
+    #[instrument(level = "debug", name = "reclaim_stale_jobs", skip(self, in_flight))]
+    pub async fn reclaim_stale_jobs(
+        &self,
+        stream: &str,
+        group: &str,
+        consumer: &str,
+        min_idle_ms: usize,
+        count: usize,
+        in_flight: &HashSet<String>,
+    ) -> Result<Vec<QueuedJob>, RedisError> {
+        let mut conn = self.manager.get().await.unwrap();
+        let pending: StreamPendingCountReply =
+            conn.xpending_count(stream, group, "-", "+", count).await?;
+
+        let stale: Vec<_> = pending
+            .ids
+            .into_iter()
+            .filter(|p| p.last_delivered_ms >= min_idle_ms && !in_flight.contains(&p.id))
+            .collect();
+
+        if stale.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let ids: Vec<&str> = stale.iter().map(|p| p.id.as_str()).collect();
+        debug!("Claiming stale jobs: {:?}", ids);
+
+        let claimed: StreamClaimReply = conn
+            .xclaim(stream, group, consumer, min_idle_ms, &ids)
+            .await?;
+
+        let jobs = claimed
+            .ids
+            .iter()
+            .filter_map(|stream_id| {
+                let deliveries = stale
+                    .iter()
+                    .find(|p| p.id == stream_id.id)
+                    .map_or(1, |p| p.times_delivered + 1);
+                QueuedJob::from_stream_id(stream, stream_id, deliveries)
+            })
+            .collect();
+        Ok(jobs)
+    }
+
+    /// Resets the idle time of the jobs still being processed by the consumer,
+    /// so that other consumers do not claim them however long they take
+    /// # Arguments
+    /// * `stream` - The stream key
+    /// * `group` - The consumer group name
+    /// * `consumer` - The consumer processing the jobs
+    /// * `ids` - The ids of the jobs
+    #[instrument(level = "debug", name = "heartbeat_jobs", skip(self))]
+    pub async fn heartbeat_jobs(
+        &self,
+        stream: &str,
+        group: &str,
+        consumer: &str,
+        ids: &[&str],
+    ) -> Result<(), RedisError> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+        // `JUSTID` leaves the delivery counter untouched
+        let opts = StreamClaimOptions::default().with_justid();
+        let mut conn = self.manager.get().await.unwrap();
+        conn.xclaim_options::<_, _, _, _, _, Vec<String>>(stream, group, consumer, 0, ids, opts)
+            .await?;
+        Ok(())
+    }
+
+    /// Schedules a job to be enqueued again on the given stream once due
+    /// # Arguments
+    /// * `stream` - The stream key
+    /// * `payload` - The serialized job
+    /// * `due_at` - The unix timestamp (seconds) after which the job is enqueued
+    pub async fn schedule_job(
+        &self,
+        stream: &str,
+        payload: &str,
+        due_at: u64,
+    ) -> Result<(), RedisError> {
+        let member = format!(
+            "{}{}{}",
+            uuid::Uuid::new_v4(),
+            SCHEDULED_JOB_SEPARATOR,
+            payload
+        );
+        let mut conn = self.manager.get().await.unwrap();
+        conn.zadd::<_, _, _, ()>(retries_key(stream), member, due_at)
+            .await?;
+        Ok(())
+    }
+
+    /// Moves the scheduled jobs that are due back to the given stream, atomically
+    /// Safe to be called by multiple consumers, a job is moved only once
+    /// # Arguments
+    /// * `stream` - The stream key
+    /// * `now` - The current unix timestamp (seconds)
+    /// * `count` - The maximum number of jobs to move
+    /// # Returns
+    /// * `Result<usize, RedisError>` - The number of jobs moved
+    #[instrument(level = "debug", name = "enqueue_due_jobs", skip(self))]
+    pub async fn enqueue_due_jobs(
+        &self,
+        stream: &str,
+        now: u64,
+        count: isize,
+    ) -> Result<usize, RedisError> {
+        let mut conn = self.manager.get().await.unwrap();
+        let moved: usize = Script::new(ENQUEUE_DUE_JOBS_SCRIPT)
+            .key(retries_key(stream))
+            .key(stream)
+            .arg(now)
+            .arg(count)
+            .arg(STREAM_MAX_LENGTH)
+            .arg(JOB_PAYLOAD_FIELD)
+            .invoke_async(&mut conn)
+            .await?;
+        if moved > 0 {
+            debug!("Enqueued {} scheduled jobs", moved);
+        }
+        Ok(moved)
+    }
+
+    /// Stores a failed job in the dead-letter queue of the given stream
+    /// # Arguments
+    /// * `stream` - The stream key the job was read from
+    /// * `dead_letter` - The failed job (its `id` is ignored)
+    /// # Returns
+    /// * `Result<String, RedisError>` - The id of the dead letter
+    pub async fn dead_letter_job(
+        &self,
+        stream: &str,
+        dead_letter: &DeadLetter,
+    ) -> Result<String, RedisError> {
+        let mut conn = self.manager.get().await.unwrap();
+        let id: String = conn
+            .xadd_maxlen(
+                dead_letters_key(stream),
+                StreamMaxlen::Approx(STREAM_MAX_LENGTH),
+                "*",
+                &[
+                    (JOB_PAYLOAD_FIELD, dead_letter.payload.clone()),
+                    (DEAD_LETTER_ERROR_FIELD, dead_letter.error.clone()),
+                    (DEAD_LETTER_ATTEMPTS_FIELD, dead_letter.attempts.to_string()),
+                    (
+                        DEAD_LETTER_FIRST_FAILED_AT_FIELD,
+                        dead_letter.first_failed_at.to_string(),
+                    ),
+                    (
+                        DEAD_LETTER_LAST_FAILED_AT_FIELD,
+                        dead_letter.last_failed_at.to_string(),
+                    ),
+                ],
+            )
+            .await?;
+        Ok(id)
+    }
+
+    /// Lists the dead letters of the given stream, oldest first
+    /// # Arguments
+    /// * `stream` - The stream key
+    pub async fn list_dead_letters(&self, stream: &str) -> Result<Vec<DeadLetter>, RedisError> {
+        let mut conn = self.manager.get().await.unwrap();
+        let reply: StreamRangeReply = conn.xrange_all(dead_letters_key(stream)).await?;
+        Ok(reply.ids.iter().map(DeadLetter::from_stream_id).collect())
+    }
+
+    /// Retrieves a single dead letter of the given stream
+    /// # Arguments
+    /// * `stream` - The stream key
+    /// * `id` - The id of the dead letter
+    pub async fn get_dead_letter(
+        &self,
+        stream: &str,
+        id: &str,
+    ) -> Result<Option<DeadLetter>, RedisError> {
+        let mut conn = self.manager.get().await.unwrap();
+        let reply: StreamRangeReply = conn.xrange(dead_letters_key(stream), id, id).await?;
+        Ok(reply.ids.first().map(DeadLetter::from_stream_id))
+    }
+
+    /// Removes a dead letter of the given stream
+    /// # Arguments
+    /// * `stream` - The stream key
+    /// * `id` - The id of the dead letter
+    pub async fn delete_dead_letter(&self, stream: &str, id: &str) -> Result<(), RedisError> {
+        let mut conn = self.manager.get().await.unwrap();
+        conn.xdel::<_, _, ()>(dead_letters_key(stream), &[id])
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod queue_test {
+    use crate::media_downloader::mock_server::MockRedis;
+
+    const STREAM: &str = "jobs";
+    const JOB_ID: &str = "1700000000000-0";
+
+    #[tokio::test]
+    async fn test_read_jobs_counts_deliveries() {
+        let redis = MockRedis::start().await;
+        let manager = redis.manager().await;
+        redis.reply(
+            "XREADGROUP",
+            &format!(
+                "*1\r\n*2\r\n$4\r\n{}\r\n*1\r\n*2\r\n$15\r\n{}\r\n*2\r\n$7\r\npayload\r\n$2\r\n{{}}\r\n",
+                STREAM, JOB_ID
+            ),
+        );
+        redis.reply(
+            "XPENDING",
+            &format!(
+                "*1\r\n*4\r\n$15\r\n{}\r\n$6\r\nworker\r\n:1200\r\n:4\r\n",
+                JOB_ID
+            ),
+        );
+
+        let resumed = manager
+            .read_jobs(&[STREAM], "group", "worker", "0", 10, None)
+            .await
+            .unwrap();
+        assert_eq!(resumed.len(), 1);
+        assert_eq!(resumed[0].id, JOB_ID);
+        assert_eq!(resumed[0].deliveries, 4);
+
+        let new = manager
+            .read_jobs(&[STREAM], "group", "worker", ">", 10, None)
+            .await
+            .unwrap();
+        assert_eq!(new[0].deliveries, 1);
+    }
+}
@@ -0,0 +1,53 @@
+use redis::{AsyncCommands, RedisError, SetExpiry, SetOptions};
+use tracing::{debug, instrument, warn};
+
+use super::backend::RedisManager;
+use crate::{media_downloader::video_metadata::VideoMetadata, DEFAULT_REDIS_TTL};
+
+const VIDEO_METADATA_KEY_SUFFIX: &str = ":metadata";
+
+fn video_metadata_key(url_id: &str) -> String {
+    format!("{}{}", url_id, VIDEO_METADATA_KEY_SUFFIX)
+}
+
+impl RedisManager {
+    /// Looks up the metadata of a video extracted by `yt-dlp`
+    /// # Arguments
+    /// * `url_id` - The ID of the video
+    #[instrument(level = "debug", name = "get_video_metadata", skip(self))]
+    pub async fn get_video_metadata(
+        &self,
+        url_id: &str,
+    ) -> Result<Option<VideoMetadata>, RedisError> {
+        let mut conn = self.manager.get().await.unwrap();
+        let value: Option<String> = conn.get(video_metadata_key(url_id)).await?;
+
+        Ok(value.and_then(|v| match serde_json::from_str(&v) {
+            Ok(metadata) => Some(metadata),
+            Err(e) => {
+                warn!("Discarding malformed video metadata `{}` ~ {}", v, e);
+                None
+            }
+        }))
+    }
+
+    /// Stores the metadata of a video, for as long as the video itself
+    /// # Arguments
+    /// * `url_id` - The ID of the video
+    /// * `metadata` - The metadata extracted by `yt-dlp`
+    #[instrument(level = "debug", name = "set_video_metadata", skip(self, metadata))]
+    pub async fn set_video_metadata(
+        &self,
+        url_id: &str,
+        metadata: &VideoMetadata,
+    ) -> Result<(), RedisError> {
+        let value = serde_json::to_string(metadata).unwrap();
+        let opts = SetOptions::default().with_expiration(SetExpiry::EX(DEFAULT_REDIS_TTL));
+
+        let mut conn = self.manager.get().await.unwrap();
+        conn.set_options::<_, _, ()>(video_metadata_key(url_id), value, opts)
+            .await?;
+        debug!("Stored metadata of `{}`", url_id);
+        Ok(())
+    }
+}
@@ -0,0 +1,2 @@
+mod signal;
+pub use signal::{drain_tasks, shutdown_signal};
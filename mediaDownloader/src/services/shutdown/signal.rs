@@ -0,0 +1,53 @@
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::task::JoinSet;
+use tracing::{debug, error, info, instrument};
+
+/// Resolves once a `SIGTERM` or `SIGINT` is received
+pub async fn shutdown_signal() {
+    let mut sigterm = signal(SignalKind::terminate()).expect("Failed to listen for SIGTERM");
+    let mut sigint = signal(SignalKind::interrupt()).expect("Failed to listen for SIGINT");
+
+    tokio::select! {
+        _ = sigterm.recv() => info!("Received SIGTERM"),
+        _ = sigint.recv() => info!("Received SIGINT"),
+    }
+}
+
+/// Waits for the given tasks to complete, aborting the ones still running after `deadline`
+/// # Arguments
+/// * `tasks` - The in-flight tasks
+/// * `deadline` - The maximum time to wait for
+/// # Returns
+/// * `bool` - Whether every task completed successfully before the deadline
+#[instrument(level = "debug", name = "drain_tasks", skip(tasks))]
+pub async fn drain_tasks(tasks: &mut JoinSet<()>, deadline: Duration) -> bool {
+    info!("Draining {} in-flight tasks", tasks.len());
+    let mut failed = 0;
+
+    let drained = tokio::time::timeout(deadline, async {
+        while let Some(outcome) = tasks.join_next().await {
+            if let Err(e) = outcome {
+                error!("In-flight task failed: {:?}", e);
+                failed += 1;
+            }
+        }
+    })
+    .await;
+
+    match drained {
+        Ok(_) => {
+            debug!("Drained in-flight tasks");
+            failed == 0
+        }
+        Err(_) => {
+            error!(
+                "{} in-flight tasks still running after {:?}, aborting them",
+                tasks.len(),
+                deadline
+            );
+            tasks.shutdown().await;
+            false
+        }
+    }
+}
@@ -1,2 +1,2 @@
 mod telemetry;
-pub use telemetry::{init_telemetry, TelemetryConfig};
+pub use telemetry::{init_telemetry, shutdown_telemetry, TelemetryConfig};
@@ -53,7 +53,6 @@ pub async fn init_telemetry(service_name: Option<String>) {
         ),
     );
 
-
     // Tracing pipeline
     let tracer = opentelemetry_otlp::new_pipeline()
         .tracing()
@@ -68,20 +67,28 @@ pub async fn init_telemetry(service_name: Option<String>) {
         .install_batch(opentelemetry::runtime::Tokio)
         .expect("Error: Failed to initialize the tracer.");
 
-
     let subscriber = Registry::default();
     let level_filter_layer =
         EnvFilter::try_from_default_env().unwrap_or(EnvFilter::new(LEVEL_TRACES));
     let tracing_layer = tracing_opentelemetry::layer().with_tracer(tracer);
 
-
     subscriber
         .with(level_filter_layer)
         .with(tracing_layer)
         .with(JsonStorageLayer)
         .with(tracing_subscriber::fmt::layer())
         .init();
+}
 
+/// Flushes the spans still buffered by the batch exporter and shuts the tracer down
+pub async fn shutdown_telemetry() {
+    debug!("Flushing telemetry");
+    // The batch processor blocks on its shutdown, keep it off the async workers
+    if let Err(e) =
+        tokio::task::spawn_blocking(opentelemetry::global::shutdown_tracer_provider).await
+    {
+        error!("Failed to shut down telemetry: {:?}", e);
+    }
 }
 
 fn is_telemetry_config_valid(telemetry_config: &Option<TelemetryConfig>) -> bool {
@@ -130,11 +137,9 @@ fn build_purpose_exporter(
         TelemetryPurpose::Metrics => endpoint + "metrics",
     };
 
-    let http_tracing_exporter = opentelemetry_otlp::new_exporter()
+    opentelemetry_otlp::new_exporter()
         .http()
         .with_endpoint(endpoint_constructed)
         .with_http_client(reqwest::Client::default())
-        .with_headers(headers);
-
-    http_tracing_exporter
+        .with_headers(headers)
 }